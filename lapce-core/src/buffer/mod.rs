@@ -264,6 +264,19 @@ pub fn init_content(&mut self, content: Rope) {
         self.set_pristine();
     }
 
+    /// Init the content at the revision another copy of the buffer is at, so
+    /// that the revisions of the two stay the same as the edits are mirrored
+    /// between them with [`Buffer::apply_delta`].
+    pub fn init_content_at(&mut self, content: Rope, rev: u64) {
+        if content.is_empty() {
+            self.revs[0].num = rev;
+            self.rev_counter = rev + 1;
+        } else {
+            self.rev_counter = rev.max(1);
+        }
+        self.init_content(content);
+    }
+
     pub fn reload(
         &mut self,
         content: Rope,
@@ -325,6 +338,15 @@ pub fn edit(
         self.add_delta(delta)
     }
 
+    /// Apply an edit that was made to another copy of the buffer.
+    pub fn apply_delta(
+        &mut self,
+        delta: RopeDelta,
+    ) -> (RopeDelta, InvalLines, SyntaxEdit) {
+        self.this_edit_type = EditType::Other;
+        self.add_delta(delta)
+    }
+
     fn add_delta(
         &mut self,
         delta: RopeDelta,
@@ -16,6 +16,25 @@ fn is_pristine() {
         buffer.do_undo();
         assert!(buffer.is_pristine());
     }
+
+    #[test]
+    fn mirrored_edits_keep_the_revision() {
+        let mut buffer = Buffer::new("");
+        buffer.init_content(Rope::from("abc"));
+        let (delta, _, _) =
+            buffer.edit(&[(Selection::caret(0), "d")], EditType::InsertChars);
+
+        let mut mirror = Buffer::new("");
+        mirror.init_content_at(Rope::from("abc"), 1);
+        mirror.apply_delta(delta);
+        assert_eq!(mirror.rev(), buffer.rev());
+        assert_eq!(mirror.text().to_string(), "dabc");
+
+        let mut late = Buffer::new("");
+        late.init_content_at(buffer.text().clone(), buffer.rev());
+        assert_eq!(late.rev(), buffer.rev());
+        assert!(late.is_pristine());
+    }
 }
 
 mod motion {
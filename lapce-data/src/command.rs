@@ -15,13 +15,13 @@
 };
 use lapce_rpc::{
     buffer::BufferId,
-    file::FileNodeItem,
-    plugin::{PluginId, VoltInfo, VoltMetadata},
+    file::{FileNodeItem, PathObject},
+    plugin::{PluginCommand, PluginId, PluginsInfo, VoltInfo, VoltMetadata},
     source_control::DiffInfo,
     style::Style,
     terminal::TermId,
 };
-use lapce_xi_rope::{spans::Spans, Rope};
+use lapce_xi_rope::{spans::Spans, Rope, RopeDelta};
 use lsp_types::{
     CodeActionOrCommand, CodeActionResponse, CompletionItem, CompletionResponse,
     InlayHint, Location, MessageType, Position, ProgressParams,
@@ -43,7 +43,7 @@
     keypress::{KeyMap, KeyPress},
     menu::MenuKind,
     palette::{PaletteItem, PaletteType},
-    plugin::{PluginsInfo, VoltIconKind},
+    plugin::VoltIconKind,
     proxy::ProxyStatus,
     rich_text::RichText,
     search::Match,
@@ -115,6 +115,7 @@ pub fn is_palette_command(&self) -> bool {
                 | LapceWorkbenchCommand::ChangeColorTheme
                 | LapceWorkbenchCommand::ChangeIconTheme
                 | LapceWorkbenchCommand::ConnectSshHost
+                | LapceWorkbenchCommand::ConnectRemoteProxy
                 | LapceWorkbenchCommand::ConnectWsl
                 | LapceWorkbenchCommand::PaletteWorkspace => return true,
                 _ => {}
@@ -257,6 +258,14 @@ pub enum LapceWorkbenchCommand {
     #[strum(message = "Open Logs Directory")]
     OpenLogsDirectory,
 
+    #[strum(serialize = "open_rpc_trace")]
+    #[strum(message = "Open RPC Trace")]
+    OpenRpcTrace,
+
+    #[strum(serialize = "open_rpc_metrics")]
+    #[strum(message = "Open RPC Metrics")]
+    OpenRpcMetrics,
+
     #[strum(serialize = "open_proxy_directory")]
     #[strum(message = "Open Proxy Directory")]
     OpenProxyDirectory,
@@ -309,6 +318,10 @@ pub enum LapceWorkbenchCommand {
     #[strum(serialize = "new_window")]
     NewWindow,
 
+    #[strum(message = "Duplicate Workspace in New Window")]
+    #[strum(serialize = "duplicate_window")]
+    DuplicateWindow,
+
     #[strum(message = "Close Window")]
     #[strum(serialize = "close_window")]
     CloseWindow,
@@ -321,6 +334,10 @@ pub enum LapceWorkbenchCommand {
     #[strum(message = "Connect to SSH Host")]
     ConnectSshHost,
 
+    #[strum(serialize = "connect_remote_proxy")]
+    #[strum(message = "Connect to Remote Proxy")]
+    ConnectRemoteProxy,
+
     #[strum(serialize = "connect_wsl")]
     #[strum(message = "Connect to WSL")]
     ConnectWsl,
@@ -446,6 +463,9 @@ pub enum LapceWorkbenchCommand {
     #[strum(serialize = "source_control_discard_target_file_changes")]
     SourceControlDiscardTargetFileChanges,
 
+    #[strum(serialize = "approve_volt_permissions")]
+    ApproveVoltPermissions,
+
     #[strum(message = "Source Control: Discard Workspace Changes")]
     #[strum(serialize = "source_control_discard_workspace_changes")]
     SourceControlDiscardWorkspaceChanges,
@@ -534,6 +554,19 @@ pub enum LapceUICommand {
         rev: u64,
         content: Rope,
     },
+    /// The edits a window sharing the proxy made to a document, which bring
+    /// it to `rev` and `content`
+    MirrorBufferUpdate {
+        path: PathBuf,
+        deltas: Vec<RopeDelta>,
+        rev: u64,
+        content: Rope,
+    },
+    /// Send the whole content of the buffer to the proxy, whose copy got out
+    /// of sync
+    ResyncBuffer {
+        path: PathBuf,
+    },
     LoadBufferHead {
         path: PathBuf,
         version: String,
@@ -559,7 +592,12 @@ pub enum LapceUICommand {
         pattern: String,
         case_sensitive: bool,
     },
+    /// A chunk of the matches of the running global search, added to the
+    /// ones received before it
     GlobalSearchResult(String, Arc<IndexMap<PathBuf, Vec<Match>>>),
+    /// The global search with the request id is done, and can't be cancelled
+    /// anymore
+    GlobalSearchFinished(usize),
     CancelFilePicker,
     SetWorkspace(LapceWorkspace),
     SetColorTheme(String, bool),
@@ -569,7 +607,9 @@ pub enum LapceUICommand {
     OpenPaths {
         window_tab_id: Option<(WindowId, WidgetId)>,
         folders: Vec<PathBuf>,
-        files: Vec<PathBuf>,
+        files: Vec<PathObject>,
+        new_window: bool,
+        reuse_window: bool,
     },
     OpenFile(PathBuf, bool),
     OpenFileDiff(PathBuf, String),
@@ -646,6 +686,11 @@ pub enum LapceUICommand {
     DisableVolt(VoltInfo),
     EnableVoltWorkspace(VoltInfo),
     DisableVoltWorkspace(VoltInfo),
+    PluginCommandRegistered(String, PluginCommand),
+    ExecutePluginCommand {
+        volt_id: String,
+        command: String,
+    },
     RequestLayout,
     RequestPaint,
     ResetFade,
@@ -665,6 +710,7 @@ pub enum LapceUICommand {
     UpdateStarted,
     UpdateFailed,
     NewWindow(WindowId),
+    DuplicateWindow(WindowId),
     CloseWindow(WindowId),
     ReloadWindow,
     CloseBuffers(Vec<BufferId>),
@@ -844,6 +890,8 @@ pub enum LapceUICommand {
 pub struct InitBufferContent<P: EditorPosition> {
     pub path: PathBuf,
     pub content: Rope,
+    /// The revision the proxy has the content at
+    pub rev: u64,
     pub locations: Vec<(WidgetId, EditorLocation<P>)>,
     pub edits: Option<Rope>,
     pub cb: Option<InitBufferContentCb>,
@@ -853,7 +901,7 @@ impl<P: EditorPosition + Clone + Send + 'static> InitBufferContent<P> {
     pub fn execute(&self, ctx: &mut EventCtx, data: &mut LapceTabData) {
         let doc = data.main_split.open_docs.get_mut(&self.path).unwrap();
         let doc = Arc::make_mut(doc);
-        doc.init_content(self.content.to_owned());
+        doc.init_content_at(self.content.to_owned(), self.rev);
 
         if let Some(rope) = &self.edits {
             doc.reload(rope.clone(), false);
@@ -2,6 +2,7 @@
     collections::HashMap,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use druid::{
@@ -11,6 +12,7 @@
 use indexmap::IndexMap;
 use lapce_core::directory::Directory;
 use lapce_proxy::plugin::wasi::find_all_volts;
+use lapce_rpc::proxy::RequestTimeouts;
 use lsp_types::{CompletionItemKind, SymbolKind};
 use once_cell::sync::Lazy;
 use parking_lot::{Mutex, RwLock};
@@ -313,6 +315,22 @@ pub struct CoreConfig {
         desc = "Enable customised titlebar and disable OS native one (Linux, BSD, Windows)"
     )]
     pub custom_titlebar: bool,
+    #[field_names(
+        desc = "Record the messages between Lapce, the proxy and the language servers, to be shown with the Open RPC Trace command"
+    )]
+    pub rpc_trace: bool,
+    #[field_names(
+        desc = "Measure the latency and size of the messages between Lapce, the proxy and the language servers, to be shown with the Open RPC Metrics command"
+    )]
+    pub rpc_metrics: bool,
+    #[field_names(
+        desc = "How long to wait for a language server to answer a request, in milliseconds. 0 waits forever"
+    )]
+    pub request_timeout: u64,
+    #[field_names(
+        desc = "How many times a request that timed out is sent again, for the requests that are safe to repeat"
+    )]
+    pub request_retries: usize,
 }
 
 #[derive(FieldNames, Debug, Clone, Deserialize, Serialize, Default)]
@@ -924,6 +942,8 @@ pub fn load(workspace: &LapceWorkspace, disabled_volts: &[String]) -> Self {
             Self::load_color_themes(disabled_volts);
         lapce_config.available_icon_themes = Self::load_icon_themes(disabled_volts);
         lapce_config.resolve_theme(workspace);
+        lapce_rpc::trace::set_enabled(lapce_config.core.rpc_trace);
+        lapce_rpc::metrics::set_enabled(lapce_config.core.rpc_metrics);
         lapce_config
     }
 
@@ -1031,6 +1051,7 @@ fn merge_config(
             }
             LapceWorkspaceType::RemoteSSH(_) => {}
             LapceWorkspaceType::RemoteWSL => {}
+            LapceWorkspaceType::RemoteProxy(_) => {}
         }
 
         config
@@ -1272,6 +1293,22 @@ pub fn log_file() -> Option<PathBuf> {
         Some(path)
     }
 
+    pub fn request_timeouts(&self) -> RequestTimeouts {
+        RequestTimeouts {
+            timeout: (self.core.request_timeout > 0)
+                .then(|| Duration::from_millis(self.core.request_timeout)),
+            retries: self.core.request_retries,
+        }
+    }
+
+    pub fn rpc_trace_file() -> Option<PathBuf> {
+        Some(Directory::logs_directory()?.join("rpc-trace.jsonl"))
+    }
+
+    pub fn rpc_metrics_file() -> Option<PathBuf> {
+        Some(Directory::logs_directory()?.join("rpc-metrics.txt"))
+    }
+
     pub fn settings_file() -> Option<PathBuf> {
         let path = Directory::config_directory()?.join("settings.toml");
 
@@ -34,14 +34,18 @@
 use lapce_rpc::{
     buffer::BufferId,
     core::{CoreMessage, CoreNotification},
-    plugin::VoltInfo,
+    file::PathObject,
+    plugin::{VoltInfo, VoltMetadata},
     proxy::ProxyResponse,
     source_control::FileDiff,
     terminal::TermId,
+    transport::ProxyAddress,
     RpcMessage,
 };
 use lapce_xi_rope::{Rope, RopeDelta};
-use lsp_types::{Diagnostic, DiagnosticSeverity, Position, ProgressToken, TextEdit};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, MessageType, Position, ProgressToken, TextEdit,
+};
 use notify::Watcher;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -117,7 +121,7 @@ impl LapceData {
     /// previously written to the Lapce database.
     pub fn load(
         event_sink: ExtEventSink,
-        paths: Vec<PathBuf>,
+        paths: Vec<PathObject>,
         log_file: Option<PathBuf>,
     ) -> Self {
         let _ = lapce_proxy::register_lapce_path();
@@ -132,8 +136,13 @@ pub fn load(
             .unwrap_or_else(|_| Self::default_panel_orders());
         let latest_release = Arc::new(None);
 
-        let dirs: Vec<&PathBuf> = paths.iter().filter(|p| p.is_dir()).collect();
-        let files: Vec<&PathBuf> = paths.iter().filter(|p| p.is_file()).collect();
+        let dirs: Vec<&PathBuf> = paths
+            .iter()
+            .map(|p| &p.path)
+            .filter(|p| p.is_dir())
+            .collect();
+        let files: Vec<&PathObject> =
+            paths.iter().filter(|p| p.path.is_file()).collect();
         if !dirs.is_empty() {
             let (size, mut pos) = db
                 .get_last_window_info()
@@ -177,6 +186,7 @@ pub fn load(
                     event_sink.clone(),
                     &info,
                     db.clone(),
+                    None,
                 );
                 windows.insert(window.window_id, window);
             }
@@ -193,6 +203,7 @@ pub fn load(
                         event_sink.clone(),
                         info,
                         db.clone(),
+                        None,
                     );
                     windows.insert(window.window_id, window);
                 }
@@ -223,6 +234,7 @@ pub fn load(
                 event_sink.clone(),
                 &info,
                 db.clone(),
+                None,
             );
             windows.insert(window.window_id, window);
         }
@@ -231,7 +243,11 @@ pub fn load(
             for file in files {
                 let _ = event_sink.submit_command(
                     LAPCE_UI_COMMAND,
-                    LapceUICommand::OpenFile(file.to_path_buf(), false),
+                    LapceUICommand::JumpToLineColLocation(
+                        None,
+                        file.clone().into(),
+                        false,
+                    ),
                     Target::Window(*window_id),
                 );
             }
@@ -308,6 +324,15 @@ pub fn reload_env(&self, env: &mut Env) {
     fn listen_local_socket(event_sink: ExtEventSink) -> Result<()> {
         let local_socket = Directory::local_socket()
             .ok_or_else(|| anyhow!("can't get local socket folder"))?;
+        // a separate instance, e.g. launched with `--new-instance` or `--wait`,
+        // leaves the socket to the Lapce that is already listening on it
+        if interprocess::local_socket::LocalSocketStream::connect(
+            local_socket.as_path(),
+        )
+        .is_ok()
+        {
+            return Ok(());
+        }
         let _ = std::fs::remove_file(&local_socket);
         let socket =
             interprocess::local_socket::LocalSocketListener::bind(local_socket)?;
@@ -323,6 +348,8 @@ fn listen_local_socket(event_sink: ExtEventSink) -> Result<()> {
                         window_tab_id,
                         folders,
                         files,
+                        new_window,
+                        reuse_window,
                     }) = msg
                     {
                         let window_tab_id =
@@ -338,6 +365,8 @@ fn listen_local_socket(event_sink: ExtEventSink) -> Result<()> {
                                 window_tab_id,
                                 folders,
                                 files,
+                                new_window,
+                                reuse_window,
                             },
                             Target::Global,
                         );
@@ -354,18 +383,29 @@ fn listen_local_socket(event_sink: ExtEventSink) -> Result<()> {
         Ok(())
     }
 
-    pub fn try_open_in_existing_process(paths: &[PathBuf]) -> Result<()> {
+    pub fn try_open_in_existing_process(
+        paths: &[PathObject],
+        new_window: bool,
+        reuse_window: bool,
+    ) -> Result<()> {
         let local_socket = Directory::local_socket()
             .ok_or_else(|| anyhow!("can't get local socket folder"))?;
         let mut socket =
             interprocess::local_socket::LocalSocketStream::connect(local_socket)?;
-        let folders: Vec<_> = paths.iter().filter(|p| p.is_dir()).cloned().collect();
-        let files: Vec<_> = paths.iter().filter(|p| p.is_file()).cloned().collect();
+        let folders: Vec<_> = paths
+            .iter()
+            .filter(|p| p.path.is_dir())
+            .map(|p| p.path.clone())
+            .collect();
+        let files: Vec<_> =
+            paths.iter().filter(|p| p.path.is_file()).cloned().collect();
         let msg: CoreMessage =
             RpcMessage::Notification(CoreNotification::OpenPaths {
                 window_tab_id: None,
                 folders,
                 files,
+                new_window,
+                reuse_window,
             });
         lapce_rpc::stdio::write_msg(&mut socket, msg)?;
 
@@ -448,6 +488,7 @@ pub fn new(
         event_sink: ExtEventSink,
         info: &WindowInfo,
         db: Arc<LapceDb>,
+        proxy: Option<Arc<LapceProxy>>,
     ) -> Self {
         let mut tabs = im::HashMap::new();
         let mut tabs_order = Vec::new();
@@ -457,6 +498,13 @@ pub fn new(
         let window_id = WindowId::next();
         for (i, workspace) in info.tabs.workspaces.iter().enumerate() {
             let tab_id = WidgetId::next();
+            // The proxy is the one of the workspace the window is opened on,
+            // the other tabs start their own
+            let tab_proxy = if i == info.tabs.active_tab {
+                proxy.clone()
+            } else {
+                None
+            };
             let tab = LapceTabData::new(
                 window_id,
                 tab_id,
@@ -469,6 +517,7 @@ pub fn new(
                 current_panels.clone(),
                 panel_orders.clone(),
                 event_sink.clone(),
+                tab_proxy,
             );
             tabs.insert(tab_id, tab);
             tabs_order.push(tab_id);
@@ -492,6 +541,7 @@ pub fn new(
                 current_panels,
                 panel_orders.clone(),
                 event_sink.clone(),
+                None,
             );
             tabs.insert(tab_id, tab);
             tabs_order.push(tab_id);
@@ -676,6 +726,7 @@ pub fn new(
         current_panels: Option<PanelData>,
         panel_orders: PanelOrder,
         event_sink: ExtEventSink,
+        proxy: Option<Arc<LapceProxy>>,
     ) -> Self {
         let disabled_volts = db.get_disabled_volts().unwrap_or_default();
         let workspace_disabled_volts = db
@@ -699,15 +750,27 @@ pub fn new(
         };
 
         let (term_sender, term_receiver) = unbounded();
-        let proxy = Arc::new(LapceProxy::new(
-            window_id,
-            tab_id,
-            workspace.clone(),
-            all_disabled_volts,
-            config.plugins.clone(),
-            term_sender.clone(),
-            event_sink.clone(),
-        ));
+        let (proxy, term_receiver) = match proxy {
+            Some(proxy) => {
+                // The terminals are updated by the tab the proxy was started
+                // for, which then repaints all of the tabs using the proxy
+                proxy.attach_tab(tab_id);
+                (proxy, None)
+            }
+            None => {
+                let proxy = Arc::new(LapceProxy::new(
+                    window_id,
+                    tab_id,
+                    workspace.clone(),
+                    all_disabled_volts,
+                    config.plugins.clone(),
+                    term_sender.clone(),
+                    event_sink.clone(),
+                ));
+                proxy.proxy_rpc.set_timeouts(config.request_timeouts());
+                (proxy, Some(term_receiver))
+            }
+        };
         let title = Arc::new(TitleData::new(config.clone()));
         let palette = Arc::new(PaletteData::new(config.clone(), proxy.clone()));
         let completion = Arc::new(CompletionData::new(config.clone()));
@@ -848,7 +911,7 @@ pub fn new(
             picker: file_picker,
             source_control,
             file_explorer,
-            term_rx: Some(term_receiver),
+            term_rx: term_receiver,
             term_tx: Arc::new(term_sender),
             palette,
             proxy,
@@ -887,17 +950,13 @@ pub fn workspace_info(&self) -> WorkspaceInfo {
 
     pub fn start_update_process(&mut self, event_sink: ExtEventSink) {
         if let Some(receiver) = self.term_rx.take() {
-            let tab_id = self.id;
-            let local_event_sink = event_sink.clone();
             let proxy = self.proxy.clone();
             let workspace = self.workspace.clone();
             let palette_widget_id = self.palette.widget_id;
             thread::spawn(move || {
                 LapceTabData::terminal_update_process(
-                    tab_id,
                     palette_widget_id,
                     receiver,
-                    local_event_sink,
                     workspace,
                     proxy,
                 );
@@ -1243,6 +1302,7 @@ pub fn palette_view_data(&self) -> PaletteViewData {
             db: self.db.clone(),
             focus_area: self.focus_area.clone(),
             terminal: self.terminal.clone(),
+            plugin: self.plugin.clone(),
         }
     }
 
@@ -1398,6 +1458,64 @@ pub fn run_workbench_command(
                     );
                 }
             }
+            LapceWorkbenchCommand::OpenRpcTrace => {
+                if !lapce_rpc::trace::is_enabled() {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::NewMessage {
+                            kind: MessageType::INFO,
+                            title: "RPC Trace".to_string(),
+                            message: "Enable core.rpc-trace in the settings to record the messages".to_string(),
+                        },
+                        Target::Widget(self.id),
+                    ));
+                } else if let Some(path) = LapceConfig::rpc_trace_file() {
+                    match lapce_rpc::trace::write_to(&path) {
+                        Ok(()) => self.main_split.jump_to_location(
+                            ctx,
+                            None,
+                            false,
+                            EditorLocation {
+                                path,
+                                position: None::<usize>,
+                                scroll_offset: None,
+                                history: None,
+                            },
+                            &self.config,
+                        ),
+                        Err(e) => log::error!("can't write the rpc trace: {e}"),
+                    }
+                }
+            }
+            LapceWorkbenchCommand::OpenRpcMetrics => {
+                if !lapce_rpc::metrics::is_enabled() {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::NewMessage {
+                            kind: MessageType::INFO,
+                            title: "RPC Metrics".to_string(),
+                            message: "Enable core.rpc-metrics in the settings to measure the messages".to_string(),
+                        },
+                        Target::Widget(self.id),
+                    ));
+                } else if let Some(path) = LapceConfig::rpc_metrics_file() {
+                    match lapce_rpc::metrics::write_to(&path) {
+                        Ok(()) => self.main_split.jump_to_location(
+                            ctx,
+                            None,
+                            false,
+                            EditorLocation {
+                                path,
+                                position: None::<usize>,
+                                scroll_offset: None,
+                                history: None,
+                            },
+                            &self.config,
+                        ),
+                        Err(e) => log::error!("can't write the rpc metrics: {e}"),
+                    }
+                }
+            }
             LapceWorkbenchCommand::OpenSettings => {
                 self.main_split.open_settings(ctx, false, &self.config);
             }
@@ -1535,6 +1653,18 @@ pub fn run_workbench_command(
                     Target::Global,
                 ));
             }
+            LapceWorkbenchCommand::DuplicateWindow => {
+                // persist the current layout so that the new window
+                // restores the same splits and editor tabs
+                if let Err(err) = self.db.save_workspace(self) {
+                    log::error!("Failed to save workspace: {err}");
+                }
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::DuplicateWindow(*self.window_id),
+                    Target::Global,
+                ));
+            }
             LapceWorkbenchCommand::CloseWindow => {
                 ctx.submit_command(Command::new(
                     LAPCE_UI_COMMAND,
@@ -1773,6 +1903,16 @@ pub fn run_workbench_command(
                     log::error!("discard target file called without a target file");
                 }
             }
+            LapceWorkbenchCommand::ApproveVoltPermissions => {
+                let volts = data.and_then(|data| {
+                    serde_json::from_value::<Vec<VoltMetadata>>(data).ok()
+                });
+                for volt in volts.unwrap_or_default() {
+                    self.proxy
+                        .proxy_rpc
+                        .approve_volt_permissions(volt.info(), volt.permissions());
+                }
+            }
             LapceWorkbenchCommand::SourceControlDiscardWorkspaceChanges => {
                 self.proxy.proxy_rpc.git_discard_workspace_changes();
             }
@@ -1790,6 +1930,13 @@ pub fn run_workbench_command(
                     Target::Widget(self.palette.widget_id),
                 ));
             }
+            LapceWorkbenchCommand::ConnectRemoteProxy => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::RunPalette(Some(PaletteType::RemoteProxy)),
+                    Target::Widget(self.palette.widget_id),
+                ));
+            }
             LapceWorkbenchCommand::ConnectWsl => ctx.submit_command(Command::new(
                 LAPCE_UI_COMMAND,
                 LapceUICommand::SetWorkspace(LapceWorkspace {
@@ -2034,12 +2181,10 @@ pub fn run_command(
     }
 
     pub fn terminal_update_process(
-        tab_id: WidgetId,
         _palette_widget_id: WidgetId,
         receiver: Receiver<(TermId, TermEvent)>,
-        event_sink: ExtEventSink,
         _workspace: Arc<LapceWorkspace>,
-        _proxy: Arc<LapceProxy>,
+        proxy: Arc<LapceProxy>,
     ) {
         let mut terminals = HashMap::new();
         let mut last_redraw = Instant::now();
@@ -2068,19 +2213,11 @@ pub fn terminal_update_process(
                         if last_event.is_some() {
                             if last_redraw.elapsed().as_millis() > 10 {
                                 last_redraw = Instant::now();
-                                let _ = event_sink.submit_command(
-                                    LAPCE_UI_COMMAND,
-                                    LapceUICommand::RequestPaint,
-                                    Target::Widget(tab_id),
-                                );
+                                proxy.notify_tabs(|| LapceUICommand::RequestPaint);
                             }
                         } else {
                             last_redraw = Instant::now();
-                            let _ = event_sink.submit_command(
-                                LAPCE_UI_COMMAND,
-                                LapceUICommand::RequestPaint,
-                                Target::Widget(tab_id),
-                            );
+                            proxy.notify_tabs(|| LapceUICommand::RequestPaint);
                         }
                     }
                 }
@@ -2588,6 +2725,24 @@ fn cursor_apply_delta(&mut self, path: &Path, delta: &RopeDelta) {
         }
     }
 
+    /// Apply the edits a window sharing the proxy made to a document.
+    pub fn apply_mirrored_update(
+        &mut self,
+        path: &Path,
+        deltas: &[RopeDelta],
+        rev: u64,
+        content: &Rope,
+    ) {
+        let doc = match self.open_docs.get_mut(path) {
+            Some(doc) => doc,
+            None => return,
+        };
+        let deltas = Arc::make_mut(doc).apply_mirrored_update(deltas, rev, content);
+        for delta in &deltas {
+            self.cursor_apply_delta(path, delta);
+        }
+    }
+
     pub fn edit(
         &mut self,
         path: &Path,
@@ -4468,13 +4623,17 @@ pub enum LapceWorkspaceType {
     Local,
     RemoteSSH(SshHost),
     RemoteWSL,
+    /// A proxy that listens on the network, started with `lapce-proxy --listen`
+    RemoteProxy(ProxyAddress),
 }
 
 impl LapceWorkspaceType {
     pub fn is_remote(&self) -> bool {
         matches!(
             self,
-            LapceWorkspaceType::RemoteSSH(_) | LapceWorkspaceType::RemoteWSL
+            LapceWorkspaceType::RemoteSSH(_)
+                | LapceWorkspaceType::RemoteWSL
+                | LapceWorkspaceType::RemoteProxy(_)
         )
     }
 }
@@ -4487,6 +4646,7 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 write!(f, "ssh://{ssh}")
             }
             LapceWorkspaceType::RemoteWSL => f.write_str("WSL"),
+            LapceWorkspaceType::RemoteProxy(address) => write!(f, "{address}"),
         }
     }
 }
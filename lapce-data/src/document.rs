@@ -33,6 +33,7 @@
     plugin::PluginId,
     proxy::ProxyResponse,
     style::{LineStyle, LineStyles, Style},
+    RequestId,
 };
 use lapce_xi_rope::{
     spans::{Spans, SpansBuilder},
@@ -357,6 +358,9 @@ pub struct Document {
     pub cursor_offset: usize,
     pub scroll_offset: Vec2,
     pub code_actions: im::HashMap<usize, (PluginId, CodeActionResponse)>,
+    /// The proxy request of the latest code actions request, cancelled when
+    /// the code actions at another offset are asked for
+    pub code_actions_rpc_id: Option<RequestId>,
     pub inlay_hints: Option<Spans<InlayHint>>,
     pub diagnostics: Option<Arc<Vec<EditorDiagnostic>>>,
     ime_text: Option<Arc<String>>,
@@ -404,6 +408,7 @@ pub fn new(
             cursor_offset: 0,
             scroll_offset: Vec2::ZERO,
             code_actions: im::HashMap::new(),
+            code_actions_rpc_id: None,
             inlay_hints: None,
             diagnostics: None,
             ime_text: None,
@@ -466,7 +471,13 @@ pub fn rev(&self) -> u64 {
     }
 
     pub fn init_content(&mut self, content: Rope) {
-        self.buffer.init_content(content);
+        self.init_content_at(content, 1);
+    }
+
+    /// Init the content at the revision the proxy has the buffer at, which
+    /// is ahead of the first one when another window has edited it already.
+    pub fn init_content_at(&mut self, content: Rope, rev: u64) {
+        self.buffer.init_content_at(content, rev);
         self.buffer.detect_indent(self.syntax.as_ref());
         self.loaded = true;
         self.on_update(None);
@@ -549,15 +560,18 @@ pub fn retrieve_file<P: EditorPosition + Send + 'static>(
             let path = path.clone();
             let event_sink = self.event_sink.clone();
             let proxy = self.proxy.clone();
+            proxy.doc_opened(tab_id, &path);
             std::thread::spawn(move || {
                 proxy.proxy_rpc.new_buffer(id, path.clone(), move |result| {
-                    if let Ok(ProxyResponse::NewBufferResponse { content }) = result
+                    if let Ok(ProxyResponse::NewBufferResponse { content, rev }) =
+                        result
                     {
                         let _ = event_sink.submit_command(
                             LAPCE_UI_COMMAND,
                             P::init_buffer_content_cmd(
                                 path,
                                 Rope::from(content),
+                                rev,
                                 locations,
                                 unsaved_buffer,
                                 cb,
@@ -1174,6 +1188,17 @@ fn apply_deltas(&mut self, deltas: &[(RopeDelta, InvalLines, SyntaxEdit)]) {
                 );
             }
         }
+        if let BufferContent::File(path) = &self.content {
+            let mirrored: Vec<RopeDelta> =
+                deltas.iter().map(|(delta, _, _)| delta.clone()).collect();
+            self.proxy.mirror_update(
+                self.tab_id,
+                path,
+                &mirrored,
+                self.rev(),
+                self.buffer.text(),
+            );
+        }
 
         // TODO(minor): We could avoid this potential allocation since most apply_delta callers are actually using a Vec
         // which we could reuse.
@@ -1182,6 +1207,47 @@ fn apply_deltas(&mut self, deltas: &[(RopeDelta, InvalLines, SyntaxEdit)]) {
         self.on_update(Some(edits));
     }
 
+    /// Apply the edits another window made to the document, which the proxy
+    /// has already been sent. Falls back to taking the whole content when
+    /// the document isn't at the revision the edits were made at. Returns
+    /// the deltas the buffer changed by, to move the cursors with.
+    pub fn apply_mirrored_update(
+        &mut self,
+        deltas: &[RopeDelta],
+        rev: u64,
+        content: &Rope,
+    ) -> Vec<RopeDelta> {
+        if !self.loaded {
+            return Vec::new();
+        }
+        if self.rev() + deltas.len() as u64 != rev {
+            // Reloading the same content would only bounce it back
+            let text = self.buffer.text();
+            if text.len() == content.len()
+                && text.slice_to_cow(..) == content.slice_to_cow(..)
+            {
+                return Vec::new();
+            }
+            self.code_actions.clear();
+            self.inlay_hints = None;
+            let delta = self.buffer.reload(content.clone(), false);
+            let deltas = vec![delta.0.clone()];
+            self.apply_deltas(&[delta]);
+            return deltas;
+        }
+
+        let mut edits = SmallVec::new();
+        for delta in deltas {
+            let (delta, _, edit) = self.buffer.apply_delta(delta.clone());
+            self.update_styles(&delta);
+            self.update_inlay_hints(&delta);
+            self.update_diagnostics(&delta);
+            edits.push(edit);
+        }
+        self.on_update(Some(edits));
+        deltas.to_vec()
+    }
+
     pub fn do_insert(
         &mut self,
         cursor: &mut Cursor,
@@ -26,7 +26,8 @@
     selection::{InsertDrift, Selection},
     syntax::edit::SyntaxEdit,
 };
-use lapce_rpc::{plugin::PluginId, proxy::ProxyResponse};
+pub use lapce_rpc::file::LineCol;
+use lapce_rpc::{file::PathObject, plugin::PluginId, proxy::ProxyResponse};
 use lapce_xi_rope::{Rope, RopeDelta, Transformer};
 use lsp_types::{
     request::GotoTypeDefinitionResponse, CodeAction, CodeActionOrCommand,
@@ -78,6 +79,7 @@ pub trait EditorPosition: Sized {
     fn init_buffer_content_cmd(
         path: PathBuf,
         content: Rope,
+        rev: u64,
         locations: Vec<(WidgetId, EditorLocation<Self>)>,
         edits: Option<Rope>,
         cb: Option<InitBufferContentCb>,
@@ -93,6 +95,7 @@ fn to_utf8_offset(&self, _buffer: &Buffer) -> usize {
     fn init_buffer_content_cmd(
         path: PathBuf,
         content: Rope,
+        rev: u64,
         locations: Vec<(WidgetId, EditorLocation<Self>)>,
         unsaved_buffers: Option<Rope>,
         cb: Option<InitBufferContentCb>,
@@ -100,6 +103,7 @@ fn init_buffer_content_cmd(
         LapceUICommand::InitBufferContent(InitBufferContent {
             path,
             content,
+            rev,
             locations,
             edits: unsaved_buffers,
             cb,
@@ -120,6 +124,7 @@ fn to_utf8_offset(&self, buffer: &Buffer) -> usize {
     fn init_buffer_content_cmd(
         path: PathBuf,
         content: Rope,
+        rev: u64,
         locations: Vec<(WidgetId, EditorLocation<Self>)>,
         edits: Option<Rope>,
         cb: Option<InitBufferContentCb>,
@@ -127,6 +132,7 @@ fn init_buffer_content_cmd(
         LapceUICommand::InitBufferContentLine(InitBufferContent {
             path,
             content,
+            rev,
             locations,
             edits,
             cb,
@@ -134,13 +140,6 @@ fn init_buffer_content_cmd(
     }
 }
 
-/// UTF8 line and column-offset
-#[derive(Debug, Clone, Copy)]
-pub struct LineCol {
-    pub line: usize,
-    pub column: usize,
-}
-
 impl EditorPosition for LineCol {
     fn to_utf8_offset(&self, buffer: &Buffer) -> usize {
         buffer.offset_of_line_col(self.line, self.column)
@@ -149,6 +148,7 @@ fn to_utf8_offset(&self, buffer: &Buffer) -> usize {
     fn init_buffer_content_cmd(
         path: PathBuf,
         content: Rope,
+        rev: u64,
         locations: Vec<(WidgetId, EditorLocation<Self>)>,
         edits: Option<Rope>,
         cb: Option<InitBufferContentCb>,
@@ -156,6 +156,7 @@ fn init_buffer_content_cmd(
         LapceUICommand::InitBufferContentLineCol(InitBufferContent {
             path,
             content,
+            rev,
             locations,
             edits,
             cb,
@@ -163,6 +164,17 @@ fn init_buffer_content_cmd(
     }
 }
 
+impl From<PathObject> for EditorLocation<LineCol> {
+    fn from(file: PathObject) -> Self {
+        EditorLocation {
+            path: file.path,
+            position: file.linecol,
+            scroll_offset: None,
+            history: None,
+        }
+    }
+}
+
 impl EditorPosition for Position {
     fn to_utf8_offset(&self, buffer: &Buffer) -> usize {
         buffer.offset_of_position(self)
@@ -171,6 +183,7 @@ fn to_utf8_offset(&self, buffer: &Buffer) -> usize {
     fn init_buffer_content_cmd(
         path: PathBuf,
         content: Rope,
+        rev: u64,
         locations: Vec<(WidgetId, EditorLocation<Self>)>,
         edits: Option<Rope>,
         cb: Option<InitBufferContentCb>,
@@ -178,6 +191,7 @@ fn init_buffer_content_cmd(
         LapceUICommand::InitBufferContentLsp(InitBufferContent {
             path,
             content,
+            rev,
             locations,
             edits,
             cb,
@@ -297,6 +311,11 @@ pub fn get_code_actions(&mut self, ctx: &mut EventCtx) {
                 false
             };
             if !exits {
+                if let Some(rpc_id) =
+                    Arc::make_mut(&mut self.doc).code_actions_rpc_id.take()
+                {
+                    self.proxy.proxy_rpc.cancel(rpc_id);
+                }
                 let position = self.doc.buffer().offset_to_position(offset);
                 let rev = self.doc.rev();
                 let event_sink = ctx.get_external_handle();
@@ -307,7 +326,7 @@ pub fn get_code_actions(&mut self, ctx: &mut EventCtx) {
                     .cloned()
                     .unwrap_or_else(|| Arc::new(Vec::new())));
                 let diagnostics = diagnostics.clone();
-                self.proxy.proxy_rpc.get_code_actions(
+                let rpc_id = self.proxy.proxy_rpc.get_code_actions(
                     path.clone(),
                     position,
                     diagnostics
@@ -348,6 +367,7 @@ pub fn get_code_actions(&mut self, ctx: &mut EventCtx) {
                         }
                     },
                 );
+                Arc::make_mut(&mut self.doc).code_actions_rpc_id = Some(rpc_id);
             }
         }
     }
@@ -1,7 +1,7 @@
 use std::{cell::RefCell, rc::Rc, sync::Arc};
 
 use druid::{ExtEventSink, Size, Target, WidgetId};
-use lapce_rpc::{buffer::BufferId, proxy::ProxyResponse};
+use lapce_rpc::{buffer::BufferId, proxy::ProxyResponse, RequestId};
 use lsp_types::{HoverContents, MarkedString, MarkupKind, Position};
 
 use crate::{
@@ -35,6 +35,9 @@ pub struct HoverData {
     pub buffer_id: BufferId,
     /// A counter to keep track of the active requests
     pub request_id: usize,
+    /// The proxy request of the latest hover request, cancelled when it is
+    /// superseded by a newer one
+    pub rpc_id: Option<RequestId>,
     /// Stores the size of the hover box
     pub size: Size,
     /// Stores the actual size of the hover content
@@ -55,6 +58,7 @@ pub fn new() -> Self {
             offset: 0,
             buffer_id: BufferId(0),
             request_id: 0,
+            rpc_id: None,
             // TODO: make this configurable by themes
             size: Size::new(600.0, 300.0),
             content_size: Rc::new(RefCell::new(Size::ZERO)),
@@ -104,11 +108,14 @@ pub fn request(
         event_sink: ExtEventSink,
         config: Arc<LapceConfig>,
     ) {
+        if let Some(rpc_id) = self.rpc_id.take() {
+            proxy.proxy_rpc.cancel(rpc_id);
+        }
         if let BufferContent::File(path) = doc.content() {
             // Clone config for use inside the proxy callback
             let p_config = config.clone();
             // Get the information/documentation that should be shown on hover
-            proxy.proxy_rpc.get_hover(
+            let rpc_id = proxy.proxy_rpc.get_hover(
                 request_id,
                 path.clone(),
                 position,
@@ -134,6 +141,7 @@ pub fn request(
                     }
                 }),
             );
+            self.rpc_id = Some(rpc_id);
             self.collect_diagnostics(position, diagnostics, config);
         }
     }
@@ -22,7 +22,9 @@
     language::LapceLanguage,
     mode::Mode,
 };
-use lapce_rpc::proxy::ProxyResponse;
+use lapce_rpc::{
+    plugin::PluginCommand, proxy::ProxyResponse, transport::ProxyAddress,
+};
 use lsp_types::{DocumentSymbolResponse, Position, Range, SymbolKind};
 use uuid::Uuid;
 
@@ -43,6 +45,7 @@
     keypress::{KeyMap, KeyPressData, KeyPressFocus},
     list::ListData,
     panel::PanelKind,
+    plugin::PluginData,
     proxy::{path_from_url, LapceProxy},
     terminal::TerminalPanelData,
 };
@@ -60,6 +63,7 @@ pub enum PaletteType {
     ColorTheme,
     IconTheme,
     SshHost,
+    RemoteProxy,
     Language,
 }
 
@@ -77,6 +81,7 @@ fn string(&self) -> String {
             | PaletteType::ColorTheme
             | PaletteType::IconTheme
             | PaletteType::SshHost
+            | PaletteType::RemoteProxy
             | PaletteType::Language => "".to_string(),
         }
     }
@@ -98,6 +103,7 @@ fn get_palette_type(current_type: &PaletteType, input: &str) -> PaletteType {
         match current_type {
             PaletteType::Reference
             | PaletteType::SshHost
+            | PaletteType::RemoteProxy
             | PaletteType::ColorTheme
             | PaletteType::IconTheme
             | PaletteType::Language => {
@@ -154,7 +160,13 @@ pub enum PaletteItemContent {
     ReferenceLocation(PathBuf, EditorLocation<Position>),
     Workspace(LapceWorkspace),
     SshHost(SshHost),
+    RemoteProxy(ProxyAddress),
     Command(LapceCommand),
+    PluginCommand {
+        volt_id: String,
+        volt_name: String,
+        command: PluginCommand,
+    },
     ColorTheme(String),
     IconTheme(String),
     Language(String),
@@ -276,6 +288,20 @@ fn select(
                 }
                 return !command.is_palette_command();
             }
+            PaletteItemContent::PluginCommand {
+                volt_id, command, ..
+            } => {
+                if !preview {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::ExecutePluginCommand {
+                            volt_id: volt_id.clone(),
+                            command: command.command.clone(),
+                        },
+                        Target::Auto,
+                    ));
+                }
+            }
             PaletteItemContent::TerminalLine(line, _content) => {
                 if !preview {
                     ctx.submit_command(Command::new(
@@ -298,6 +324,19 @@ fn select(
                     ));
                 }
             }
+            PaletteItemContent::RemoteProxy(address) => {
+                if !preview {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::SetWorkspace(LapceWorkspace {
+                            kind: LapceWorkspaceType::RemoteProxy(address.clone()),
+                            path: None,
+                            last_open: 0,
+                        }),
+                        Target::Auto,
+                    ));
+                }
+            }
         }
         true
     }
@@ -324,6 +363,7 @@ pub struct PaletteViewData {
     pub db: Arc<LapceDb>,
     pub focus_area: FocusArea,
     pub terminal: Arc<TerminalPanelData>,
+    pub plugin: Arc<PluginData>,
 }
 
 impl Lens<LapceTabData, PaletteViewData> for PaletteViewLens {
@@ -510,7 +550,8 @@ pub fn get_input(&self) -> &str {
             | PaletteType::ColorTheme
             | PaletteType::IconTheme
             | PaletteType::Language
-            | PaletteType::SshHost => &self.input,
+            | PaletteType::SshHost
+            | PaletteType::RemoteProxy => &self.input,
             PaletteType::Line
             | PaletteType::DocumentSymbol
             | PaletteType::WorkspaceSymbol
@@ -648,6 +689,9 @@ pub fn run(
             PaletteType::SshHost => {
                 self.get_ssh_hosts(ctx);
             }
+            PaletteType::RemoteProxy => {
+                self.get_remote_proxies(ctx);
+            }
             PaletteType::GlobalSearch => {
                 self.get_global_search(ctx);
             }
@@ -710,7 +754,8 @@ pub fn delete_to_beginning_of_line(&mut self, ctx: &mut EventCtx) {
             | PaletteType::ColorTheme
             | PaletteType::IconTheme
             | PaletteType::Language
-            | PaletteType::SshHost => 0,
+            | PaletteType::SshHost
+            | PaletteType::RemoteProxy => 0,
             PaletteType::Line
             | PaletteType::DocumentSymbol
             | PaletteType::WorkspaceSymbol
@@ -782,6 +827,26 @@ pub fn select(&mut self, ctx: &mut EventCtx) {
                 ));
                 return;
             }
+            if self.palette.palette_type == PaletteType::RemoteProxy {
+                let input = self.palette.get_input();
+                match input.parse::<ProxyAddress>() {
+                    Ok(address) => {
+                        ctx.submit_command(Command::new(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::SetWorkspace(LapceWorkspace {
+                                kind: LapceWorkspaceType::RemoteProxy(address),
+                                path: None,
+                                last_open: 0,
+                            }),
+                            Target::Auto,
+                        ));
+                    }
+                    Err(e) => {
+                        log::warn!("invalid proxy address {input}: {e}");
+                    }
+                }
+                return;
+            }
             self.cancel(ctx);
         }
     }
@@ -893,6 +958,27 @@ fn get_ssh_hosts(&mut self, _ctx: &mut EventCtx) {
             .collect();
     }
 
+    fn get_remote_proxies(&mut self, _ctx: &mut EventCtx) {
+        let workspaces = self.db.recent_workspaces().unwrap_or_default();
+        let mut addresses = HashSet::new();
+        for workspace in workspaces.iter() {
+            if let LapceWorkspaceType::RemoteProxy(address) = &workspace.kind {
+                addresses.insert(address.clone());
+            }
+        }
+
+        let palette = Arc::make_mut(&mut self.palette);
+        palette.total_items = addresses
+            .iter()
+            .map(|address| PaletteItem {
+                content: PaletteItemContent::RemoteProxy(address.clone()),
+                filter_text: address.to_string(),
+                score: 0,
+                indices: vec![],
+            })
+            .collect();
+    }
+
     fn get_workspaces(&mut self, _ctx: &mut EventCtx) {
         let workspaces = self.db.recent_workspaces().unwrap_or_default();
         let palette = Arc::make_mut(&mut self.palette);
@@ -905,6 +991,9 @@ fn get_workspaces(&mut self, _ctx: &mut EventCtx) {
                     LapceWorkspaceType::RemoteSSH(ssh) => {
                         format!("[{ssh}] {}", text)
                     }
+                    LapceWorkspaceType::RemoteProxy(address) => {
+                        format!("[{address}] {text}")
+                    }
                     LapceWorkspaceType::RemoteWSL => {
                         format!("[wsl] {text}")
                     }
@@ -1007,6 +1096,18 @@ fn get_commands(&mut self, _ctx: &mut EventCtx) {
                 indices: vec![],
             })
         }));
+        items.extend(self.plugin.enabled_commands().map(|(volt, command)| {
+            PaletteItem {
+                content: PaletteItemContent::PluginCommand {
+                    volt_id: volt.id(),
+                    volt_name: volt.display_name.clone(),
+                    command: command.clone(),
+                },
+                filter_text: format!("{}: {}", volt.display_name, command.title),
+                score: 0,
+                indices: vec![],
+            }
+        }));
 
         let palette = Arc::make_mut(&mut self.palette);
         palette.total_items = items;
@@ -9,16 +9,23 @@
 };
 use indexmap::IndexMap;
 use lapce_core::directory::Directory;
-use lapce_proxy::plugin::{download_volt, volt_icon, wasi::find_all_volts};
-use lapce_rpc::plugin::{VoltInfo, VoltMetadata};
+use lapce_proxy::plugin::{
+    registry::{self, download_volt},
+    volt_icon,
+    wasi::find_all_volts,
+};
+use lapce_rpc::plugin::{PluginCommand, PluginsInfo, VoltInfo, VoltMetadata};
 use parking_lot::Mutex;
 use plugin_install_status::PluginInstallStatus;
-use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use strum_macros::Display;
 
 use crate::{
-    command::{LapceUICommand, LAPCE_UI_COMMAND},
+    alert::AlertContentData,
+    command::{
+        CommandKind, LapceCommand, LapceUICommand, LapceWorkbenchCommand,
+        LAPCE_UI_COMMAND,
+    },
     config::LapceConfig,
     markdown::parse_markdown,
     proxy::LapceProxy,
@@ -149,6 +156,8 @@ pub struct PluginData {
     pub installed_icons: im::HashMap<String, VoltIconKind>,
     pub disabled: HashSet<String>,
     pub workspace_disabled: HashSet<String>,
+    /// The commands the volts registered, by the id of the volt
+    pub commands: IndexMap<String, Vec<PluginCommand>>,
 }
 
 #[derive(Clone, PartialEq, Eq)]
@@ -158,12 +167,6 @@ pub enum PluginLoadStatus {
     Success,
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct PluginsInfo {
-    pub plugins: Vec<VoltInfo>,
-    pub total: usize,
-}
-
 impl PluginData {
     pub fn new(
         tab_id: WidgetId,
@@ -190,6 +193,7 @@ pub fn new(
             installed_icons: im::HashMap::new(),
             disabled: HashSet::from_iter(disabled.into_iter()),
             workspace_disabled: HashSet::from_iter(workspace_disabled.into_iter()),
+            commands: IndexMap::new(),
         }
     }
 
@@ -225,10 +229,10 @@ pub fn plugin_status(&self, id: &str) -> PluginStatus {
                 .get(id)
                 .or_else(|| self.volts.volts.get(id))
             {
-                if meta.version == volt.version {
-                    PluginStatus::Installed
-                } else {
+                if registry::is_newer(&volt.version, &meta.version) {
                     PluginStatus::Upgrade(volt.version.clone())
+                } else {
+                    PluginStatus::Installed
                 }
             } else {
                 PluginStatus::Installed
@@ -240,8 +244,12 @@ pub fn plugin_status(&self, id: &str) -> PluginStatus {
 
     fn load_icon(volt: &VoltInfo) -> Result<VoltIconKind> {
         let url = format!(
-            "https://plugins.lapce.dev/api/v1/plugins/{}/{}/{}/icon?id={}",
-            volt.author, volt.name, volt.version, volt.updated_at_ts
+            "{}/{}/{}/{}/icon?id={}",
+            registry::REGISTRY_URL,
+            volt.author,
+            volt.name,
+            volt.version,
+            volt.updated_at_ts
         );
 
         let cache_file_path = Directory::cache_directory().map(|cache_dir| {
@@ -282,7 +290,7 @@ fn load_volts(
         loading: Option<Arc<Mutex<bool>>>,
         event_sink: ExtEventSink,
     ) -> Result<()> {
-        match PluginData::query_volts(query, offset) {
+        match registry::search(query, offset) {
             Ok(info) => {
                 for v in info.plugins.iter() {
                     {
@@ -322,14 +330,6 @@ fn load_volts(
         Ok(())
     }
 
-    fn query_volts(query: &str, offset: usize) -> Result<PluginsInfo> {
-        let url = format!(
-            "https://plugins.lapce.dev/api/v1/plugins?q={query}&offset={offset}"
-        );
-        let plugins: PluginsInfo = reqwest::blocking::get(&url)?.json()?;
-        Ok(plugins)
-    }
-
     pub fn download_readme(
         widget_id: WidgetId,
         volt: &VoltInfo,
@@ -337,8 +337,11 @@ pub fn download_readme(
         event_sink: ExtEventSink,
     ) -> Result<()> {
         let url = format!(
-            "https://plugins.lapce.dev/api/v1/plugins/{}/{}/{}/readme",
-            volt.author, volt.name, volt.version
+            "{}/{}/{}/{}/readme",
+            registry::REGISTRY_URL,
+            volt.author,
+            volt.name,
+            volt.version
         );
         let resp = reqwest::blocking::get(url)?;
         if resp.status() != 200 {
@@ -413,6 +416,35 @@ pub fn remove_volt(proxy: Arc<LapceProxy>, meta: VoltMetadata) -> Result<()> {
         Ok(())
     }
 
+    /// Add a command from a volt, or update it if the volt registered it
+    /// before, like when it's restarted.
+    pub fn plugin_command_registered(
+        &mut self,
+        volt_id: &str,
+        command: &PluginCommand,
+    ) {
+        let commands = self.commands.entry(volt_id.to_string()).or_default();
+        match commands.iter_mut().find(|c| c.command == command.command) {
+            Some(existing) => *existing = command.clone(),
+            None => commands.push(command.clone()),
+        }
+    }
+
+    /// The commands of the volts that are installed and enabled, with the
+    /// volt they belong to.
+    pub fn enabled_commands(
+        &self,
+    ) -> impl Iterator<Item = (&VoltMetadata, &PluginCommand)> {
+        self.commands
+            .iter()
+            .filter(move |(id, _)| !self.plugin_disabled(id))
+            .filter_map(move |(id, commands)| {
+                let volt = self.installed.get(id)?;
+                Some(commands.iter().map(move |command| (volt, command)))
+            })
+            .flatten()
+    }
+
     pub fn volt_installed(
         &mut self,
         tab_id: WidgetId,
@@ -431,24 +463,72 @@ pub fn volt_installed(
             self.installed_icons.insert(volt_id.clone(), icon);
         }
 
-        if !self.volts.volts.contains_key(&volt_id) {
-            let url = format!(
-                "https://plugins.lapce.dev/api/v1/plugins/{}/{}/latest",
-                volt.author, volt.name
-            );
+        // The proxy reports the updates of the wasm volts it runs
+        if volt.wasm.is_none() && !self.volts.volts.contains_key(&volt_id) {
+            let (author, name) = (volt.author.clone(), volt.name.clone());
+            let version = volt.version.clone();
             std::thread::spawn(move || -> Result<()> {
-                let info: VoltInfo = reqwest::blocking::get(url)?.json()?;
-                let _ = event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::LoadPluginLatest(info),
-                    Target::Widget(tab_id),
-                );
+                let info = registry::latest(&author, &name)?;
+                if registry::is_newer(&info.version, &version) {
+                    let _ = event_sink.submit_command(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::LoadPluginLatest(info),
+                        Target::Widget(tab_id),
+                    );
+                }
                 Ok(())
             });
         }
     }
 }
 
+/// The alert that asks the user to approve what the volts are allowed to do,
+/// which is where they're enabled from.
+pub fn permissions_alert(
+    tab_id: WidgetId,
+    volts: &[VoltMetadata],
+) -> AlertContentData {
+    let title = match volts {
+        [volt] => format!("Allow {} to run?", volt.display_name),
+        _ => format!("Allow {} plugins to run?", volts.len()),
+    };
+    let msg = volts
+        .iter()
+        .map(|volt| {
+            let mut lines = vec![format!("{} can:", volt.display_name)];
+            lines.extend(
+                volt.permissions()
+                    .describe()
+                    .into_iter()
+                    .map(|line| format!("  {line}")),
+            );
+            if volt.permissions.is_none() {
+                lines.push(
+                    "It doesn't declare its permissions, so it gets the ones \
+                     every plugin used to have."
+                        .to_string(),
+                );
+            }
+            lines.join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    AlertContentData {
+        title,
+        msg,
+        buttons: vec![(
+            "Allow".to_string(),
+            tab_id,
+            LapceCommand {
+                kind: CommandKind::Workbench(
+                    LapceWorkbenchCommand::ApproveVoltPermissions,
+                ),
+                data: serde_json::to_value(volts).ok(),
+            },
+        )],
+    }
+}
+
 #[derive(Display, PartialEq, Eq, Clone)]
 pub enum PluginStatus {
     Installed,
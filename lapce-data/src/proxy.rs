@@ -1,30 +1,47 @@
 #[cfg(target_os = "windows")]
 use std::os::windows::process::CommandExt;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::{BufReader, Write},
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Child, Command, Stdio},
     str::FromStr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
     thread,
+    time::Duration,
 };
 
 use anyhow::{anyhow, Result};
-use crossbeam_channel::Sender;
+use crossbeam_channel::{Receiver, SendError, Sender};
 use druid::{ExtEventSink, Target, WidgetId, WindowId};
 use flate2::read::GzDecoder;
 use lapce_core::{directory::Directory, meta};
-use lapce_proxy::dispatch::Dispatcher;
+use lapce_proxy::{dispatch::Dispatcher, TOKEN_ENV};
 use lapce_rpc::{
-    core::{CoreHandler, CoreNotification, CoreRequest, CoreRpcHandler},
-    proxy::{ProxyRpc, ProxyRpcHandler},
+    buffer::BufferId,
+    core::{
+        CoreHandler, CoreNotification, CoreRequest, CoreResponse, CoreRpcHandler,
+    },
+    handshake::{
+        client_handshake, client_handshake_timeout, Capability, Handshake, Hello,
+        HELLO_TIMEOUT,
+    },
+    mux::{mux_transport, Multiplexer},
+    proxy::{
+        ProxyNotification, ProxyRequest, ProxyResponse, ProxyRpc, ProxyRpcHandler,
+    },
+    session::{SessionId, Unacked, ACK_INTERVAL},
     stdio::stdio_transport,
     terminal::TermId,
+    transport::{ProxyAddress, Transport, TransportReader, TransportWriter},
     RequestId, RpcMessage,
 };
-use lapce_xi_rope::Rope;
-use lsp_types::Url;
+use lapce_xi_rope::{Rope, RopeDelta};
+use lsp_types::{MessageType, Url};
+use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use serde_json::Value;
 use thiserror::Error;
@@ -32,12 +49,43 @@
 use crate::{
     command::{LapceUICommand, LAPCE_UI_COMMAND},
     data::{LapceWorkspace, LapceWorkspaceType, SshHost},
+    plugin::permissions_alert,
     terminal::RawTerminal,
 };
 
 const UNIX_PROXY_SCRIPT: &[u8] = include_bytes!("../../extra/proxy.sh");
 const WINDOWS_PROXY_SCRIPT: &[u8] = include_bytes!("../../extra/proxy.ps1");
 
+/// How long to wait before the first attempt to reconnect to a remote proxy
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// The longest wait between two attempts to reconnect to a remote proxy
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+type WriterMessage = RpcMessage<ProxyRequest, ProxyNotification, CoreResponse>;
+type ReaderMessage = RpcMessage<CoreRequest, CoreNotification, ProxyResponse>;
+type Reconnect = Box<dyn FnMut() -> Result<Transport> + Send>;
+
+/// The remote proxies that serve several workspaces over one connection, by
+/// the [`Remote::id`] of the host they run on.
+static SHARED_REMOTES: Lazy<Mutex<HashMap<String, Weak<SharedRemote>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A remote proxy process that the workspaces opened on the same host share.
+/// It's killed once the last of them is closed.
+struct SharedRemote {
+    handshake: Handshake,
+    mux: Multiplexer<WriterMessage, ReaderMessage>,
+    child: Mutex<Child>,
+}
+
+impl Drop for SharedRemote {
+    fn drop(&mut self) {
+        let mut child = self.child.lock();
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
 pub enum TermEvent {
     NewTerminal(Arc<Mutex<RawTerminal>>),
     UpdateContent(String),
@@ -92,9 +140,124 @@ enum HostArchitecture {
     ARM32v6,
 }
 
+/// What lapce opened on a remote proxy, to open it again when the proxy
+/// starts a new session for lapce after a reconnect.
+#[derive(Default)]
+struct ReplayState {
+    initialize: Option<ProxyNotification>,
+    /// The buffers that are being opened, by the id of the request
+    opening: HashMap<RequestId, (BufferId, PathBuf)>,
+    buffers: HashMap<PathBuf, ReplayBuffer>,
+    /// The messages of the session the proxy hasn't acknowledged, to send
+    /// them again when the session is picked up after a reconnect
+    unacked: Unacked<WriterMessage>,
+}
+
+struct ReplayBuffer {
+    buffer_id: BufferId,
+    rope: Rope,
+    rev: u64,
+}
+
+/// A message sent to the proxy that changes the [`ReplayState`]
+enum ReplayChange {
+    Initialize(ProxyNotification),
+    NewBuffer(RequestId, BufferId, PathBuf),
+    Update(PathBuf, RopeDelta, u64),
+}
+
+impl ReplayChange {
+    fn of(msg: &WriterMessage) -> Option<Self> {
+        match msg {
+            RpcMessage::Request(id, ProxyRequest::NewBuffer { buffer_id, path }) => {
+                Some(ReplayChange::NewBuffer(*id, *buffer_id, path.clone()))
+            }
+            RpcMessage::Notification(
+                notification @ ProxyNotification::Initialize { .. },
+            ) => Some(ReplayChange::Initialize(notification.clone())),
+            RpcMessage::Notification(ProxyNotification::Update {
+                path,
+                delta,
+                rev,
+                ..
+            }) => Some(ReplayChange::Update(path.clone(), delta.clone(), *rev)),
+            _ => None,
+        }
+    }
+}
+
+impl ReplayState {
+    fn apply(&mut self, change: ReplayChange) {
+        match change {
+            ReplayChange::Initialize(notification) => {
+                self.initialize = Some(notification);
+            }
+            ReplayChange::NewBuffer(id, buffer_id, path) => {
+                self.opening.insert(id, (buffer_id, path));
+            }
+            ReplayChange::Update(path, delta, rev) => {
+                if let Some(buffer) = self.buffers.get_mut(&path) {
+                    buffer.rope = delta.apply(&buffer.rope);
+                    buffer.rev = rev;
+                }
+            }
+        }
+    }
+
+    /// The proxy responded to the request with the given id.
+    fn opened(&mut self, id: RequestId, resp: &ProxyResponse) {
+        if let Some((buffer_id, path)) = self.opening.remove(&id) {
+            if let ProxyResponse::NewBufferResponse { content, rev } = resp {
+                self.buffers.insert(
+                    path,
+                    ReplayBuffer {
+                        buffer_id,
+                        rope: Rope::from(content),
+                        rev: *rev,
+                    },
+                );
+            }
+        }
+    }
+
+    /// The messages that bring a new session to where the lost one was.
+    fn messages(&self) -> Vec<WriterMessage> {
+        self.initialize
+            .iter()
+            .cloned()
+            .chain(self.buffers.iter().map(|(path, buffer)| {
+                ProxyNotification::ReopenBuffer {
+                    buffer_id: buffer.buffer_id,
+                    path: path.clone(),
+                    content: buffer.rope.to_string(),
+                    rev: buffer.rev,
+                }
+            }))
+            .map(RpcMessage::Notification)
+            .collect()
+    }
+}
+
+/// The tabs that use a proxy: the tab it was started for, and the tabs of the
+/// windows duplicated from it.
+#[derive(Default)]
+struct ProxyTabs {
+    tabs: Vec<WidgetId>,
+    /// The tabs that have a document open, by its path, in the order they
+    /// opened it
+    docs: HashMap<PathBuf, Vec<WidgetId>>,
+}
+
+impl ProxyTabs {
+    /// The tab that's been using the proxy the longest, if any still does
+    fn first(&self) -> Option<WidgetId> {
+        self.tabs.first().copied()
+    }
+}
+
 #[derive(Clone)]
 pub struct LapceProxy {
-    pub tab_id: WidgetId,
+    tabs: Arc<Mutex<ProxyTabs>>,
     pub proxy_rpc: ProxyRpcHandler,
     pub core_rpc: CoreRpcHandler,
     term_tx: Sender<(TermId, TermEvent)>,
@@ -109,6 +272,8 @@ fn handle_notification(&mut self, rpc: CoreNotification) {
                 window_tab_id,
                 folders,
                 files,
+                new_window,
+                reuse_window,
             } => {
                 let _ = self.event_sink.submit_command(
                     LAPCE_UI_COMMAND,
@@ -121,116 +286,118 @@ fn handle_notification(&mut self, rpc: CoreNotification) {
                         }),
                         folders,
                         files,
+                        new_window,
+                        reuse_window,
                     },
                     Target::Global,
                 );
             }
             ProxyConnected {} => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::ProxyUpdateStatus(ProxyStatus::Connected),
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| {
+                    LapceUICommand::ProxyUpdateStatus(ProxyStatus::Connected)
+                });
             }
             OpenFileChanged { path, content } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
+                self.notify_tab(
+                    self.doc_tab_id(&path),
                     LapceUICommand::OpenFileChanged {
                         path,
                         content: Rope::from(content),
                     },
-                    Target::Widget(self.tab_id),
                 );
             }
             ReloadBuffer { path, content, rev } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
+                self.notify_tab(
+                    self.doc_tab_id(&path),
                     LapceUICommand::ReloadBuffer {
                         path,
                         rev,
                         content: Rope::from(content),
                     },
-                    Target::Widget(self.tab_id),
                 );
             }
-            WorkspaceFileChange {} => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::WorkspaceFileChange,
-                    Target::Widget(self.tab_id),
+            ResyncBuffer { path } => {
+                self.notify_tab(
+                    self.doc_tab_id(&path),
+                    LapceUICommand::ResyncBuffer { path },
                 );
             }
+            WorkspaceFileChange {} => {
+                self.notify_tabs(|| LapceUICommand::WorkspaceFileChange);
+            }
             PublishDiagnostics { diagnostics } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::PublishDiagnostics(diagnostics),
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| {
+                    LapceUICommand::PublishDiagnostics(diagnostics.clone())
+                });
             }
             WorkDoneProgress { progress } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::WorkDoneProgress(progress),
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| {
+                    LapceUICommand::WorkDoneProgress(progress.clone())
+                });
             }
             LogMessage { .. } => {}
             ShowMessage { title, message } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
+                self.notify_tab(
+                    self.tab_id(),
                     LapceUICommand::NewMessage {
                         kind: message.typ,
                         title,
                         message: message.message,
                     },
-                    Target::Widget(self.tab_id),
                 );
             }
             HomeDir { path } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::HomeDir(path),
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| LapceUICommand::HomeDir(path.clone()));
             }
             VoltInstalled { volt, icon } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::VoltInstalled(volt, icon),
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| {
+                    LapceUICommand::VoltInstalled(volt.clone(), icon.clone())
+                });
+            }
+            VoltUpdatesAvailable { volts } => {
+                for volt in volts {
+                    self.notify_tabs(|| {
+                        LapceUICommand::LoadPluginLatest(volt.clone())
+                    });
+                }
+            }
+            VoltPermissionsRequested { volts } => {
+                if let Some(tab_id) = self.tab_id() {
+                    self.notify_tab(
+                        Some(tab_id),
+                        LapceUICommand::ShowAlert(permissions_alert(tab_id, &volts)),
+                    );
+                }
+            }
+            PluginCommandRegistered { volt_id, command } => {
+                self.notify_tabs(|| {
+                    LapceUICommand::PluginCommandRegistered(
+                        volt_id.clone(),
+                        command.clone(),
+                    )
+                });
             }
             VoltInstalling { volt, error } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::VoltInstalling(volt, error),
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| {
+                    LapceUICommand::VoltInstalling(volt.clone(), error.clone())
+                });
             }
             VoltRemoving { volt, error } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::VoltRemoving(volt, error),
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| {
+                    LapceUICommand::VoltRemoving(volt.clone(), error.clone())
+                });
             }
             VoltRemoved {
                 volt,
                 only_installing,
             } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::VoltRemoved(volt, only_installing),
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| {
+                    LapceUICommand::VoltRemoved(volt.clone(), only_installing)
+                });
             }
             ListDir { .. } | DiffFiles { .. } => {}
             DiffInfo { diff } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::UpdateDiffInfo(diff),
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| LapceUICommand::UpdateDiffInfo(diff.clone()));
             }
             UpdateTerminal { term_id, content } => {
                 let _ = self
@@ -239,11 +406,7 @@ fn handle_notification(&mut self, rpc: CoreNotification) {
             }
             CloseTerminal { term_id } => {
                 let _ = self.term_tx.send((term_id, TermEvent::CloseTerminal));
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::CloseTerminal(term_id),
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| LapceUICommand::CloseTerminal(term_id));
             }
             CompletionResponse {
                 request_id,
@@ -251,28 +414,26 @@ fn handle_notification(&mut self, rpc: CoreNotification) {
                 resp,
                 plugin_id,
             } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
+                // Only the tab that is waiting for the request id takes it
+                self.notify_tabs(|| {
                     LapceUICommand::UpdateCompletion(
-                        request_id, input, resp, plugin_id,
-                    ),
-                    Target::Widget(self.tab_id),
-                );
+                        request_id,
+                        input.clone(),
+                        resp.clone(),
+                        plugin_id,
+                    )
+                });
             }
             SignatureHelpResponse {
                 request_id,
                 resp,
                 plugin_id,
             } => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::UpdateSignature {
-                        request_id,
-                        resp,
-                        plugin_id,
-                    },
-                    Target::Widget(self.tab_id),
-                );
+                self.notify_tabs(|| LapceUICommand::UpdateSignature {
+                    request_id,
+                    resp: resp.clone(),
+                    plugin_id,
+                });
             }
             Log { level, message } => {
                 if let Ok(level) = log::Level::from_str(&level) {
@@ -299,13 +460,45 @@ pub fn new(
         let core_rpc = CoreRpcHandler::new();
 
         let proxy = Self {
-            tab_id,
+            tabs: Arc::new(Mutex::new(ProxyTabs {
+                tabs: vec![tab_id],
+                docs: HashMap::new(),
+            })),
             proxy_rpc,
             core_rpc,
             term_tx,
             event_sink: event_sink.clone(),
         };
 
+        // Let the user know which kind of request isn't answered, once
+        let timed_out = Mutex::new(HashSet::new());
+        let local_event_sink = event_sink.clone();
+        let local_tabs = proxy.tabs.clone();
+        proxy.proxy_rpc.on_timeout(move |request| {
+            let method = serde_json::to_value(request)
+                .ok()
+                .and_then(|value| value.get("method")?.as_str().map(String::from))
+                .unwrap_or_default();
+            let tab_id = match local_tabs.lock().first() {
+                Some(tab_id) => tab_id,
+                None => return,
+            };
+            if !timed_out.lock().insert(method.clone()) {
+                return;
+            }
+            let _ = local_event_sink.submit_command(
+                LAPCE_UI_COMMAND,
+                LapceUICommand::NewMessage {
+                    kind: MessageType::WARNING,
+                    title: "Request timed out".to_string(),
+                    message: format!(
+                        "The language server didn't answer {method} in time"
+                    ),
+                },
+                Target::Widget(tab_id),
+            );
+        });
+
         let local_proxy = proxy.clone();
         thread::spawn(move || {
             let _ = event_sink.submit_command(
@@ -320,11 +513,9 @@ pub fn new(
                 window_id.to_usize(),
                 tab_id.to_usize(),
             );
-            let _ = event_sink.submit_command(
-                LAPCE_UI_COMMAND,
-                LapceUICommand::ProxyUpdateStatus(ProxyStatus::Disconnected),
-                Target::Widget(tab_id),
-            );
+            local_proxy.notify_tabs(|| {
+                LapceUICommand::ProxyUpdateStatus(ProxyStatus::Disconnected)
+            });
         });
 
         proxy
@@ -359,6 +550,9 @@ fn start(
             LapceWorkspaceType::RemoteSSH(ssh) => {
                 self.start_remote(SshRemote { ssh })?;
             }
+            LapceWorkspaceType::RemoteProxy(address) => {
+                self.start_remote_proxy(&address)?;
+            }
             LapceWorkspaceType::RemoteWSL => {
                 let distro = WslDistro::all()?
                     .into_iter()
@@ -376,6 +570,17 @@ fn start(
     }
 
     fn start_remote(&self, remote: impl Remote) -> Result<()> {
+        let shared = SHARED_REMOTES
+            .lock()
+            .get(&remote.id())
+            .and_then(Weak::upgrade)
+            .filter(|shared| !shared.mux.is_closed());
+        if let Some(shared) = shared {
+            log::debug!(target: "lapce_data::proxy::start_remote", "sharing the proxy on {}", remote.id());
+            self.start_multiplexed(shared);
+            return Ok(());
+        }
+
         let proxy_version = match *meta::RELEASE {
             "Debug" | "Nightly" => "nightly".to_string(),
             _ => format!("v{}", *meta::VERSION),
@@ -406,7 +611,7 @@ fn start_remote(&self, remote: impl Remote) -> Result<()> {
         // shells retain similar syntax, although shells like Nushell might not
         // work (hopefully no one uses it as login shell)
         use HostPlatform::*;
-        let (platform, architecture) = self.host_specification(&remote).unwrap();
+        let (platform, architecture) = self.host_specification(&remote)?;
 
         if platform == UnknownOS || architecture == HostArchitecture::UnknownArch {
             log::error!(target: "lapce_data::proxy::start_remote", "detected remote host: {platform}/{architecture}");
@@ -514,26 +719,17 @@ fn start_remote(&self, remote: impl Remote) -> Result<()> {
                     .status()?,
             };
             if !cmd.success() {
+                // The binaries are kept per version, so that switching
+                // between remotes on different versions doesn't download them
+                // again. Nightly gets a new binary under the same name, so
+                // it's always downloaded.
                 let local_proxy_file = Directory::proxy_directory()
                     .ok_or_else(|| anyhow!("can't find proxy directory"))?
-                    .join(&proxy_filename);
-                // remove possibly outdated proxy
-                if local_proxy_file.exists() {
-                    // TODO: add proper proxy version detection and update proxy
-                    // when needed
-                    std::fs::remove_file(&local_proxy_file)?;
-                }
-                let url = format!("https://github.com/lapce/lapce/releases/download/{proxy_version}/{proxy_filename}.gz");
-                log::debug!(target: "lapce_data::proxy::start_remote", "proxy download URI: {url}");
-                let mut resp = reqwest::blocking::get(url).expect("request failed");
-                if resp.status().is_success() {
-                    let mut out = std::fs::File::create(&local_proxy_file)
-                        .expect("failed to create file");
-                    let mut gz = GzDecoder::new(&mut resp);
-                    std::io::copy(&mut gz, &mut out)
-                        .expect("failed to copy content");
-                } else {
-                    log::error!(target: "lapce_data::proxy::start_remote", "proxy download failed with: {}", resp.status());
+                    .join(format!("{proxy_filename}-{proxy_version}"));
+                if proxy_version == "nightly" || !local_proxy_file.exists() {
+                    let url = format!("https://github.com/lapce/lapce/releases/download/{proxy_version}/{proxy_filename}.gz");
+                    log::debug!(target: "lapce_data::proxy::start_remote", "proxy download URI: {url}");
+                    download_proxy(&url, &local_proxy_file)?;
                 }
 
                 match platform {
@@ -564,92 +760,402 @@ fn start_remote(&self, remote: impl Remote) -> Result<()> {
             }
         }
 
-        let mut child = match platform {
-            // Force cmd.exe usage to resolve %envvar% variables
-            Windows => remote
-                .command_builder()
-                .args(["cmd", "/c"])
-                .arg(&remote_proxy_file)
-                .arg("--proxy")
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()?,
-            _ => remote
-                .command_builder()
-                .arg(&remote_proxy_file)
-                .arg("--proxy")
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()?,
-        };
-        let stdin = child
-            .stdin
-            .take()
-            .ok_or_else(|| anyhow!("can't find stdin"))?;
-        let stdout = BufReader::new(
-            child
-                .stdout
+        let spawn = || -> Result<_> {
+            let mut child = match platform {
+                // Force cmd.exe usage to resolve %envvar% variables
+                Windows => remote
+                    .command_builder()
+                    .args(["cmd", "/c"])
+                    .arg(&remote_proxy_file)
+                    .arg("--proxy")
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()?,
+                _ => remote
+                    .command_builder()
+                    .arg(&remote_proxy_file)
+                    .arg("--proxy")
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()?,
+            };
+            let stdin = child
+                .stdin
                 .take()
-                .ok_or_else(|| anyhow!("can't find stdout"))?,
+                .ok_or_else(|| anyhow!("can't find stdin"))?;
+            let stdout = BufReader::new(
+                child
+                    .stdout
+                    .take()
+                    .ok_or_else(|| anyhow!("can't find stdout"))?,
+            );
+            log::debug!(target: "lapce_data::proxy::start_remote", "process id: {}", child.id());
+            Ok((child, stdin, stdout))
+        };
+
+        let (mut child, mut stdin, stdout) = spawn()?;
+        let hello = Hello::new(*meta::VERSION).with(Capability::Multiplex);
+        let handshake = client_handshake_timeout(
+            &mut stdin,
+            stdout,
+            hello.clone(),
+            HELLO_TIMEOUT,
+        )?;
+        let (handshake, stdout) = match handshake {
+            Some(handshake) => handshake,
+            None => {
+                // A proxy from before the handshake, which took the hello for a
+                // message it doesn't know. It is started again, and spoken to
+                // the way it expects.
+                log::warn!(target: "lapce_data::proxy::start_remote", "the proxy on {} didn't answer the handshake", remote.id());
+                let _ = child.kill();
+                let _ = child.wait();
+                let (mut child, stdin, stdout) = spawn()?;
+                return self.start_transport(
+                    Handshake::legacy(&hello),
+                    Box::new(stdin),
+                    Box::new(stdout),
+                    move || {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    },
+                    None,
+                );
+            }
+        };
+        if !handshake.same_version() {
+            log::warn!(target: "lapce_data::proxy::start_remote", "the proxy on {} runs version {}", remote.id(), handshake.peer.version);
+        }
+        if !handshake.multiplexed {
+            // An older proxy, which serves a single workspace
+            return self.start_transport(
+                handshake,
+                Box::new(stdin),
+                Box::new(stdout),
+                move || {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                },
+                None,
+            );
+        }
+
+        let (writer_tx, writer_rx) = crossbeam_channel::unbounded();
+        let (reader_tx, reader_rx) = crossbeam_channel::unbounded();
+        mux_transport(handshake.codec, stdin, writer_rx, stdout, reader_tx);
+        let shared = Arc::new(SharedRemote {
+            handshake,
+            mux: Multiplexer::new(writer_tx, reader_rx),
+            child: Mutex::new(child),
+        });
+        SHARED_REMOTES
+            .lock()
+            .insert(remote.id(), Arc::downgrade(&shared));
+        self.start_multiplexed(shared);
+        Ok(())
+    }
+
+    /// Open the workspace on a proxy that is shared with other workspaces.
+    fn start_multiplexed(&self, shared: Arc<SharedRemote>) {
+        let (id, writer_tx, reader_rx) = shared.mux.open();
+        let handshake = shared.handshake.clone();
+        self.serve(
+            handshake,
+            writer_tx,
+            reader_rx,
+            move || shared.mux.close(id),
+            None,
         );
-        log::debug!(target: "lapce_data::proxy::start_remote", "process id: {}", child.id());
+    }
+
+    /// Talk to a proxy over the network, see `lapce-proxy --listen`. If the
+    /// connection is lost, lapce connects again and picks up its session.
+    fn start_remote_proxy(&self, address: &ProxyAddress) -> Result<()> {
+        let (mut writer, mut reader) = lapce_rpc::transport::connect(address)?;
+        log::debug!(target: "lapce_data::proxy::start_remote_proxy", "connected to {address}");
+        let hello = Hello {
+            token: proxy_token(),
+            ..Hello::new(*meta::VERSION)
+        };
+        let handshake = client_handshake(&mut writer, &mut reader, hello)?;
+        let address = address.clone();
+        self.start_transport(
+            handshake,
+            writer,
+            reader,
+            || {},
+            Some(Box::new(move || lapce_rpc::transport::connect(&address))),
+        )
+    }
 
+    /// Forward the messages between lapce and a proxy that isn't running in
+    /// this process, over a transport the handshake was done on.
+    fn start_transport(
+        &self,
+        handshake: Handshake,
+        writer: TransportWriter,
+        reader: TransportReader,
+        on_shutdown: impl FnOnce() + Send + 'static,
+        reconnect: Option<Reconnect>,
+    ) -> Result<()> {
         let (writer_tx, writer_rx) = crossbeam_channel::unbounded();
         let (reader_tx, reader_rx) = crossbeam_channel::unbounded();
-        stdio_transport(stdin, writer_rx, stdout, reader_tx);
+        stdio_transport(handshake.codec, writer, writer_rx, reader, reader_tx);
+        self.serve(handshake, writer_tx, reader_rx, on_shutdown, reconnect);
+        Ok(())
+    }
+
+    /// Forward the messages between lapce and the proxy over the given
+    /// channels. `on_shutdown` is called once the proxy is no longer needed,
+    /// and `reconnect` is used to connect again when the connection is lost.
+    fn serve(
+        &self,
+        handshake: Handshake,
+        writer_tx: Sender<WriterMessage>,
+        reader_rx: Receiver<ReaderMessage>,
+        on_shutdown: impl FnOnce() + Send + 'static,
+        reconnect: Option<Reconnect>,
+    ) {
+        log::debug!(target: "lapce_data::proxy::serve", "rpc codec: {:?}", handshake.codec);
+        if handshake.is_legacy() {
+            log::warn!(target: "lapce_data::proxy::serve", "remote proxy runs an older lapce, some features may not be available");
+        } else if !handshake.same_version() {
+            log::warn!(target: "lapce_data::proxy::serve", "remote proxy runs lapce {}, some features may not be available", handshake.peer.version);
+        }
+        let send_cancel = handshake.supports(Capability::Cancel);
+        // Only the messages of a session lapce can pick up again are sent
+        // again, and acknowledged
+        let resend = handshake.peer.session.is_some();
+
+        // The connection the messages to the proxy are sent on, or `None`
+        // while there is none
+        let (attach_tx, attach_rx) =
+            crossbeam_channel::unbounded::<Option<Sender<WriterMessage>>>();
+        let _ = attach_tx.send(Some(writer_tx.clone()));
+        let replay = Arc::new(Mutex::new(ReplayState::default()));
+        let shutdown = Arc::new(AtomicBool::new(false));
 
         let local_proxy_rpc = self.proxy_rpc.clone();
-        let local_writer_tx = writer_tx.clone();
+        let local_replay = replay.clone();
+        let local_shutdown = shutdown.clone();
         thread::spawn(move || {
+            let mut writer_tx: Option<Sender<WriterMessage>> = None;
             for msg in local_proxy_rpc.rx() {
-                match msg {
-                    ProxyRpc::Request(id, rpc) => {
-                        let _ = local_writer_tx.send(RpcMessage::Request(id, rpc));
-                    }
-                    ProxyRpc::Notification(rpc) => {
-                        let _ = local_writer_tx.send(RpcMessage::Notification(rpc));
+                local_proxy_rpc.record_queue();
+                let mut msg = match msg {
+                    ProxyRpc::Request(id, rpc) => RpcMessage::Request(id, rpc),
+                    ProxyRpc::Notification(rpc) => RpcMessage::Notification(rpc),
+                    ProxyRpc::Cancel(id) => {
+                        if !send_cancel {
+                            continue;
+                        }
+                        RpcMessage::Cancel(id)
                     }
                     ProxyRpc::Shutdown => {
-                        let _ = child.kill();
-                        let _ = child.wait();
+                        local_shutdown.store(true, Ordering::SeqCst);
+                        on_shutdown();
                         return;
                     }
+                };
+                let change = ReplayChange::of(&msg);
+                loop {
+                    // Locked while sending, so that a new connection can't
+                    // be attached between the replay and this message
+                    let mut replay = local_replay.lock();
+                    while let Ok(tx) = attach_rx.try_recv() {
+                        writer_tx = tx;
+                    }
+                    if let Some(tx) = writer_tx.as_ref() {
+                        let sent = resend.then(|| msg.clone());
+                        match tx.send(msg) {
+                            Ok(()) => {
+                                if let Some(change) = change {
+                                    replay.apply(change);
+                                }
+                                if let Some(sent) = sent {
+                                    replay.unacked.push(sent);
+                                }
+                                break;
+                            }
+                            Err(SendError(unsent)) => {
+                                msg = unsent;
+                                writer_tx = None;
+                            }
+                        }
+                    }
+                    drop(replay);
+                    match attach_rx.recv() {
+                        Ok(tx) => writer_tx = tx,
+                        Err(_) => return,
+                    }
                 }
             }
         });
 
-        let core_rpc = self.core_rpc.clone();
-        let proxy_rpc = self.proxy_rpc.clone();
+        let proxy = self.clone();
+        let mut reconnect = reconnect;
         thread::spawn(move || {
-            for msg in reader_rx {
-                match msg {
-                    RpcMessage::Request(id, req) => {
-                        let writer_tx = writer_tx.clone();
-                        let core_rpc = core_rpc.clone();
-                        thread::spawn(move || match core_rpc.request(req) {
-                            Ok(resp) => {
-                                let _ =
-                                    writer_tx.send(RpcMessage::Response(id, resp));
-                            }
-                            Err(e) => {
-                                let _ = writer_tx.send(RpcMessage::Error(id, e));
+            let mut session = handshake.peer.session;
+            let mut writer_tx = writer_tx;
+            let mut reader_rx = reader_rx;
+            // The number of messages received from the proxy in the session
+            let mut received = 0;
+            loop {
+                proxy.handle_messages(
+                    reader_rx,
+                    writer_tx,
+                    &replay,
+                    resend.then_some(&mut received),
+                );
+                let _ = attach_tx.send(None);
+                let connect = match reconnect.as_mut() {
+                    Some(connect) => connect,
+                    None => return,
+                };
+                proxy.notify_tabs(|| {
+                    LapceUICommand::ProxyUpdateStatus(ProxyStatus::Connecting)
+                });
+                let (handshake, writer, reader) =
+                    match proxy.reconnect(connect, session, received, &shutdown) {
+                        Some(connection) => connection,
+                        None => return,
+                    };
+                log::info!(target: "lapce_data::proxy::serve", "reconnected to the proxy, session resumed: {}", handshake.resumed);
+
+                let (new_writer_tx, writer_rx) = crossbeam_channel::unbounded();
+                let (reader_tx, new_reader_rx) = crossbeam_channel::unbounded();
+                stdio_transport(
+                    handshake.codec,
+                    writer,
+                    writer_rx,
+                    reader,
+                    reader_tx,
+                );
+                {
+                    let mut replay = replay.lock();
+                    if handshake.resumed {
+                        // What the proxy didn't get before the connection
+                        // was lost
+                        replay.unacked.ack(handshake.peer.received);
+                        for msg in replay.unacked.iter() {
+                            let _ = new_writer_tx.send(msg.clone());
+                        }
+                    } else {
+                        // The proxy lost everything lapce had opened on it
+                        proxy
+                            .proxy_rpc
+                            .fail_pending("the connection to the proxy was lost");
+                        received = 0;
+                        replay.unacked = Unacked::default();
+                        for msg in replay.messages() {
+                            if resend {
+                                replay.unacked.push(msg.clone());
                             }
-                        });
-                    }
-                    RpcMessage::Notification(n) => {
-                        core_rpc.notification(n);
-                    }
-                    RpcMessage::Response(id, resp) => {
-                        proxy_rpc.handle_response(id, Ok(resp));
-                    }
-                    RpcMessage::Error(id, err) => {
-                        proxy_rpc.handle_response(id, Err(err));
+                            let _ = new_writer_tx.send(msg);
+                        }
                     }
+                    let _ = attach_tx.send(Some(new_writer_tx.clone()));
                 }
+                proxy.notify_tabs(|| {
+                    LapceUICommand::ProxyUpdateStatus(ProxyStatus::Connected)
+                });
+                session = handshake.peer.session;
+                writer_tx = new_writer_tx;
+                reader_rx = new_reader_rx;
             }
         });
+    }
 
-        Ok(())
+    /// Handle the messages from the proxy until the connection is lost. With
+    /// `received`, the messages are counted, and acknowledged every
+    /// [`ACK_INTERVAL`] messages.
+    fn handle_messages(
+        &self,
+        reader_rx: Receiver<ReaderMessage>,
+        writer_tx: Sender<WriterMessage>,
+        replay: &Mutex<ReplayState>,
+        mut received: Option<&mut u64>,
+    ) {
+        for msg in reader_rx {
+            if let Some(received) = received.as_deref_mut() {
+                if !matches!(msg, RpcMessage::Ack(_)) {
+                    *received += 1;
+                    if *received % ACK_INTERVAL == 0 {
+                        let _ = writer_tx.send(RpcMessage::Ack(*received));
+                    }
+                }
+            }
+            match msg {
+                RpcMessage::Request(id, req) => {
+                    let writer_tx = writer_tx.clone();
+                    let core_rpc = self.core_rpc.clone();
+                    thread::spawn(move || match core_rpc.request(req) {
+                        Ok(resp) => {
+                            let _ = writer_tx.send(RpcMessage::Response(id, resp));
+                        }
+                        Err(e) => {
+                            let _ = writer_tx.send(RpcMessage::Error(id, e));
+                        }
+                    });
+                }
+                RpcMessage::Notification(n) => {
+                    self.core_rpc.notification(n);
+                }
+                RpcMessage::Partial(id, resp) => {
+                    self.proxy_rpc.handle_partial(id, resp);
+                }
+                RpcMessage::Response(id, resp) => {
+                    replay.lock().opened(id, &resp);
+                    self.proxy_rpc.handle_response(id, Ok(resp));
+                }
+                RpcMessage::Error(id, err) => {
+                    self.proxy_rpc.handle_response(id, Err(err));
+                }
+                // The proxy doesn't cancel the requests it makes to us
+                RpcMessage::Cancel(_) => {}
+                RpcMessage::Ack(received) => {
+                    replay.lock().unacked.ack(received);
+                }
+                RpcMessage::Invalid(id, err) => {
+                    let _ = writer_tx.send(RpcMessage::Error(id, err));
+                }
+            }
+        }
+    }
+
+    /// Try to connect to the proxy again, waiting longer after every
+    /// failure. Gives up once lapce shuts the proxy down.
+    fn reconnect(
+        &self,
+        connect: &mut Reconnect,
+        session: Option<SessionId>,
+        received: u64,
+        shutdown: &AtomicBool,
+    ) -> Option<(Handshake, TransportWriter, TransportReader)> {
+        let mut delay = MIN_RECONNECT_DELAY;
+        loop {
+            thread::sleep(delay);
+            if shutdown.load(Ordering::SeqCst) {
+                return None;
+            }
+            let result = connect().and_then(|(mut writer, mut reader)| {
+                let hello = Hello {
+                    session: session.clone(),
+                    token: proxy_token(),
+                    received,
+                    ..Hello::new(*meta::VERSION)
+                };
+                let handshake = client_handshake(&mut writer, &mut reader, hello)?;
+                Ok((handshake, writer, reader))
+            });
+            match result {
+                Ok(connection) => return Some(connection),
+                Err(e) => {
+                    log::warn!(target: "lapce_data::proxy::reconnect", "can't reconnect to the proxy: {e}");
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        }
     }
 
     fn host_specification(
@@ -754,12 +1260,148 @@ pub fn new_terminal(
         self.proxy_rpc.new_terminal(term_id, cwd, shell);
     }
 
+    /// The tab the notifications that only one tab should handle go to, if
+    /// any tab still uses the proxy.
+    pub fn tab_id(&self) -> Option<WidgetId> {
+        self.tabs.lock().first()
+    }
+
+    /// Let the tab of a duplicated window use the proxy as well.
+    pub fn attach_tab(&self, tab_id: WidgetId) {
+        self.tabs.lock().tabs.push(tab_id);
+    }
+
+    /// The tab doesn't use the proxy anymore. The proxy is stopped once the
+    /// last of its tabs is gone.
+    pub fn detach_tab(&self, tab_id: WidgetId) {
+        let last = {
+            let mut tabs = self.tabs.lock();
+            tabs.tabs.retain(|id| *id != tab_id);
+            for doc_tabs in tabs.docs.values_mut() {
+                doc_tabs.retain(|id| *id != tab_id);
+            }
+            tabs.tabs.is_empty()
+        };
+        if last {
+            self.stop();
+        }
+    }
+
+    /// The tab opened the document at the path.
+    pub fn doc_opened(&self, tab_id: WidgetId, path: &Path) {
+        let mut tabs = self.tabs.lock();
+        let doc_tabs = tabs.docs.entry(path.to_path_buf()).or_default();
+        if !doc_tabs.contains(&tab_id) {
+            doc_tabs.push(tab_id);
+        }
+    }
+
+    /// The tab that applies the changes the proxy makes to a document, which
+    /// then reach the other tabs through [`LapceProxy::mirror_update`].
+    fn doc_tab_id(&self, path: &Path) -> Option<WidgetId> {
+        let tabs = self.tabs.lock();
+        tabs.docs
+            .get(path)
+            .and_then(|doc_tabs| doc_tabs.first().copied())
+            .or_else(|| tabs.first())
+    }
+
+    /// Send the command to the tab, which is dropped when no tab uses the
+    /// proxy anymore, like while the last one is being closed.
+    fn notify_tab(&self, tab_id: Option<WidgetId>, cmd: LapceUICommand) {
+        match tab_id {
+            Some(tab_id) => {
+                let _ = self.event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    cmd,
+                    Target::Widget(tab_id),
+                );
+            }
+            None => log::warn!("no tab uses the proxy, dropping a notification"),
+        }
+    }
+
+    /// Send the command to each of the tabs that use the proxy.
+    pub fn notify_tabs(&self, cmd: impl Fn() -> LapceUICommand) {
+        let tabs = self.tabs.lock().tabs.clone();
+        for tab_id in tabs {
+            let _ = self.event_sink.submit_command(
+                LAPCE_UI_COMMAND,
+                cmd(),
+                Target::Widget(tab_id),
+            );
+        }
+    }
+
+    /// Apply the changes a tab made to a document, at the revisions it sent to
+    /// the proxy, to the same document in the other tabs that use the proxy.
+    pub fn mirror_update(
+        &self,
+        from_tab_id: WidgetId,
+        path: &Path,
+        deltas: &[RopeDelta],
+        rev: u64,
+        content: &Rope,
+    ) {
+        let doc_tabs = match self.tabs.lock().docs.get(path) {
+            Some(doc_tabs) if doc_tabs.len() > 1 => doc_tabs.clone(),
+            _ => return,
+        };
+        for tab_id in doc_tabs {
+            if tab_id == from_tab_id {
+                continue;
+            }
+            let _ = self.event_sink.submit_command(
+                LAPCE_UI_COMMAND,
+                LapceUICommand::MirrorBufferUpdate {
+                    path: path.to_path_buf(),
+                    deltas: deltas.to_vec(),
+                    rev,
+                    content: content.clone(),
+                },
+                Target::Widget(tab_id),
+            );
+        }
+    }
+
     pub fn stop(&self) {
         self.proxy_rpc.shutdown();
         self.core_rpc.shutdown();
     }
 }
 
+/// The token to present to the proxies that listen on the network, taken
+/// from the same environment variable the proxy reads it from.
+fn proxy_token() -> Option<String> {
+    std::env::var(TOKEN_ENV)
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+/// Download a gzipped proxy binary from a release. It goes through a
+/// temporary file, so that a failed download doesn't leave a broken binary
+/// behind to be uploaded next time.
+fn download_proxy(url: &str, local_proxy_file: &Path) -> Result<()> {
+    let mut resp = reqwest::blocking::get(url)?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("proxy download failed with: {}", resp.status()));
+    }
+    let mut partial = local_proxy_file.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    let result = std::fs::File::create(&partial).and_then(|mut out| {
+        let mut gz = GzDecoder::new(&mut resp);
+        std::io::copy(&mut gz, &mut out)?;
+        out.flush()
+    });
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(err.into());
+    }
+    std::fs::rename(&partial, local_proxy_file)?;
+    Ok(())
+}
+
 fn new_command(program: &str) -> Command {
     #[allow(unused_mut)]
     let mut cmd = Command::new(program);
@@ -769,6 +1411,10 @@ fn new_command(program: &str) -> Command {
 }
 
 trait Remote: Sized {
+    /// Tells apart the hosts, so that the workspaces opened on the same one
+    /// can share its proxy.
+    fn id(&self) -> String;
+
     fn home_dir(&self) -> Result<String> {
         let cmd = self
             .command_builder()
@@ -808,6 +1454,10 @@ impl SshRemote {
 }
 
 impl Remote for SshRemote {
+    fn id(&self) -> String {
+        format!("ssh://{}", self.ssh)
+    }
+
     fn upload_file(&self, local: impl AsRef<Path>, remote: &str) -> Result<()> {
         let mut cmd = new_command("scp");
 
@@ -891,6 +1541,10 @@ struct WslRemote {
 }
 
 impl Remote for WslRemote {
+    fn id(&self) -> String {
+        format!("wsl://{}", self.distro)
+    }
+
     fn upload_file(&self, local: impl AsRef<Path>, remote: &str) -> Result<()> {
         let mut wsl_path = Path::new(r"\\wsl.localhost\").join(&self.distro);
         if !wsl_path.exists() {
@@ -2,6 +2,7 @@
 use std::{path::PathBuf, sync::Arc};
 
 use druid::WidgetId;
+use lapce_rpc::RequestId;
 
 pub type Match = (usize, (usize, usize), String);
 #[derive(Clone)]
@@ -11,6 +12,10 @@ pub struct SearchData {
     pub split_id: WidgetId,
     pub editor_view_id: WidgetId,
     pub matches: Arc<IndexMap<PathBuf, Vec<Match>>>,
+    /// A counter to keep track of the searches
+    pub request_id: usize,
+    /// The proxy request of the search that is running
+    pub rpc_id: Option<RequestId>,
 }
 
 impl SearchData {
@@ -22,6 +27,8 @@ pub fn new() -> Self {
             split_id: WidgetId::next(),
             editor_view_id,
             matches: Arc::new(IndexMap::new()),
+            request_id: 0,
+            rpc_id: None,
         }
     }
 }
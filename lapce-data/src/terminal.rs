@@ -923,11 +923,9 @@ fn send_event(&self, event: alacritty_terminal::event::Event) {
                 self.proxy.proxy_rpc.terminal_write(self.term_id, &s);
             }
             alacritty_terminal::event::Event::Title(title) => {
-                let _ = self.event_sink.submit_command(
-                    LAPCE_UI_COMMAND,
-                    LapceUICommand::UpdateTerminalTitle(self.term_id, title),
-                    Target::Widget(self.proxy.tab_id),
-                );
+                self.proxy.notify_tabs(|| {
+                    LapceUICommand::UpdateTerminalTitle(self.term_id, title.clone())
+                });
             }
             _ => (),
         }
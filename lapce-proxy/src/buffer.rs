@@ -42,6 +42,25 @@ pub fn new(id: BufferId, path: PathBuf) -> Buffer {
         }
     }
 
+    /// A buffer with the content lapce has, rather than the one on disk.
+    pub fn with_content(
+        id: BufferId,
+        path: PathBuf,
+        content: String,
+        rev: u64,
+    ) -> Buffer {
+        let language_id = language_id_from_path(&path).unwrap_or("");
+        let mod_time = get_mod_time(&path);
+        Buffer {
+            id,
+            rope: Rope::from(content),
+            path,
+            language_id,
+            rev,
+            mod_time,
+        }
+    }
+
     pub fn save(&mut self, rev: u64) -> Result<()> {
         if self.rev != rev {
             return Err(anyhow!("not the right rev"));
@@ -71,15 +90,21 @@ pub fn save(&mut self, rev: u64) -> Result<()> {
         Ok(())
     }
 
+    /// Apply an edit that takes the buffer from `base_rev` to `rev`. `None`
+    /// if the buffer isn't at `base_rev`, in which case it's left alone.
     pub fn update(
         &mut self,
         delta: &RopeDelta,
+        base_rev: u64,
         rev: u64,
     ) -> Option<TextDocumentContentChangeEvent> {
-        if self.rev + 1 != rev {
+        if self.rev != base_rev
+            || rev <= base_rev
+            || delta.base_len != self.rope.len()
+        {
             return None;
         }
-        self.rev += 1;
+        self.rev = rev;
         let content_change = get_document_content_changes(delta, self);
         self.rope = delta.apply(&self.rope);
         Some(
@@ -302,3 +327,36 @@ pub fn get_mod_time<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
         .and_then(|meta| meta.modified())
         .ok()
 }
+
+#[cfg(test)]
+mod test {
+    use lapce_xi_rope::{Delta, Interval};
+
+    use super::*;
+
+    #[test]
+    fn test_update_revisions() {
+        let mut buffer = Buffer::with_content(
+            BufferId(0),
+            PathBuf::from("a.txt"),
+            "hello".to_string(),
+            1,
+        );
+        let insert = |at: usize, len: usize| {
+            Delta::simple_edit(Interval::new(at, at), Rope::from("!"), len)
+        };
+
+        assert!(buffer.update(&insert(5, 5), 1, 2).is_some());
+        assert_eq!(buffer.rev, 2);
+        // Coalesced updates span several revisions
+        assert!(buffer.update(&insert(6, 6), 2, 4).is_some());
+        assert_eq!(buffer.rev, 4);
+        assert_eq!(buffer.get_document(), "hello!!");
+
+        // An update for another revision leaves the buffer alone
+        assert!(buffer.update(&insert(7, 7), 5, 6).is_none());
+        assert!(buffer.update(&insert(6, 6), 4, 5).is_none());
+        assert_eq!(buffer.rev, 4);
+        assert_eq!(buffer.get_document(), "hello!!");
+    }
+}
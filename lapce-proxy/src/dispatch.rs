@@ -2,9 +2,12 @@
     collections::{HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use alacritty_terminal::{event::WindowSize, event_loop::Msg};
@@ -16,7 +19,7 @@
 use grep_searcher::{sinks::UTF8, SearcherBuilder};
 use indexmap::IndexMap;
 use lapce_rpc::{
-    core::{CoreNotification, CoreRpcHandler},
+    core::{CoreHandler, CoreNotification, CoreRequest, CoreRpcHandler},
     file::FileNodeItem,
     proxy::{
         ProxyHandler, ProxyNotification, ProxyRequest, ProxyResponse,
@@ -25,32 +28,55 @@
     source_control::{DiffInfo, FileDiff},
     style::{LineStyle, SemanticStyles},
     terminal::TermId,
-    RequestId, RpcError,
+    RequestId, RpcError, REQUEST_CANCELLED,
 };
-use lapce_xi_rope::Rope;
+use lapce_xi_rope::{Delta, Interval, Rope};
 use lsp_types::{Position, Range, TextDocumentItem, Url};
 use parking_lot::Mutex;
 use regex::Regex;
 
 use crate::{
     buffer::{get_mod_time, load_file, Buffer},
-    plugin::{catalog::PluginCatalog, remove_volt, PluginCatalogRpcHandler},
+    plugin::{
+        catalog::PluginCatalog, remove_volt, wasi::approve_permissions,
+        PluginCatalogRpcHandler,
+    },
     terminal::Terminal,
     watcher::{FileWatcher, Notify, WatchToken},
 };
 
+/// The number of files with matches sent back at a time while a global search
+/// is running
+const GLOBAL_SEARCH_CHUNK_SIZE: usize = 100;
+
+/// How long the shared plugin catalog waits for a workspace to send the
+/// files it has open, as one that's closing may not answer anymore
+const OPEN_FILES_TIMEOUT: Duration = Duration::from_secs(5);
+
 const OPEN_FILE_EVENT_TOKEN: WatchToken = WatchToken(1);
 const WORKSPACE_EVENT_TOKEN: WatchToken = WatchToken(2);
+/// The workspaces sharing a file watcher each get this many tokens of their
+/// own, see [`watch_token`]
+const WATCH_TOKENS_PER_WORKSPACE: usize = 2;
 
 pub struct Dispatcher {
     workspace: Option<PathBuf>,
     pub proxy_rpc: ProxyRpcHandler,
     core_rpc: CoreRpcHandler,
     catalog_rpc: PluginCatalogRpcHandler,
+    /// The plugin catalog and the file watcher, which may be shared with the
+    /// dispatchers of other workspaces
+    shared: SharedServices,
+    /// Tells this workspace apart from the others sharing the services
+    workspace_id: usize,
     buffers: HashMap<PathBuf, Buffer>,
+    /// The buffers whose updates are dropped until lapce sends their whole
+    /// content, after an update didn't fit
+    out_of_sync: HashSet<PathBuf>,
+    /// The global search that is running, and the flag that stops it
+    running_search: Option<(RequestId, Arc<AtomicBool>)>,
     #[allow(deprecated)]
     terminals: HashMap<TermId, mio::channel::Sender<Msg>>,
-    file_watcher: FileWatcher,
 
     window_id: usize,
     tab_id: usize,
@@ -70,27 +96,12 @@ fn handle_notification(&mut self, rpc: ProxyNotification) {
                 self.window_id = window_id;
                 self.tab_id = tab_id;
                 self.workspace = workspace;
-                self.file_watcher.notify(FileWatchNotifier::new(
+                self.shared.initialize(
+                    self.workspace_id,
                     self.workspace.clone(),
-                    self.core_rpc.clone(),
-                    self.proxy_rpc.clone(),
-                ));
-                if let Some(workspace) = self.workspace.as_ref() {
-                    self.file_watcher
-                        .watch(workspace, true, WORKSPACE_EVENT_TOKEN);
-                }
-
-                let plugin_rpc = self.catalog_rpc.clone();
-                let workspace = self.workspace.clone();
-                thread::spawn(move || {
-                    let mut plugin = PluginCatalog::new(
-                        workspace,
-                        disabled_volts,
-                        plugin_configurations,
-                        plugin_rpc.clone(),
-                    );
-                    plugin_rpc.mainloop(&mut plugin);
-                });
+                    disabled_volts,
+                    plugin_configurations,
+                );
                 self.core_rpc.proxy_connected();
             }
             OpenPaths { folders, files } => {
@@ -98,6 +109,8 @@ fn handle_notification(&mut self, rpc: ProxyNotification) {
                     window_tab_id: Some((self.window_id, self.tab_id)),
                     folders,
                     files,
+                    new_window: false,
+                    reuse_window: false,
                 });
             }
             OpenFileChanged { path } => {
@@ -127,17 +140,36 @@ fn handle_notification(&mut self, rpc: ProxyNotification) {
                 self.catalog_rpc.signature_help(request_id, &path, position);
             }
             Shutdown {} => {
-                self.catalog_rpc.shutdown();
+                self.shared.leave(self.workspace_id);
                 for (_, sender) in self.terminals.iter() {
                     #[allow(deprecated)]
                     let _ = sender.send(Msg::Shutdown);
                 }
-                self.proxy_rpc.shutdown();
             }
-            Update { path, delta, rev } => {
-                let buffer = self.buffers.get_mut(&path).unwrap();
+            Update {
+                path,
+                delta,
+                base_rev,
+                rev,
+            } => {
+                if self.out_of_sync.contains(&path) {
+                    // Waiting for the whole content
+                    return;
+                }
+                let buffer = match self.buffers.get_mut(&path) {
+                    Some(buffer) => buffer,
+                    None => return,
+                };
                 let old_text = buffer.rope.clone();
-                buffer.update(&delta, rev);
+                if buffer.update(&delta, base_rev, rev).is_none() {
+                    log::warn!(
+                        "{path:?} is at revision {}, not {base_rev}, resyncing",
+                        buffer.rev
+                    );
+                    self.out_of_sync.insert(path.clone());
+                    self.core_rpc.resync_buffer(path);
+                    return;
+                }
                 self.catalog_rpc.did_change_text_document(
                     &path,
                     rev,
@@ -146,6 +178,46 @@ fn handle_notification(&mut self, rpc: ProxyNotification) {
                     buffer.rope.clone(),
                 );
             }
+            ReopenBuffer {
+                buffer_id,
+                path,
+                content,
+                rev,
+            } => {
+                self.out_of_sync.remove(&path);
+                if let Some(buffer) = self.buffers.get_mut(&path) {
+                    // The language servers know the buffer already, and get
+                    // the whole content as a change
+                    let old_text = buffer.rope.clone();
+                    let new_text = Rope::from(content);
+                    let delta = Delta::simple_edit(
+                        Interval::new(0, old_text.len()),
+                        new_text.clone(),
+                        old_text.len(),
+                    );
+                    buffer.rope = new_text.clone();
+                    buffer.rev = rev;
+                    self.catalog_rpc.did_change_text_document(
+                        &path, rev, delta, old_text, new_text,
+                    );
+                    return;
+                }
+                let buffer =
+                    Buffer::with_content(buffer_id, path.clone(), content, rev);
+                self.catalog_rpc.did_open_document(
+                    &path,
+                    buffer.language_id.to_string(),
+                    buffer.rev as i32,
+                    buffer.rope.to_string(),
+                );
+                self.shared.watch(
+                    self.workspace_id,
+                    &path,
+                    false,
+                    OPEN_FILE_EVENT_TOKEN,
+                );
+                self.buffers.insert(path, buffer);
+            }
             UpdatePluginConfigs { configs } => {
                 let _ = self.catalog_rpc.update_plugin_configs(configs);
             }
@@ -211,6 +283,19 @@ fn handle_notification(&mut self, rpc: ProxyNotification) {
             EnableVolt { volt } => {
                 let _ = self.catalog_rpc.enable_volt(volt);
             }
+            ApproveVoltPermissions { volt, permissions } => {
+                if let Err(err) = approve_permissions(&volt.id(), &permissions) {
+                    eprintln!(
+                        "can't approve the permissions of {}: {err}",
+                        volt.id()
+                    );
+                    return;
+                }
+                let _ = self.catalog_rpc.enable_volt(volt);
+            }
+            ExecutePluginCommand { volt_id, command } => {
+                let _ = self.catalog_rpc.execute_command(volt_id, command);
+            }
             GitCommit { message, diffs } => {
                 if let Some(workspace) = self.workspace.as_ref() {
                     match git_commit(workspace, &message, diffs) {
@@ -261,19 +346,37 @@ fn handle_request(&mut self, id: RequestId, rpc: ProxyRequest) {
         use ProxyRequest::*;
         match rpc {
             NewBuffer { buffer_id, path } => {
+                // Another window sharing the proxy has the buffer open, with
+                // edits that might not be saved yet
+                if let Some(buffer) = self.buffers.get(&path) {
+                    self.respond_rpc(
+                        id,
+                        Ok(ProxyResponse::NewBufferResponse {
+                            content: buffer.rope.to_string(),
+                            rev: buffer.rev,
+                        }),
+                    );
+                    return;
+                }
                 let buffer = Buffer::new(buffer_id, path.clone());
                 let content = buffer.rope.to_string();
+                let rev = buffer.rev;
                 self.catalog_rpc.did_open_document(
                     &path,
                     buffer.language_id.to_string(),
                     buffer.rev as i32,
                     content.clone(),
                 );
-                self.file_watcher.watch(&path, false, OPEN_FILE_EVENT_TOKEN);
+                self.shared.watch(
+                    self.workspace_id,
+                    &path,
+                    false,
+                    OPEN_FILE_EVENT_TOKEN,
+                );
                 self.buffers.insert(path, buffer);
                 self.respond_rpc(
                     id,
-                    Ok(ProxyResponse::NewBufferResponse { content }),
+                    Ok(ProxyResponse::NewBufferResponse { content, rev }),
                 );
             }
             BufferHead { path } => {
@@ -304,6 +407,12 @@ fn handle_request(&mut self, id: RequestId, rpc: ProxyRequest) {
             } => {
                 let workspace = self.workspace.clone();
                 let proxy_rpc = self.proxy_rpc.clone();
+                // Only the latest search is shown
+                if let Some((_, cancelled)) = self.running_search.take() {
+                    cancelled.store(true, Ordering::Relaxed);
+                }
+                let cancelled = Arc::new(AtomicBool::new(false));
+                self.running_search = Some((id, cancelled.clone()));
                 // Perform the search on another thread to avoid blocking the proxy thread
                 thread::spawn(move || {
                     let result = if let Some(workspace) = workspace.as_ref() {
@@ -315,6 +424,19 @@ fn handle_request(&mut self, id: RequestId, rpc: ProxyRequest) {
                         {
                             let mut searcher = SearcherBuilder::new().build();
                             for path in ignore::Walk::new(workspace).flatten() {
+                                if cancelled.load(Ordering::Relaxed) {
+                                    // Answered, so that lapce doesn't wait
+                                    // for the search a newer one replaced
+                                    proxy_rpc.handle_response(
+                                        id,
+                                        Err(RpcError {
+                                            code: REQUEST_CANCELLED,
+                                            message: "the search was cancelled"
+                                                .to_string(),
+                                        }),
+                                    );
+                                    return;
+                                }
                                 if let Some(file_type) = path.file_type() {
                                     if file_type.is_file() {
                                         let path = path.into_path();
@@ -348,6 +470,17 @@ fn handle_request(&mut self, id: RequestId, rpc: ProxyRequest) {
                                             matches
                                                 .insert(path.clone(), line_matches);
                                         }
+                                        if matches.len() >= GLOBAL_SEARCH_CHUNK_SIZE
+                                        {
+                                            proxy_rpc.handle_partial(
+                                                id,
+                                                ProxyResponse::GlobalSearchResponse {
+                                                    matches: std::mem::take(
+                                                        &mut matches,
+                                                    ),
+                                                },
+                                            );
+                                        }
                                     }
                                 }
                             }
@@ -386,7 +519,8 @@ fn handle_request(&mut self, id: RequestId, rpc: ProxyRequest) {
                 position,
             } => {
                 let proxy_rpc = self.proxy_rpc.clone();
-                self.catalog_rpc.hover(&path, position, move |_, result| {
+                let catalog_rpc = self.catalog_rpc.for_request(id);
+                catalog_rpc.hover(&path, position, move |_, result| {
                     let result = result.map(|hover| ProxyResponse::HoverResponse {
                         request_id,
                         hover,
@@ -526,7 +660,8 @@ fn handle_request(&mut self, id: RequestId, rpc: ProxyRequest) {
                 diagnostics,
             } => {
                 let proxy_rpc = self.proxy_rpc.clone();
-                self.catalog_rpc.get_code_actions(
+                let catalog_rpc = self.catalog_rpc.for_request(id);
+                catalog_rpc.get_code_actions(
                     &path,
                     position,
                     diagnostics,
@@ -807,23 +942,44 @@ fn handle_request(&mut self, id: RequestId, rpc: ProxyRequest) {
             }
         }
     }
+
+    fn handle_cancel(&mut self, id: RequestId) {
+        if let Some((search_id, cancelled)) = self.running_search.as_ref() {
+            if *search_id == id {
+                cancelled.store(true, Ordering::Relaxed);
+                self.running_search = None;
+            }
+        }
+        self.catalog_rpc.cancel_request(id);
+    }
 }
 
 impl Dispatcher {
     pub fn new(core_rpc: CoreRpcHandler, proxy_rpc: ProxyRpcHandler) -> Self {
-        let plugin_rpc =
-            PluginCatalogRpcHandler::new(core_rpc.clone(), proxy_rpc.clone());
+        Self::with_shared(core_rpc, proxy_rpc, &SharedServices::new())
+    }
 
-        let file_watcher = FileWatcher::new();
+    /// A dispatcher that shares the plugin catalog, with its language
+    /// servers, and the file watcher with the other dispatchers of `shared`.
+    pub fn with_shared(
+        core_rpc: CoreRpcHandler,
+        proxy_rpc: ProxyRpcHandler,
+        shared: &SharedServices,
+    ) -> Self {
+        let (workspace_id, plugin_rpc) =
+            shared.join(core_rpc.clone(), proxy_rpc.clone());
 
         Self {
             workspace: None,
             proxy_rpc,
             core_rpc,
             catalog_rpc: plugin_rpc,
+            shared: shared.clone(),
+            workspace_id,
             buffers: HashMap::new(),
+            out_of_sync: HashSet::new(),
+            running_search: None,
             terminals: HashMap::new(),
-            file_watcher,
             window_id: 1,
             tab_id: 1,
         }
@@ -834,6 +990,386 @@ fn respond_rpc(&self, id: RequestId, result: Result<ProxyResponse, RpcError>) {
     }
 }
 
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        // Such as when the connection was lost before lapce shut it down
+        self.shared.leave(self.workspace_id);
+    }
+}
+
+/// The plugin catalog, with the plugins and the language servers it runs,
+/// and the file watcher, owned once for the dispatchers that share them. The
+/// catalog is started in the first workspace that's initialized, the ones
+/// after it are added to its servers as workspace folders, and it's shut down
+/// once the last of them leaves.
+#[derive(Clone)]
+pub struct SharedServices {
+    state: Arc<Mutex<SharedState>>,
+    file_watcher: Arc<Mutex<FileWatcher>>,
+}
+
+struct SharedState {
+    /// `None` until a dispatcher joins, and again once the last one left
+    catalog: Option<SharedCatalog>,
+    workspaces: HashMap<usize, SharedWorkspace>,
+    next_id: usize,
+}
+
+struct SharedWorkspace {
+    core_rpc: CoreRpcHandler,
+    proxy_rpc: ProxyRpcHandler,
+    workspace: Option<PathBuf>,
+    /// Set once the workspace is initialized
+    notifier: Option<Arc<FileWatchNotifier>>,
+}
+
+struct SharedCatalog {
+    catalog_rpc: PluginCatalogRpcHandler,
+    /// Where the messages of the catalog for lapce go, to be passed on to
+    /// every workspace
+    core_rpc: CoreRpcHandler,
+    /// What the catalog asks the workspaces through
+    proxy_rpc: ProxyRpcHandler,
+    started: bool,
+}
+
+impl SharedCatalog {
+    fn new(state: Arc<Mutex<SharedState>>) -> Self {
+        let core_rpc = CoreRpcHandler::new();
+        let proxy_rpc = ProxyRpcHandler::new();
+        let catalog_rpc =
+            PluginCatalogRpcHandler::new(core_rpc.clone(), proxy_rpc.clone());
+
+        let mut core = SharedCore {
+            state: state.clone(),
+        };
+        let local_core_rpc = core_rpc.clone();
+        thread::spawn(move || local_core_rpc.mainloop(&mut core));
+
+        let mut proxy = SharedProxy {
+            state,
+            proxy_rpc: proxy_rpc.clone(),
+        };
+        let local_proxy_rpc = proxy_rpc.clone();
+        thread::spawn(move || local_proxy_rpc.mainloop(&mut proxy));
+
+        Self {
+            catalog_rpc,
+            core_rpc,
+            proxy_rpc,
+            started: false,
+        }
+    }
+
+    fn start(
+        &mut self,
+        workspace: Option<PathBuf>,
+        disabled_volts: Vec<String>,
+        plugin_configurations: HashMap<String, HashMap<String, serde_json::Value>>,
+    ) {
+        self.started = true;
+        let plugin_rpc = self.catalog_rpc.clone();
+        let core_rpc = self.core_rpc.clone();
+        let proxy_rpc = self.proxy_rpc.clone();
+        thread::spawn(move || {
+            let mut plugin = PluginCatalog::new(
+                workspace,
+                disabled_volts,
+                plugin_configurations,
+                plugin_rpc.clone(),
+            );
+            plugin_rpc.mainloop(&mut plugin);
+            // The catalog doesn't need them anymore
+            core_rpc.shutdown();
+            proxy_rpc.shutdown();
+        });
+    }
+
+    fn shutdown(&self) {
+        if self.started {
+            self.catalog_rpc.shutdown();
+        } else {
+            self.core_rpc.shutdown();
+            self.proxy_rpc.shutdown();
+        }
+    }
+}
+
+impl SharedServices {
+    pub fn new() -> Self {
+        let state = Arc::new(Mutex::new(SharedState {
+            catalog: None,
+            workspaces: HashMap::new(),
+            next_id: 0,
+        }));
+        let mut file_watcher = FileWatcher::new();
+        file_watcher.notify(SharedNotifier {
+            state: state.clone(),
+        });
+        Self {
+            state,
+            file_watcher: Arc::new(Mutex::new(file_watcher)),
+        }
+    }
+
+    /// Add a workspace, returning its id and the handler of the catalog
+    /// whose answers go to it.
+    fn join(
+        &self,
+        core_rpc: CoreRpcHandler,
+        proxy_rpc: ProxyRpcHandler,
+    ) -> (usize, PluginCatalogRpcHandler) {
+        let mut state = self.state.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        let catalog = state
+            .catalog
+            .get_or_insert_with(|| SharedCatalog::new(self.state.clone()));
+        let catalog_rpc = catalog.catalog_rpc.for_workspace(
+            id,
+            core_rpc.clone(),
+            proxy_rpc.clone(),
+        );
+        state.workspaces.insert(
+            id,
+            SharedWorkspace {
+                core_rpc,
+                proxy_rpc,
+                workspace: None,
+                notifier: None,
+            },
+        );
+        (id, catalog_rpc)
+    }
+
+    /// Watch the workspace `id` and start the catalog in it, unless it's
+    /// running already, in which case the workspace is added to it.
+    fn initialize(
+        &self,
+        id: usize,
+        workspace: Option<PathBuf>,
+        disabled_volts: Vec<String>,
+        plugin_configurations: HashMap<String, HashMap<String, serde_json::Value>>,
+    ) {
+        {
+            let mut state = self.state.lock();
+            let SharedState {
+                catalog,
+                workspaces,
+                ..
+            } = &mut *state;
+            let shared = match workspaces.get_mut(&id) {
+                Some(shared) => shared,
+                None => return,
+            };
+            shared.workspace = workspace.clone();
+            shared.notifier = Some(Arc::new(FileWatchNotifier::new(
+                workspace.clone(),
+                shared.core_rpc.clone(),
+                shared.proxy_rpc.clone(),
+            )));
+            if let Some(catalog) = catalog.as_mut() {
+                if !catalog.started {
+                    catalog.start(
+                        workspace.clone(),
+                        disabled_volts,
+                        plugin_configurations,
+                    );
+                } else if let Some(workspace) = workspace.clone() {
+                    let _ = catalog.catalog_rpc.add_workspace_folder(workspace);
+                }
+            }
+        }
+        if let Some(workspace) = workspace.as_ref() {
+            self.watch(id, workspace, true, WORKSPACE_EVENT_TOKEN);
+        }
+    }
+
+    fn watch(&self, id: usize, path: &Path, recursive: bool, token: WatchToken) {
+        self.file_watcher
+            .lock()
+            .watch(path, recursive, watch_token(id, token));
+    }
+
+    /// Remove the workspace `id`, which shuts the catalog down if it was the
+    /// last one.
+    fn leave(&self, id: usize) {
+        let catalog = {
+            let mut state = self.state.lock();
+            let shared = match state.workspaces.remove(&id) {
+                Some(shared) => shared,
+                None => return,
+            };
+            if state.workspaces.is_empty() {
+                state.catalog.take()
+            } else {
+                if let (Some(catalog), Some(workspace)) =
+                    (state.catalog.as_ref(), shared.workspace)
+                {
+                    if catalog.started {
+                        let _ =
+                            catalog.catalog_rpc.remove_workspace_folder(workspace);
+                    }
+                }
+                None
+            }
+        };
+        {
+            let mut file_watcher = self.file_watcher.lock();
+            file_watcher.unwatch_token(watch_token(id, OPEN_FILE_EVENT_TOKEN));
+            file_watcher.unwatch_token(watch_token(id, WORKSPACE_EVENT_TOKEN));
+        }
+        if let Some(catalog) = catalog {
+            catalog.shutdown();
+        }
+    }
+}
+
+impl Default for SharedServices {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Passes the messages of the shared catalog for lapce on to every workspace.
+struct SharedCore {
+    state: Arc<Mutex<SharedState>>,
+}
+
+impl CoreHandler for SharedCore {
+    fn handle_notification(&mut self, rpc: CoreNotification) {
+        let core_rpcs: Vec<CoreRpcHandler> = self
+            .state
+            .lock()
+            .workspaces
+            .values()
+            .map(|shared| shared.core_rpc.clone())
+            .collect();
+        for core_rpc in core_rpcs {
+            core_rpc.notification(rpc.clone());
+        }
+    }
+
+    fn handle_request(&mut self, _id: RequestId, rpc: CoreRequest) {
+        match rpc {}
+    }
+}
+
+/// Answers what the shared catalog asks the workspaces, for all of them.
+struct SharedProxy {
+    state: Arc<Mutex<SharedState>>,
+    proxy_rpc: ProxyRpcHandler,
+}
+
+impl ProxyHandler for SharedProxy {
+    fn handle_notification(&mut self, _rpc: ProxyNotification) {}
+
+    fn handle_request(&mut self, id: RequestId, rpc: ProxyRequest) {
+        let result = match rpc {
+            ProxyRequest::GetOpenFilesContent {} => {
+                Ok(ProxyResponse::GetOpenFilesContentResponse {
+                    items: self.open_files_content(),
+                })
+            }
+            _ => Err(RpcError {
+                code: 0,
+                message: "not supported by the shared plugin catalog".to_string(),
+            }),
+        };
+        self.proxy_rpc.handle_response(id, result);
+    }
+
+    fn handle_cancel(&mut self, _id: RequestId) {}
+}
+
+impl SharedProxy {
+    /// The files open in all the workspaces, each of them once.
+    fn open_files_content(&self) -> Vec<TextDocumentItem> {
+        let proxy_rpcs: Vec<ProxyRpcHandler> = self
+            .state
+            .lock()
+            .workspaces
+            .values()
+            .map(|shared| shared.proxy_rpc.clone())
+            .collect();
+        let (tx, rx) = crossbeam_channel::unbounded();
+        for proxy_rpc in proxy_rpcs.iter() {
+            let tx = tx.clone();
+            proxy_rpc.request_async(
+                ProxyRequest::GetOpenFilesContent {},
+                move |result| {
+                    let _ = tx.send(result);
+                },
+            );
+        }
+
+        let deadline = Instant::now() + OPEN_FILES_TIMEOUT;
+        let mut uris = HashSet::new();
+        let mut items = Vec::new();
+        for _ in proxy_rpcs.iter() {
+            match rx.recv_deadline(deadline) {
+                Ok(Ok(ProxyResponse::GetOpenFilesContentResponse {
+                    items: open,
+                })) => {
+                    for item in open {
+                        if uris.insert(item.uri.clone()) {
+                            items.push(item);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        items
+    }
+}
+
+/// Passes the events of the shared file watcher on to the workspaces that
+/// watch them.
+struct SharedNotifier {
+    state: Arc<Mutex<SharedState>>,
+}
+
+impl Notify for SharedNotifier {
+    fn notify(&self, events: Vec<(WatchToken, notify::Event)>) {
+        let mut by_workspace: HashMap<usize, Vec<(WatchToken, notify::Event)>> =
+            HashMap::new();
+        for (token, event) in events {
+            let (id, token) = workspace_token(token);
+            by_workspace.entry(id).or_default().push((token, event));
+        }
+        let notified: Vec<_> = {
+            let state = self.state.lock();
+            by_workspace
+                .into_iter()
+                .filter_map(|(id, events)| {
+                    let notifier = state.workspaces.get(&id)?.notifier.clone()?;
+                    Some((notifier, events))
+                })
+                .collect()
+        };
+        for (notifier, events) in notified {
+            notifier.notify(events);
+        }
+    }
+}
+
+/// The token of the workspace `id` for what `token` stands for, as the
+/// workspaces sharing a file watcher each need tokens of their own.
+fn watch_token(id: usize, token: WatchToken) -> WatchToken {
+    WatchToken(id * WATCH_TOKENS_PER_WORKSPACE + token.0)
+}
+
+/// The workspace a token of [`watch_token`] is for, and what it stands for.
+fn workspace_token(token: WatchToken) -> (usize, WatchToken) {
+    let index = token.0.saturating_sub(1);
+    (
+        index / WATCH_TOKENS_PER_WORKSPACE,
+        WatchToken(index % WATCH_TOKENS_PER_WORKSPACE + 1),
+    )
+}
+
 struct FileWatchNotifier {
     core_rpc: CoreRpcHandler,
     proxy_rpc: ProxyRpcHandler,
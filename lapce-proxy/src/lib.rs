@@ -5,22 +5,37 @@
 pub mod watcher;
 
 use std::{
-    io::{stdin, stdout, BufReader},
-    path::PathBuf,
-    sync::Arc,
+    collections::HashMap,
+    io::{stdin, stdout, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     thread,
+    time::Duration,
 };
 
 use anyhow::{anyhow, Result};
 use clap::Parser;
-use dispatch::Dispatcher;
+use crossbeam_channel::{Receiver, Sender};
+use dispatch::{Dispatcher, SharedServices};
 use lapce_core::{directory::Directory, meta};
 use lapce_rpc::{
-    core::{CoreRpc, CoreRpcHandler},
-    proxy::{ProxyMessage, ProxyNotification, ProxyRpcHandler},
+    core::{CoreNotification, CoreRequest, CoreResponse, CoreRpc, CoreRpcHandler},
+    file::PathObject,
+    handshake::{check_token, server_handshake, Capability, Handshake, Hello},
+    mux::{mux_transport, Multiplexer},
+    proxy::{
+        ProxyMessage, ProxyNotification, ProxyRequest, ProxyResponse,
+        ProxyRpcHandler,
+    },
+    session::{new_session_id, Relay, SessionId, ACK_INTERVAL},
     stdio::stdio_transport,
-    RpcMessage,
+    transport::{HandshakeTimeout, Listener, ProxyAddress},
+    RequestId, RpcMessage,
 };
+use parking_lot::Mutex;
 
 #[derive(Parser)]
 #[clap(name = "Lapce")]
@@ -28,85 +43,538 @@
 struct Cli {
     #[clap(short, long, action)]
     proxy: bool,
+    /// Serve lapce over the network instead of stdio, on an address such as
+    /// tcp://0.0.0.0:9000. The schemes tcp, tls, ws and wss are supported.
+    #[clap(long, value_name = "ADDRESS")]
+    listen: Option<ProxyAddress>,
+    /// The PEM encoded certificate for the tls and wss schemes
+    #[clap(long, value_name = "FILE", requires = "tls-key")]
+    tls_cert: Option<PathBuf>,
+    /// The PEM encoded private key of the certificate
+    #[clap(long, value_name = "FILE", requires = "tls-cert")]
+    tls_key: Option<PathBuf>,
+    /// Only let in the connections with a certificate signed by one of the
+    /// PEM encoded certificates in the file. Lapce presents the certificate
+    /// and the key in the files named by the LAPCE_PROXY_CLIENT_CERT and
+    /// LAPCE_PROXY_CLIENT_KEY environment variables.
+    #[clap(long, value_name = "FILE", requires = "tls-cert")]
+    tls_client_ca: Option<PathBuf>,
+    /// Only let in the connections that present this token. It can also be
+    /// set with the LAPCE_PROXY_TOKEN environment variable, which keeps it
+    /// out of the process list.
+    #[clap(long, value_name = "TOKEN", requires = "listen")]
+    token: Option<String>,
+    /// Listen on an address other machines can reach without a token or a
+    /// client certificate, which lets anyone who can reach it connect
+    #[clap(long, action, requires = "listen")]
+    insecure: bool,
     paths: Vec<PathBuf>,
 }
 
+/// The environment variable that holds the token of a proxy listening on the
+/// network, read by both the proxy and lapce.
+pub const TOKEN_ENV: &str = "LAPCE_PROXY_TOKEN";
+
 pub fn mainloop() {
     let cli = Cli::parse();
+    if let Some(address) = cli.listen.as_ref() {
+        let tls = cli.tls_cert.as_deref().zip(cli.tls_key.as_deref());
+        let token = cli
+            .token
+            .clone()
+            .or_else(|| std::env::var(TOKEN_ENV).ok())
+            .filter(|token| !token.is_empty());
+        let client_ca = cli.tls_client_ca.as_deref();
+        if token.is_none() && client_ca.is_none() {
+            if !address.is_loopback() && !cli.insecure {
+                eprintln!(
+                    "can't listen on {address} without a token: pass --token or \
+                     set {TOKEN_ENV}, or pass --insecure to let anyone connect"
+                );
+                return;
+            }
+            eprintln!(
+                "warning: no token set, anyone who can reach {address} can connect"
+            );
+        }
+        if let Err(e) = listen(address, tls, client_ca, token) {
+            eprintln!("can't listen on {address}: {e}");
+        }
+        return;
+    }
     if !cli.proxy {
         let pwd = std::env::current_dir().unwrap_or_default();
         let paths: Vec<_> = cli
             .paths
             .iter()
-            .map(|p| pwd.join(p).canonicalize().unwrap_or_default())
+            .map(|p| PathObject::from_arg(&pwd, p))
             .collect();
         let _ = try_open_in_existing_process(&paths);
         return;
     }
-    let core_rpc = CoreRpcHandler::new();
-    let proxy_rpc = ProxyRpcHandler::new();
-    let mut dispatcher = Dispatcher::new(core_rpc.clone(), proxy_rpc.clone());
-
-    let (writer_tx, writer_rx) = crossbeam_channel::unbounded();
-    let (reader_tx, reader_rx) = crossbeam_channel::unbounded();
-    stdio_transport(stdout(), writer_rx, BufReader::new(stdin()), reader_tx);
-
-    let local_core_rpc = core_rpc.clone();
-    let local_writer_tx = writer_tx.clone();
-    thread::spawn(move || {
-        for msg in local_core_rpc.rx() {
-            match msg {
-                CoreRpc::Request(id, rpc) => {
-                    let _ = local_writer_tx.send(RpcMessage::Request(id, rpc));
+    let mut writer = stdout();
+    let mut reader = BufReader::new(stdin());
+    let hello = Hello::new(*meta::VERSION).with(Capability::Multiplex);
+    let mut handshake =
+        match server_handshake(&mut writer, &mut reader, hello, |_, _| Ok(())) {
+            Ok(handshake) => handshake,
+            Err(e) => {
+                eprintln!("handshake failed: {e}");
+                return;
+            }
+        };
+    let _ = register_lapce_path();
+    let reader = handshake.reader(reader);
+    if handshake.multiplexed {
+        serve_multiplexed(&handshake, writer, reader);
+        return;
+    }
+
+    let session = Session::new(None);
+    let mut dispatcher =
+        Dispatcher::new(session.core_rpc.clone(), session.proxy_rpc.clone());
+    let proxy_rpc = dispatcher.proxy_rpc.clone();
+
+    let local_proxy_rpc = proxy_rpc.clone();
+    std::thread::spawn(move || {
+        let _ = listen_local_socket(local_proxy_rpc);
+    });
+
+    session.connect(&handshake, writer, reader, 0, None);
+    proxy_rpc.mainloop(&mut dispatcher);
+}
+
+/// Serve the workspaces lapce opens on a multiplexed connection, each with a
+/// dispatcher of its own, until the connection is closed.
+///
+/// The dispatchers share one plugin catalog, so the language servers are
+/// started once for all the workspaces, and one file watcher.
+fn serve_multiplexed<W, R>(handshake: &Handshake, writer: W, reader: R)
+where
+    W: 'static + Write + Send,
+    R: 'static + BufRead + Send,
+{
+    let (writer_tx, writer_rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+    let (reader_tx, reader_rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+    mux_transport(handshake.codec, writer, writer_rx, reader, reader_tx);
+    let stream_partials = handshake.supports(Capability::Partial);
+
+    let shared = SharedServices::new();
+    let mut workspaces = Vec::new();
+    for (workspace_tx, workspace_rx) in Multiplexer::accept(writer_tx, reader_rx) {
+        let session = Session::new(None);
+        let mut dispatcher = Dispatcher::with_shared(
+            session.core_rpc.clone(),
+            session.proxy_rpc.clone(),
+            &shared,
+        );
+        if workspaces.is_empty() {
+            // Paths opened from the command line go to the first workspace
+            let proxy_rpc = dispatcher.proxy_rpc.clone();
+            thread::spawn(move || {
+                let _ = listen_local_socket(proxy_rpc);
+            });
+        }
+        session.clone().serve(
+            workspace_tx,
+            workspace_rx,
+            stream_partials,
+            0,
+            0,
+            None,
+        );
+        workspaces.push(thread::spawn(move || {
+            let proxy_rpc = dispatcher.proxy_rpc.clone();
+            proxy_rpc.mainloop(&mut dispatcher);
+            // Lets go of the channel of the workspace
+            session.relay.close();
+        }));
+    }
+    for workspace in workspaces {
+        let _ = workspace.join();
+    }
+}
+
+/// Accept connections from lapce on `address`. Each connection gets a
+/// dispatcher of its own, unless it picks up a session lapce had before it
+/// lost its connection. With a `token`, the connections that don't present it
+/// are turned down, as are the ones without a certificate signed by the
+/// `client_ca`, if there is one.
+fn listen(
+    address: &ProxyAddress,
+    tls: Option<(&Path, &Path)>,
+    client_ca: Option<&Path>,
+    token: Option<String>,
+) -> Result<()> {
+    let listener = Listener::bind(address, tls, client_ca)?;
+    let _ = register_lapce_path();
+    let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
+    let token = Arc::new(token);
+    loop {
+        let incoming = match listener.accept() {
+            Ok(incoming) => incoming,
+            // Such as running out of file descriptors, which doesn't have to
+            // last, so the listener waits a bit and keeps going
+            Err(e) => {
+                eprintln!("can't accept a connection: {e}");
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        let sessions = sessions.clone();
+        let token = token.clone();
+        thread::spawn(move || -> Result<()> {
+            let ((writer, reader), timeout) = match incoming.establish() {
+                Ok(established) => established,
+                Err(e) => {
+                    eprintln!("can't establish a connection: {e}");
+                    return Err(e);
                 }
-                CoreRpc::Notification(rpc) => {
-                    let _ = local_writer_tx.send(RpcMessage::Notification(rpc));
+            };
+            if let Some((session, mut dispatcher)) =
+                start_session(writer, reader, timeout, &sessions, token.as_deref())?
+            {
+                session.proxy_rpc.mainloop(&mut dispatcher);
+                // The workspace was closed in lapce
+                if let Some(id) = session.id.as_ref() {
+                    sessions.lock().remove(id);
                 }
-                CoreRpc::Shutdown => {
+                session.relay.close();
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Run the handshake with lapce on the transport, and start forwarding the
+/// messages between it and a dispatcher. If lapce picks up an existing
+/// session, the transport is attached to it and `None` is returned. Otherwise
+/// a new dispatcher is returned, which is driven by calling `mainloop` on its
+/// handler. With a `token`, lapce has to present it to get in. The `timeout`
+/// of the handshake is cleared once lapce got in.
+fn start_session<W, R>(
+    mut writer: W,
+    mut reader: R,
+    timeout: HandshakeTimeout,
+    sessions: &Sessions,
+    token: Option<&str>,
+) -> Result<Option<(Arc<Session>, Dispatcher)>>
+where
+    W: 'static + Write + Send,
+    R: 'static + BufRead + Send,
+{
+    let mut session = None;
+    let mut connection = 0;
+    let hello = Hello::new(*meta::VERSION);
+    let handshake =
+        server_handshake(&mut writer, &mut reader, hello, |peer, hello| {
+            check_token(token, peer)?;
+            let mut sessions = sessions.lock();
+            let existing = peer
+                .session
+                .as_ref()
+                .and_then(|id| sessions.get(id))
+                .cloned();
+            let claimed = existing.unwrap_or_else(|| {
+                let id = new_session_id();
+                let new = Session::new(Some(id.clone()));
+                sessions.insert(id, new.clone());
+                new
+            });
+            // Claimed while the sessions are locked, so that it can't expire
+            // before the connection is attached
+            connection = claimed.connections.fetch_add(1, Ordering::SeqCst) + 1;
+            // Read after the connection lapce lost stopped taking messages, see
+            // `Session::serve`
+            hello.received = *claimed.received.lock();
+            hello.session = claimed.id.clone();
+            session = Some(claimed);
+            Ok(())
+        });
+    let handshake = handshake.and_then(|handshake| {
+        timeout.clear()?;
+        Ok(handshake)
+    });
+    let mut handshake = match handshake {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            if let Some(session) = session {
+                session.expire(connection, sessions.clone());
+            }
+            return Err(e);
+        }
+    };
+    let session = session.ok_or_else(|| anyhow!("no session for the connection"))?;
+    let dispatcher = if handshake.resumed {
+        None
+    } else {
+        Some(Dispatcher::new(
+            session.core_rpc.clone(),
+            session.proxy_rpc.clone(),
+        ))
+    };
+
+    let reader = handshake.reader(reader);
+    session.connect(
+        &handshake,
+        writer,
+        reader,
+        connection,
+        Some(sessions.clone()),
+    );
+
+    Ok(dispatcher.map(|dispatcher| (session, dispatcher)))
+}
+
+/// How long a session that lost its connection is kept for lapce to pick it
+/// up again, before its dispatcher is shut down.
+const SESSION_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+type Sessions = Arc<Mutex<HashMap<SessionId, Arc<Session>>>>;
+
+/// A dispatcher, and what is needed to serve it over one connection after
+/// another.
+struct Session {
+    /// `None` if the session can't be picked up again
+    id: Option<SessionId>,
+    core_rpc: CoreRpcHandler,
+    proxy_rpc: ProxyRpcHandler,
+    /// Where the messages for lapce go, to be sent on whichever connection
+    /// is attached to the relay
+    out_tx: Sender<CoreWriterMessage>,
+    relay: Relay<CoreWriterMessage>,
+    /// The number of messages received from lapce in the session, see
+    /// [`Hello::received`]
+    received: Mutex<u64>,
+    /// Maps the ids of the requests from lapce to the ids they were given
+    /// in the dispatcher, so that they can be cancelled.
+    in_flight: Mutex<HashMap<RequestId, RequestId>>,
+    /// The number of connections the session had, so that it can tell
+    /// whether lapce came back after it lost one
+    connections: AtomicU64,
+}
+
+impl Session {
+    fn new(id: Option<SessionId>) -> Arc<Self> {
+        let core_rpc = CoreRpcHandler::new();
+        let (out_tx, out_rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+        // Only a session lapce can pick up again has its messages sent again
+        let relay = Relay::spawn(out_rx, id.is_some());
+
+        let local_core_rpc = core_rpc.clone();
+        let local_out_tx = out_tx.clone();
+        thread::spawn(move || {
+            while let Some(msg) = local_core_rpc.recv() {
+                let msg = match msg {
+                    CoreRpc::Request(id, rpc) => RpcMessage::Request(id, rpc),
+                    CoreRpc::Notification(rpc) => RpcMessage::Notification(rpc),
+                    CoreRpc::Shutdown => return,
+                };
+                if local_out_tx.send(msg).is_err() {
                     return;
                 }
             }
+        });
+
+        Arc::new(Self {
+            id,
+            core_rpc,
+            proxy_rpc: ProxyRpcHandler::new(),
+            out_tx,
+            relay,
+            received: Mutex::new(0),
+            in_flight: Mutex::new(HashMap::new()),
+            connections: AtomicU64::new(0),
+        })
+    }
+
+    /// The connection with the given number was lost. Unless lapce comes
+    /// back within [`SESSION_EXPIRY`], the session is shut down.
+    fn expire(self: Arc<Self>, connection: u64, sessions: Sessions) {
+        {
+            let _sessions = sessions.lock();
+            if self.connections.load(Ordering::SeqCst) != connection {
+                // lapce is back already
+                return;
+            }
+            self.relay.detach();
         }
-    });
+        thread::spawn(move || {
+            thread::sleep(SESSION_EXPIRY);
+            let mut sessions = sessions.lock();
+            if self.connections.load(Ordering::SeqCst) != connection {
+                return;
+            }
+            if let Some(id) = self.id.as_ref() {
+                sessions.remove(id);
+            }
+            // Closing the relay first, so that the dispatcher can't be stuck
+            // on sending to it
+            self.relay.close();
+            self.proxy_rpc.shutdown();
+        });
+    }
 
-    let local_proxy_rpc = proxy_rpc.clone();
-    let writer_tx = Arc::new(writer_tx);
-    thread::spawn(move || {
-        for msg in reader_rx {
-            match msg {
-                RpcMessage::Request(id, req) => {
-                    let writer_tx = writer_tx.clone();
-                    local_proxy_rpc.request_async(req, move |result| match result {
-                        Ok(resp) => {
-                            let _ = writer_tx.send(RpcMessage::Response(id, resp));
-                        }
-                        Err(e) => {
-                            let _ = writer_tx.send(RpcMessage::Error(id, e));
-                        }
-                    });
-                }
-                RpcMessage::Notification(n) => {
-                    local_proxy_rpc.notification(n);
+    /// Start forwarding the messages between lapce and the session over a
+    /// transport.
+    fn connect<W, R>(
+        self: &Arc<Self>,
+        handshake: &Handshake,
+        writer: W,
+        reader: R,
+        connection: u64,
+        sessions: Option<Sessions>,
+    ) where
+        W: 'static + Write + Send,
+        R: 'static + BufRead + Send,
+    {
+        // The channels are bounded so that a flood of messages blocks whoever
+        // produces them, instead of piling up in memory.
+        let (writer_tx, writer_rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+        let (reader_tx, reader_rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+        stdio_transport(handshake.codec, writer, writer_rx, reader, reader_tx);
+        let received = if handshake.resumed {
+            handshake.peer.received
+        } else {
+            0
+        };
+        self.clone().serve(
+            writer_tx,
+            reader_rx,
+            handshake.supports(Capability::Partial),
+            received,
+            connection,
+            sessions,
+        );
+    }
+
+    /// Forward the messages between lapce and the session over the given
+    /// channels, starting with the ones after the first `received` that lapce
+    /// already got. Once lapce is gone, the session expires if it's kept in
+    /// `sessions`, and is shut down otherwise.
+    fn serve(
+        self: Arc<Self>,
+        writer_tx: Sender<CoreWriterMessage>,
+        reader_rx: Receiver<CoreReaderMessage>,
+        stream_partials: bool,
+        received: u64,
+        connection: u64,
+        sessions: Option<Sessions>,
+    ) {
+        self.relay.attach(writer_tx.clone(), received);
+        let acks = self.id.is_some();
+        thread::spawn(move || {
+            let session = self;
+            for msg in reader_rx {
+                let mut received = session.received.lock();
+                // lapce reconnected, and sends the messages this connection
+                // didn't get to on the new one
+                if session.connections.load(Ordering::SeqCst) != connection {
+                    break;
                 }
-                RpcMessage::Response(id, resp) => {
-                    core_rpc.handle_response(id, Ok(resp));
+                if matches!(
+                    msg,
+                    RpcMessage::Request(..)
+                        | RpcMessage::Invalid(..)
+                        | RpcMessage::Notification(_)
+                        | RpcMessage::Cancel(_)
+                ) {
+                    *received += 1;
+                    if acks && *received % ACK_INTERVAL == 0 {
+                        // A later ack covers one that can't be sent now
+                        let _ = writer_tx.try_send(RpcMessage::Ack(*received));
+                    }
                 }
-                RpcMessage::Error(id, err) => {
-                    core_rpc.handle_response(id, Err(err));
+                match msg {
+                    RpcMessage::Request(id, req) => {
+                        let out_tx = session.out_tx.clone();
+                        let local_session = session.clone();
+                        // Hold the lock until the id is recorded, so that a quick
+                        // response can't remove it before it's inserted
+                        let mut in_flight = session.in_flight.lock();
+                        // Every request is made as a stream, so that the responses
+                        // the dispatcher streams back are forwarded chunk by chunk,
+                        // or merged into the final response if lapce can't take
+                        // the chunks on their own
+                        let partial_out_tx = out_tx.clone();
+                        let merged: Arc<Mutex<Option<ProxyResponse>>> =
+                            Arc::new(Mutex::new(None));
+                        let local_merged = merged.clone();
+                        let local_id = session.proxy_rpc.request_stream(
+                            req,
+                            move |chunk| {
+                                if stream_partials {
+                                    let _ = partial_out_tx
+                                        .send(RpcMessage::Partial(id, chunk));
+                                } else {
+                                    let mut merged = local_merged.lock();
+                                    *merged = Some(match merged.take() {
+                                        Some(merged) => merged.merge(chunk),
+                                        None => chunk,
+                                    });
+                                }
+                            },
+                            move |result| {
+                                local_session.in_flight.lock().remove(&id);
+                                let result =
+                                    result.map(|resp| match merged.lock().take() {
+                                        Some(merged) => merged.merge(resp),
+                                        None => resp,
+                                    });
+                                match result {
+                                    Ok(resp) => {
+                                        let _ = out_tx
+                                            .send(RpcMessage::Response(id, resp));
+                                    }
+                                    Err(e) => {
+                                        let _ =
+                                            out_tx.send(RpcMessage::Error(id, e));
+                                    }
+                                }
+                            },
+                        );
+                        in_flight.insert(id, local_id);
+                    }
+                    RpcMessage::Cancel(id) => {
+                        let local_id = { session.in_flight.lock().remove(&id) };
+                        if let Some(local_id) = local_id {
+                            session.proxy_rpc.cancel(local_id);
+                        }
+                    }
+                    RpcMessage::Notification(n) => {
+                        session.proxy_rpc.notification(n);
+                    }
+                    RpcMessage::Response(id, resp) => {
+                        session.core_rpc.handle_response(id, Ok(resp));
+                    }
+                    // Responses from lapce are never streamed
+                    RpcMessage::Partial(_, _) => {}
+                    RpcMessage::Error(id, err) => {
+                        session.core_rpc.handle_response(id, Err(err));
+                    }
+                    RpcMessage::Ack(received) => {
+                        session.relay.ack(received);
+                    }
+                    RpcMessage::Invalid(id, err) => {
+                        let _ = session.out_tx.send(RpcMessage::Error(id, err));
+                    }
                 }
             }
-        }
-        local_proxy_rpc.shutdown();
-    });
+            match sessions {
+                Some(sessions) => session.expire(connection, sessions),
+                None => session.proxy_rpc.shutdown(),
+            }
+        });
+    }
+}
 
-    let local_proxy_rpc = proxy_rpc.clone();
-    std::thread::spawn(move || {
-        let _ = listen_local_socket(local_proxy_rpc);
-    });
-    let _ = register_lapce_path();
+/// The number of messages that can be queued up in each direction of the
+/// transport, and of the file events waiting to be handled.
+const CHANNEL_CAPACITY: usize = 1024;
 
-    proxy_rpc.mainloop(&mut dispatcher);
-}
+type CoreWriterMessage =
+    RpcMessage<CoreRequest, Box<CoreNotification>, ProxyResponse>;
+type CoreReaderMessage = RpcMessage<ProxyRequest, ProxyNotification, CoreResponse>;
 
 pub fn register_lapce_path() -> Result<()> {
     let path = std::env::current_exe()?;
@@ -126,13 +594,17 @@ pub fn register_lapce_path() -> Result<()> {
     Ok(())
 }
 
-fn try_open_in_existing_process(paths: &[PathBuf]) -> Result<()> {
+fn try_open_in_existing_process(paths: &[PathObject]) -> Result<()> {
     let local_socket = Directory::local_socket()
         .ok_or_else(|| anyhow!("can't get local socket folder"))?;
     let mut socket =
         interprocess::local_socket::LocalSocketStream::connect(local_socket)?;
-    let folders: Vec<_> = paths.iter().filter(|p| p.is_dir()).cloned().collect();
-    let files: Vec<_> = paths.iter().filter(|p| p.is_file()).cloned().collect();
+    let folders: Vec<_> = paths
+        .iter()
+        .filter(|p| p.path.is_dir())
+        .map(|p| p.path.clone())
+        .collect();
+    let files: Vec<_> = paths.iter().filter(|p| p.path.is_file()).cloned().collect();
     let msg: ProxyMessage =
         RpcMessage::Notification(ProxyNotification::OpenPaths { folders, files });
     lapce_rpc::stdio::write_msg(&mut socket, msg)?;
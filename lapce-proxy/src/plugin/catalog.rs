@@ -1,6 +1,6 @@
 use std::{
     collections::HashMap,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
@@ -16,22 +16,31 @@
 };
 use lapce_xi_rope::{Rope, RopeDelta};
 use lsp_types::{
-    notification::DidOpenTextDocument, DidOpenTextDocumentParams, SemanticTokens,
-    TextDocumentIdentifier, TextDocumentItem, VersionedTextDocumentIdentifier,
+    notification::{DidChangeWorkspaceFolders, DidOpenTextDocument},
+    DidChangeWorkspaceFoldersParams, DidOpenTextDocumentParams, SemanticTokens,
+    TextDocumentIdentifier, TextDocumentItem, Url, VersionedTextDocumentIdentifier,
+    WorkspaceFolder, WorkspaceFoldersChangeEvent,
 };
 use parking_lot::Mutex;
 use psp_types::Notification;
 use serde_json::Value;
 
 use super::{
-    psp::{ClonableCallback, PluginServerRpc, PluginServerRpcHandler, RpcCallback},
-    wasi::{load_all_volts, start_volt},
+    psp::{
+        ClonableCallback, ExecuteCommandParams, PluginServerRpc,
+        PluginServerRpcHandler, RequestOrigin, RpcCallback, EXECUTE_COMMAND,
+    },
+    wasi::{is_approved, load_all_volts, start_volt},
     PluginCatalogNotification, PluginCatalogRpcHandler,
 };
 use crate::plugin::{install_volt, wasi::enable_volt};
 
 pub struct PluginCatalog {
+    /// The workspace the servers are started in
     workspace: Option<PathBuf>,
+    /// All the workspaces that share the servers, which may no longer include
+    /// the one they are started in
+    workspace_folders: Vec<PathBuf>,
     plugin_rpc: PluginCatalogRpcHandler,
     plugins: HashMap<PluginId, PluginServerRpcHandler>,
     plugin_configurations: HashMap<String, HashMap<String, serde_json::Value>>,
@@ -47,6 +56,7 @@ pub fn new(
         plugin_rpc: PluginCatalogRpcHandler,
     ) -> Self {
         let plugin = Self {
+            workspace_folders: workspace.iter().cloned().collect(),
             workspace,
             plugin_rpc: plugin_rpc.clone(),
             plugin_configurations,
@@ -67,6 +77,7 @@ pub fn handle_server_request(
         &mut self,
         plugin_id: Option<PluginId>,
         request_sent: Option<Arc<AtomicUsize>>,
+        origin: Option<RequestOrigin>,
         method: &'static str,
         params: Value,
         language_id: Option<String>,
@@ -81,6 +92,7 @@ pub fn handle_server_request(
                     language_id,
                     path,
                     true,
+                    origin,
                     move |result| {
                         f(plugin_id, result);
                     },
@@ -126,6 +138,7 @@ pub fn handle_server_request(
                 language_id.clone(),
                 path.clone(),
                 true,
+                origin,
                 move |result| {
                     f(plugin_id, result);
                 },
@@ -318,6 +331,15 @@ pub fn handle_notification(&mut self, notification: PluginCatalogNotification) {
         use PluginCatalogNotification::*;
         match notification {
             UnactivatedVolts(volts) => {
+                // The volts whose permissions the user hasn't approved wait
+                // until they are, and are enabled again then.
+                let (volts, unapproved): (Vec<_>, Vec<_>) =
+                    volts.into_iter().partition(is_approved);
+                if !unapproved.is_empty() {
+                    self.plugin_rpc
+                        .core_rpc
+                        .volt_permissions_requested(unapproved);
+                }
                 for volt in volts {
                     let id = volt.id();
                     self.unactivated_volts.insert(id, volt);
@@ -346,6 +368,19 @@ pub fn handle_notification(&mut self, notification: PluginCatalogNotification) {
                         );
                     }
                 }
+                let added: Vec<PathBuf> = self
+                    .workspace_folders
+                    .iter()
+                    .filter(|folder| Some(*folder) != self.workspace.as_ref())
+                    .cloned()
+                    .collect();
+                let removed: Vec<PathBuf> = self
+                    .workspace
+                    .iter()
+                    .filter(|workspace| !self.workspace_folders.contains(workspace))
+                    .cloned()
+                    .collect();
+                change_workspace_folders(&plugin, &added, &removed);
                 self.plugins.insert(plugin.plugin_id, plugin);
             }
             InstallVolt(volt) => {
@@ -392,6 +427,42 @@ pub fn handle_notification(&mut self, notification: PluginCatalogNotification) {
                     let _ = enable_volt(plugin_rpc, volt);
                 });
             }
+            ExecuteCommand(volt_id, command) => {
+                for (_, plugin) in self.plugins.iter() {
+                    if plugin.volt_id == volt_id {
+                        plugin.server_notification(
+                            EXECUTE_COMMAND,
+                            ExecuteCommandParams { command },
+                            None,
+                            None,
+                            false,
+                        );
+                    }
+                }
+            }
+            AddWorkspaceFolder(folder) => {
+                if self.workspace_folders.contains(&folder) {
+                    return;
+                }
+                for (_, plugin) in self.plugins.iter() {
+                    change_workspace_folders(plugin, &[folder.clone()], &[]);
+                }
+                self.workspace_folders.push(folder);
+            }
+            RemoveWorkspaceFolder(folder) => {
+                if !self.workspace_folders.contains(&folder) {
+                    return;
+                }
+                for (_, plugin) in self.plugins.iter() {
+                    change_workspace_folders(plugin, &[], &[folder.clone()]);
+                }
+                self.workspace_folders.retain(|f| f != &folder);
+            }
+            CancelRequest(origin) => {
+                for (_, plugin) in self.plugins.iter() {
+                    plugin.cancel_request(origin);
+                }
+            }
             Shutdown => {
                 for (_, plugin) in self.plugins.iter() {
                     plugin.shutdown();
@@ -400,3 +471,38 @@ pub fn handle_notification(&mut self, notification: PluginCatalogNotification) {
         }
     }
 }
+
+/// Tell a server about the workspaces that were added to the ones it serves,
+/// or removed from them, if it wants to know.
+fn change_workspace_folders(
+    plugin: &PluginServerRpcHandler,
+    added: &[PathBuf],
+    removed: &[PathBuf],
+) {
+    if added.is_empty() && removed.is_empty() {
+        return;
+    }
+    let folders = |paths: &[PathBuf]| -> Vec<WorkspaceFolder> {
+        paths.iter().filter_map(|p| workspace_folder(p)).collect()
+    };
+    plugin.server_notification(
+        DidChangeWorkspaceFolders::METHOD,
+        DidChangeWorkspaceFoldersParams {
+            event: WorkspaceFoldersChangeEvent {
+                added: folders(added),
+                removed: folders(removed),
+            },
+        },
+        None,
+        None,
+        true,
+    );
+}
+
+fn workspace_folder(path: &Path) -> Option<WorkspaceFolder> {
+    let uri = Url::from_directory_path(path).ok()?;
+    Some(WorkspaceFolder {
+        name: uri.as_str().to_string(),
+        uri,
+    })
+}
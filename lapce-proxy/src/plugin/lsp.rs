@@ -12,7 +12,7 @@
 use crossbeam_channel::Sender;
 use jsonrpc_lite::{Id, Params};
 use lapce_core::meta;
-use lapce_rpc::{style::LineStyle, RpcError};
+use lapce_rpc::{plugin::VoltPermissions, style::LineStyle, RpcError};
 use lapce_xi_rope::Rope;
 use lsp_types::{
     notification::{Initialized, Notification},
@@ -249,6 +249,9 @@ fn new(
             pwd,
             volt_id,
             volt_display_name,
+            // The volt was checked before it could start the server, which
+            // doesn't ask for more than a language server does
+            VoltPermissions::legacy(),
             document_selector,
             server_rpc.clone(),
             plugin_rpc.clone(),
@@ -398,6 +401,7 @@ fn initialize(&mut self) {
                     ..Default::default()
                 }),
                 configuration: Some(false),
+                workspace_folders: Some(true),
                 ..Default::default()
             }),
 
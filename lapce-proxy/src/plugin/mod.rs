@@ -1,11 +1,11 @@
 pub mod catalog;
 pub mod lsp;
 pub mod psp;
+pub mod registry;
 pub mod wasi;
 
 use std::{
     collections::HashMap,
-    fs,
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
@@ -17,8 +17,6 @@
 use anyhow::{anyhow, Result};
 use crossbeam_channel::{Receiver, Sender};
 use dyn_clone::DynClone;
-use flate2::read::GzDecoder;
-use lapce_core::directory::Directory;
 use lapce_rpc::{
     core::CoreRpcHandler,
     plugin::{PluginId, VoltInfo, VoltMetadata},
@@ -51,12 +49,12 @@
 use parking_lot::Mutex;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
-use tar::Archive;
 
 use self::{
     catalog::PluginCatalog,
-    psp::{ClonableCallback, PluginServerRpcHandler, RpcCallback},
-    wasi::{load_volt, start_volt},
+    psp::{ClonableCallback, PluginServerRpcHandler, RequestOrigin, RpcCallback},
+    registry::download_volt,
+    wasi::{is_approved, remove_approval, start_volt},
 };
 use crate::buffer::language_id_from_path;
 
@@ -67,6 +65,7 @@ pub enum PluginCatalogRpc {
     ServerRequest {
         plugin_id: Option<PluginId>,
         request_sent: Option<Arc<AtomicUsize>>,
+        origin: Option<RequestOrigin>,
         method: &'static str,
         params: Value,
         language_id: Option<String>,
@@ -114,6 +113,13 @@ pub enum PluginCatalogNotification {
     StopVolt(VoltInfo),
     EnableVolt(VoltInfo),
     ReloadVolt(VoltMetadata),
+    /// Run a command a volt registered, by the id of the volt
+    ExecuteCommand(String, String),
+    /// A workspace that shares the servers with the ones open already
+    AddWorkspaceFolder(PathBuf),
+    /// A workspace that no longer shares the servers
+    RemoveWorkspaceFolder(PathBuf),
+    CancelRequest(RequestOrigin),
     Shutdown,
 }
 
@@ -123,6 +129,11 @@ pub struct PluginCatalogRpcHandler {
     proxy_rpc: ProxyRpcHandler,
     plugin_tx: Sender<PluginCatalogRpc>,
     plugin_rx: Arc<Mutex<Option<Receiver<PluginCatalogRpc>>>>,
+    /// What the server requests are made for
+    origin: Option<RequestOrigin>,
+    /// The workspace the requests are made in, when several of them share
+    /// the catalog
+    workspace_id: usize,
     #[allow(dead_code)]
     id: Arc<AtomicU64>,
     #[allow(dead_code, clippy::type_complexity)]
@@ -137,6 +148,8 @@ pub fn new(core_rpc: CoreRpcHandler, proxy_rpc: ProxyRpcHandler) -> Self {
             proxy_rpc,
             plugin_tx,
             plugin_rx: Arc::new(Mutex::new(Some(plugin_rx))),
+            origin: None,
+            workspace_id: 0,
             id: Arc::new(AtomicU64::new(0)),
             pending: Arc::new(Mutex::new(HashMap::new())),
         }
@@ -156,6 +169,7 @@ pub fn mainloop(&self, plugin: &mut PluginCatalog) {
                 PluginCatalogRpc::ServerRequest {
                     plugin_id,
                     request_sent,
+                    origin,
                     method,
                     params,
                     language_id,
@@ -165,6 +179,7 @@ pub fn mainloop(&self, plugin: &mut PluginCatalog) {
                     plugin.handle_server_request(
                         plugin_id,
                         request_sent,
+                        origin,
                         method,
                         params,
                         language_id,
@@ -234,6 +249,63 @@ pub fn mainloop(&self, plugin: &mut PluginCatalog) {
         }
     }
 
+    /// A handler for the workspace `workspace_id` of the ones sharing this
+    /// catalog, whose answers go to its own lapce and proxy.
+    pub fn for_workspace(
+        &self,
+        workspace_id: usize,
+        core_rpc: CoreRpcHandler,
+        proxy_rpc: ProxyRpcHandler,
+    ) -> Self {
+        Self {
+            core_rpc,
+            proxy_rpc,
+            origin: None,
+            workspace_id,
+            ..self.clone()
+        }
+    }
+
+    /// A handler whose server requests are made on behalf of the proxy
+    /// request `id`, so that they are cancelled along with it.
+    pub fn for_request(&self, id: RequestId) -> Self {
+        Self {
+            origin: Some(RequestOrigin::Request(self.workspace_id, id)),
+            ..self.clone()
+        }
+    }
+
+    /// A handler whose server requests replace the ones made for `origin`
+    /// before, which are cancelled.
+    fn superseding(&self, origin: RequestOrigin) -> Self {
+        self.cancel_origin(origin);
+        Self {
+            origin: Some(origin),
+            ..self.clone()
+        }
+    }
+
+    pub fn cancel_request(&self, id: RequestId) {
+        self.cancel_origin(RequestOrigin::Request(self.workspace_id, id));
+    }
+
+    fn cancel_origin(&self, origin: RequestOrigin) {
+        let _ = self
+            .catalog_notification(PluginCatalogNotification::CancelRequest(origin));
+    }
+
+    pub fn add_workspace_folder(&self, workspace: PathBuf) -> Result<()> {
+        self.catalog_notification(PluginCatalogNotification::AddWorkspaceFolder(
+            workspace,
+        ))
+    }
+
+    pub fn remove_workspace_folder(&self, workspace: PathBuf) -> Result<()> {
+        self.catalog_notification(PluginCatalogNotification::RemoveWorkspaceFolder(
+            workspace,
+        ))
+    }
+
     pub fn shutdown(&self) {
         let _ = self.catalog_notification(PluginCatalogNotification::Shutdown);
         let _ = self.plugin_tx.send(PluginCatalogRpc::Shutdown);
@@ -315,6 +387,7 @@ fn send_request<P: Serialize>(
         let rpc = PluginCatalogRpc::ServerRequest {
             plugin_id,
             request_sent,
+            origin: self.origin,
             method,
             params,
             language_id,
@@ -768,7 +841,11 @@ pub fn completion(
         let language_id =
             Some(language_id_from_path(path).unwrap_or("").to_string());
 
-        self.send_request_to_all_plugins(
+        // The servers are still working on the completion of what was typed
+        // before, which nobody is waiting for anymore
+        let catalog_rpc =
+            self.superseding(RequestOrigin::Completion(self.workspace_id));
+        catalog_rpc.send_request_to_all_plugins(
             method,
             params,
             language_id,
@@ -843,7 +920,9 @@ pub fn signature_help(
         let core_rpc = self.core_rpc.clone();
         let language_id =
             Some(language_id_from_path(path).unwrap_or("").to_string());
-        self.send_request(
+        let catalog_rpc =
+            self.superseding(RequestOrigin::SignatureHelp(self.workspace_id));
+        catalog_rpc.send_request(
             None,
             None,
             method,
@@ -951,6 +1030,12 @@ pub fn reload_volt(&self, volt: VoltMetadata) -> Result<()> {
     pub fn enable_volt(&self, volt: VoltInfo) -> Result<()> {
         self.catalog_notification(PluginCatalogNotification::EnableVolt(volt))
     }
+
+    pub fn execute_command(&self, volt_id: String, command: String) -> Result<()> {
+        self.catalog_notification(PluginCatalogNotification::ExecuteCommand(
+            volt_id, command,
+        ))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -981,53 +1066,6 @@ pub fn volt_icon(volt: &VoltMetadata) -> Option<String> {
     std::fs::read(icon).ok().map(base64::encode)
 }
 
-pub fn download_volt(volt: &VoltInfo) -> Result<VoltMetadata> {
-    let url = format!(
-        "https://plugins.lapce.dev/api/v1/plugins/{}/{}/{}/download",
-        volt.author, volt.name, volt.version
-    );
-
-    let resp = reqwest::blocking::get(url)?;
-    if !resp.status().is_success() {
-        return Err(anyhow!("can't download plugin"));
-    }
-
-    // this is the s3 url
-    let url = resp.text()?;
-
-    let mut resp = reqwest::blocking::get(url)?;
-    if !resp.status().is_success() {
-        return Err(anyhow!("can't download plugin"));
-    }
-
-    let is_zstd = resp
-        .headers()
-        .get("content-type")
-        .and_then(|v| v.to_str().ok())
-        == Some("application/zstd");
-
-    let id = volt.id();
-    let plugin_dir = Directory::plugins_directory()
-        .ok_or_else(|| anyhow!("can't get plugin directory"))?
-        .join(&id);
-    let _ = fs::remove_dir_all(&plugin_dir);
-    fs::create_dir_all(&plugin_dir)?;
-
-    if is_zstd {
-        let tar = zstd::Decoder::new(&mut resp).unwrap();
-        let mut archive = Archive::new(tar);
-        archive.unpack(&plugin_dir)?;
-    } else {
-        let tar = GzDecoder::new(&mut resp);
-        let mut archive = Archive::new(tar);
-        archive.unpack(&plugin_dir)?;
-    }
-
-    let meta_path = plugin_dir.join("volt.toml");
-    let meta = load_volt(&meta_path)?;
-    Ok(meta)
-}
-
 pub fn install_volt(
     catalog_rpc: PluginCatalogRpcHandler,
     workspace: Option<PathBuf>,
@@ -1041,10 +1079,15 @@ pub fn install_volt(
             .volt_installing(volt, "Could not download Plugin".to_string());
     }
     let meta = download_volt_result?;
-    let local_catalog_rpc = catalog_rpc.clone();
-    let local_meta = meta.clone();
-
-    let _ = start_volt(workspace, configurations, local_catalog_rpc, local_meta);
+    if is_approved(&meta) {
+        let local_catalog_rpc = catalog_rpc.clone();
+        let local_meta = meta.clone();
+        let _ = start_volt(workspace, configurations, local_catalog_rpc, local_meta);
+    } else {
+        catalog_rpc
+            .core_rpc
+            .volt_permissions_requested(vec![meta.clone()]);
+    }
     let icon = volt_icon(&meta);
     catalog_rpc.core_rpc.volt_installed(meta, icon);
     Ok(())
@@ -1068,6 +1111,7 @@ pub fn remove_volt(
                 "Could not remove Plugin Directory".to_string(),
             );
         } else {
+            remove_approval(&volt.id());
             catalog_rpc.core_rpc.volt_removed(volt.info(), false);
         }
         Ok(())
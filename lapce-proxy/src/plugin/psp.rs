@@ -14,16 +14,18 @@
 use jsonrpc_lite::{Id, JsonRpc, Params};
 use lapce_core::{buffer::rope_text::RopeText, encoding::offset_utf16_to_utf8};
 use lapce_rpc::{
-    plugin::PluginId,
+    metrics,
+    plugin::{PluginCommand, PluginId, VoltPermissions},
     style::{LineStyle, Style},
-    RpcError,
+    trace::{self, TraceChannel, TraceEvent, TraceKind},
+    RequestId, RpcError,
 };
 use lapce_xi_rope::{Rope, RopeDelta};
 use lsp_types::{
     notification::{
-        DidChangeTextDocument, DidOpenTextDocument, DidSaveTextDocument,
-        Initialized, LogMessage, Notification, Progress, PublishDiagnostics,
-        ShowMessage,
+        Cancel, DidChangeTextDocument, DidChangeWorkspaceFolders,
+        DidOpenTextDocument, DidSaveTextDocument, Initialized, LogMessage,
+        Notification, Progress, PublishDiagnostics, ShowMessage,
     },
     request::{
         CodeActionRequest, CodeActionResolveRequest, Completion,
@@ -35,8 +37,8 @@
     },
     CodeActionProviderCapability, DidChangeTextDocumentParams,
     DidSaveTextDocumentParams, DocumentSelector, HoverProviderCapability,
-    LogMessageParams, OneOf, ProgressParams, PublishDiagnosticsParams, Range,
-    Registration, RegistrationParams, SemanticTokens, SemanticTokensLegend,
+    LogMessageParams, MessageType, OneOf, ProgressParams, PublishDiagnosticsParams,
+    Range, Registration, RegistrationParams, SemanticTokens, SemanticTokensLegend,
     SemanticTokensServerCapabilities, ServerCapabilities, ShowMessageParams,
     TextDocumentContentChangeEvent, TextDocumentIdentifier,
     TextDocumentSaveRegistrationOptions, TextDocumentSyncCapability,
@@ -48,7 +50,7 @@
     ExecuteProcess, ExecuteProcessParams, ExecuteProcessResult, Request,
     StartLspServer, StartLspServerParams,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use super::{
@@ -56,6 +58,16 @@
     PluginCatalogRpcHandler,
 };
 
+/// Sent by a volt to add a [`PluginCommand`] to the palette.
+pub const REGISTER_COMMAND: &str = "lapce/registerCommand";
+/// Sent to a volt when one of its commands is run from the palette.
+pub const EXECUTE_COMMAND: &str = "lapce/executeCommand";
+
+#[derive(Deserialize, Serialize)]
+pub struct ExecuteCommandParams {
+    pub command: String,
+}
+
 pub enum ResponseHandler<Resp, Error> {
     Chan(Sender<Result<Resp, Error>>),
     Callback(Box<dyn RpcCallback<Resp, Error>>),
@@ -152,6 +164,19 @@ pub enum PluginServerRpc {
     },
 }
 
+/// What a server request is made for, so that the server requests made for
+/// the same thing can be cancelled together. The workspaces sharing the
+/// servers are told apart by their id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOrigin {
+    /// A request of lapce to the proxy
+    Request(usize, RequestId),
+    /// A completion, which is superseded by the next one in its workspace
+    Completion(usize),
+    /// A signature help, which is superseded by the next one in its workspace
+    SignatureHelp(usize),
+}
+
 #[derive(Clone)]
 pub struct PluginServerRpcHandler {
     pub plugin_id: PluginId,
@@ -161,6 +186,8 @@ pub struct PluginServerRpcHandler {
     io_tx: Sender<JsonRpc>,
     id: Arc<AtomicU64>,
     server_pending: Arc<Mutex<HashMap<Id, ResponseHandler<Value, RpcError>>>>,
+    /// What the pending server requests were made for
+    server_origins: Arc<Mutex<HashMap<Id, RequestOrigin>>>,
 }
 
 pub trait PluginServerHandler {
@@ -223,6 +250,7 @@ pub fn new(volt_id: String, io_tx: Sender<JsonRpc>) -> Self {
             io_tx,
             id: Arc::new(AtomicU64::new(0)),
             server_pending: Arc::new(Mutex::new(HashMap::new())),
+            server_origins: Arc::new(Mutex::new(HashMap::new())),
         };
 
         rpc.initialize();
@@ -256,11 +284,28 @@ fn send_server_notification(&self, method: &str, params: Params) {
     }
 
     fn send_server_rpc(&self, msg: JsonRpc) {
+        trace::record(
+            TraceChannel::Plugin,
+            self.plugin_id.0,
+            trace_kind(&msg),
+            trace_id(&msg),
+            &msg,
+        );
         let _ = self.io_tx.send(msg);
     }
 
     pub fn handle_rpc(&self, rpc: PluginServerRpc) {
         let _ = self.rpc_tx.send(rpc);
+        self.record_queue();
+    }
+
+    /// Record how many messages are queued up for the plugin in the metrics.
+    fn record_queue(&self) {
+        metrics::record_queue(
+            TraceChannel::Plugin,
+            self.plugin_id.0,
+            self.rpc_rx.len(),
+        );
     }
 
     pub fn server_notification<P: Serialize>(
@@ -274,7 +319,7 @@ pub fn server_notification<P: Serialize>(
         let params = Params::from(serde_json::to_value(params).unwrap());
 
         if check {
-            let _ = self.rpc_tx.send(PluginServerRpc::ServerNotification {
+            self.handle_rpc(PluginServerRpc::ServerNotification {
                 method,
                 params,
                 language_id,
@@ -304,6 +349,7 @@ pub fn server_request<P: Serialize>(
             language_id,
             path,
             check,
+            None,
             ResponseHandler::Chan(tx),
         );
         rx.recv().unwrap_or_else(|_| {
@@ -321,6 +367,7 @@ pub fn server_request_async<P: Serialize>(
         language_id: Option<String>,
         path: Option<PathBuf>,
         check: bool,
+        origin: Option<RequestOrigin>,
         f: impl RpcCallback<Value, RpcError> + 'static,
     ) {
         self.server_request_common(
@@ -329,6 +376,7 @@ pub fn server_request_async<P: Serialize>(
             language_id,
             path,
             check,
+            origin,
             ResponseHandler::Callback(Box::new(f)),
         );
     }
@@ -340,12 +388,18 @@ fn server_request_common<P: Serialize>(
         language_id: Option<String>,
         path: Option<PathBuf>,
         check: bool,
+        origin: Option<RequestOrigin>,
         rh: ResponseHandler<Value, RpcError>,
     ) {
         let id = self.id.fetch_add(1, Ordering::Relaxed);
         let params = Params::from(serde_json::to_value(params).unwrap());
+        if let Some(origin) = origin {
+            self.server_origins
+                .lock()
+                .insert(Id::Num(id as i64), origin);
+        }
         if check {
-            let _ = self.rpc_tx.send(PluginServerRpc::ServerRequest {
+            self.handle_rpc(PluginServerRpc::ServerRequest {
                 id: Id::Num(id as i64),
                 method,
                 params,
@@ -359,11 +413,35 @@ fn server_request_common<P: Serialize>(
     }
 
     pub fn handle_server_response(&self, id: Id, result: Result<Value, RpcError>) {
+        self.server_origins.lock().remove(&id);
         if let Some(handler) = { self.server_pending.lock().remove(&id) } {
             handler.invoke(result);
         }
     }
 
+    /// Drop the pending server requests made for `origin`, and tell the
+    /// server it doesn't need to answer them.
+    pub fn cancel_request(&self, origin: RequestOrigin) {
+        let ids: Vec<Id> = {
+            let mut origins = self.server_origins.lock();
+            let ids: Vec<Id> = origins
+                .iter()
+                .filter(|(_, o)| **o == origin)
+                .map(|(id, _)| id.clone())
+                .collect();
+            for id in ids.iter() {
+                origins.remove(id);
+            }
+            ids
+        };
+        for id in ids {
+            if self.server_pending.lock().remove(&id).is_some() {
+                let params = Params::from(serde_json::json!({ "id": id }));
+                self.send_server_notification(Cancel::METHOD, params);
+            }
+        }
+    }
+
     pub fn shutdown(&self) {
         self.handle_rpc(PluginServerRpc::Handler(
             PluginHandlerNotification::Shutdown,
@@ -376,6 +454,7 @@ pub fn mainloop<H>(&self, handler: &mut H)
         H: PluginServerHandler,
     {
         for msg in &self.rpc_rx {
+            self.record_queue();
             match msg {
                 PluginServerRpc::ServerRequest {
                     id,
@@ -391,6 +470,7 @@ pub fn mainloop<H>(&self, handler: &mut H)
                     {
                         self.send_server_request(id, method, params, rh);
                     } else {
+                        self.server_origins.lock().remove(&id);
                         rh.invoke(Err(RpcError {
                             code: 0,
                             message: "server not capable".to_string(),
@@ -465,11 +545,45 @@ pub fn mainloop<H>(&self, handler: &mut H)
     }
 }
 
+fn trace_kind(msg: &JsonRpc) -> TraceKind {
+    match msg {
+        JsonRpc::Request(_) => TraceKind::Request,
+        JsonRpc::Notification(_) if msg.get_method() == Some(Cancel::METHOD) => {
+            TraceKind::Cancel
+        }
+        JsonRpc::Notification(_) => TraceKind::Notification,
+        JsonRpc::Success(_) => TraceKind::Response,
+        JsonRpc::Error(_) => TraceKind::Error,
+    }
+}
+
+/// Only numeric ids are traced, which are the ones lapce gives its requests.
+fn trace_id(msg: &JsonRpc) -> Option<RequestId> {
+    match msg.get_id()? {
+        Id::Num(id) => u64::try_from(id).ok(),
+        _ => None,
+    }
+}
+
 pub fn handle_plugin_server_message(
     server_rpc: &PluginServerRpcHandler,
     message: &str,
 ) -> Option<JsonRpc> {
-    match JsonRpc::parse(message) {
+    let msg = JsonRpc::parse(message);
+    if trace::is_recording() {
+        if let Ok(msg) = msg.as_ref() {
+            trace::record_event(TraceEvent {
+                time: trace::now(),
+                channel: TraceChannel::Plugin,
+                source: server_rpc.plugin_id.0,
+                kind: trace_kind(msg),
+                id: trace_id(msg),
+                method: msg.get_method().map(|method| method.to_string()),
+                size: message.len(),
+            });
+        }
+    }
+    match msg {
         Ok(value @ JsonRpc::Request(_)) => {
             let (tx, rx) = crossbeam_channel::unbounded();
             let id = value.get_id().unwrap();
@@ -538,6 +652,7 @@ struct ServerRegistrations {
 pub struct PluginHostHandler {
     volt_id: String,
     volt_display_name: String,
+    permissions: VoltPermissions,
     pwd: Option<PathBuf>,
     pub(crate) workspace: Option<PathBuf>,
     document_selector: Vec<DocumentFilter>,
@@ -553,6 +668,7 @@ pub fn new(
         pwd: Option<PathBuf>,
         volt_id: String,
         volt_display_name: String,
+        permissions: VoltPermissions,
         document_selector: DocumentSelector,
         server_rpc: PluginServerRpcHandler,
         catalog_rpc: PluginCatalogRpcHandler,
@@ -566,6 +682,7 @@ pub fn new(
             workspace,
             volt_id,
             volt_display_name,
+            permissions,
             document_selector,
             catalog_rpc,
             server_rpc,
@@ -715,6 +832,17 @@ pub fn method_registered(&mut self, method: &'static str) -> bool {
             CodeActionResolveRequest::METHOD => {
                 self.server_capabilities.code_action_provider.is_some()
             }
+            DidChangeWorkspaceFolders::METHOD => self
+                .server_capabilities
+                .workspace
+                .as_ref()
+                .and_then(|w| w.workspace_folders.as_ref())
+                .and_then(|f| f.change_notifications.as_ref())
+                .map(|c| match c {
+                    OneOf::Left(is_capable) => *is_capable,
+                    OneOf::Right(_) => true,
+                })
+                .unwrap_or(false),
             _ => false,
         }
     }
@@ -822,6 +950,7 @@ pub fn process_request(
                 Ok(Value::Null)
             }
             ExecuteProcess::METHOD => {
+                self.check_process_permission()?;
                 let params: ExecuteProcessParams =
                     serde_json::from_value(serde_json::to_value(params)?)?;
                 let output = std::process::Command::new(params.program)
@@ -835,6 +964,26 @@ pub fn process_request(
         }
     }
 
+    /// Fail if the volt can't run programs, and tell the user about it, as
+    /// the volt may not.
+    fn check_process_permission(&self) -> Result<()> {
+        if self.permissions.process {
+            return Ok(());
+        }
+        let message = format!(
+            "{} isn't permitted to run programs, see the permissions in its volt.toml",
+            self.volt_display_name
+        );
+        self.catalog_rpc.core_rpc.show_message(
+            format!("Plugin: {}", self.volt_display_name),
+            ShowMessageParams {
+                typ: MessageType::WARNING,
+                message: message.clone(),
+            },
+        );
+        Err(anyhow!(message))
+    }
+
     pub fn handle_notification(
         &mut self,
         method: String,
@@ -842,6 +991,7 @@ pub fn handle_notification(
     ) -> Result<()> {
         match method.as_str() {
             StartLspServer::METHOD => {
+                self.check_process_permission()?;
                 let params: StartLspServerParams =
                     serde_json::from_value(serde_json::to_value(params)?)?;
                 let workspace = self.workspace.clone();
@@ -884,6 +1034,13 @@ pub fn handle_notification(
                     serde_json::from_value(serde_json::to_value(params)?)?;
                 self.catalog_rpc.core_rpc.log_message(message);
             }
+            REGISTER_COMMAND => {
+                let command: PluginCommand =
+                    serde_json::from_value(serde_json::to_value(params)?)?;
+                self.catalog_rpc
+                    .core_rpc
+                    .plugin_command_registered(self.volt_id.clone(), command);
+            }
             _ => {
                 eprintln!("host notificaton {method} not handled");
             }
@@ -0,0 +1,184 @@
+use std::{fs, io::Read, path::Path};
+
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use lapce_core::directory::Directory;
+use lapce_rpc::{
+    core::CoreRpcHandler,
+    plugin::{PluginsInfo, VoltInfo, VoltMetadata},
+};
+use sha2::{Digest, Sha256};
+use tar::Archive;
+
+use super::wasi::load_volt;
+
+/// Where the volts are searched for and downloaded from
+pub const REGISTRY_URL: &str = "https://plugins.lapce.dev/api/v1/plugins";
+
+/// Search the registry, a page at a time.
+pub fn search(query: &str, offset: usize) -> Result<PluginsInfo> {
+    let plugins: PluginsInfo = reqwest::blocking::Client::new()
+        .get(REGISTRY_URL)
+        .query(&[("q", query), ("offset", &offset.to_string())])
+        .send()?
+        .json()?;
+    Ok(plugins)
+}
+
+/// The newest version of a volt in the registry.
+pub fn latest(author: &str, name: &str) -> Result<VoltInfo> {
+    latest_with(&reqwest::blocking::Client::new(), author, name)
+}
+
+fn latest_with(
+    client: &reqwest::blocking::Client,
+    author: &str,
+    name: &str,
+) -> Result<VoltInfo> {
+    let url = format!("{REGISTRY_URL}/{author}/{name}/latest");
+    let info: VoltInfo = client.get(url).send()?.json()?;
+    Ok(info)
+}
+
+/// Whether the version in the registry is newer than the installed one. The
+/// versions that aren't semver never are.
+pub fn is_newer(latest: &str, installed: &str) -> bool {
+    match (
+        semver::Version::parse(latest),
+        semver::Version::parse(installed),
+    ) {
+        (Ok(latest), Ok(installed)) => latest > installed,
+        _ => false,
+    }
+}
+
+/// Tell lapce about the volts that have a newer version in the registry, so
+/// that the plugin panel offers to upgrade them. The registry is asked about
+/// every volt before they're all reported at once; this blocks until then.
+pub fn check_updates(core_rpc: &CoreRpcHandler, volts: &[VoltMetadata]) {
+    let client = reqwest::blocking::Client::new();
+    let updates: Vec<VoltInfo> = volts
+        .iter()
+        .filter_map(
+            |volt| match latest_with(&client, &volt.author, &volt.name) {
+                Ok(info) if is_newer(&info.version, &volt.version) => Some(info),
+                Ok(_) => None,
+                Err(err) => {
+                    eprintln!("can't check {} for updates: {err}", volt.id());
+                    None
+                }
+            },
+        )
+        .collect();
+    if !updates.is_empty() {
+        core_rpc.volt_updates_available(updates);
+    }
+}
+
+/// Download a volt and install it into the plugins directory, replacing the
+/// version that was installed before, if any. Volts the registry doesn't list
+/// a checksum for are refused.
+pub fn download_volt(volt: &VoltInfo) -> Result<VoltMetadata> {
+    let id = volt.id();
+    // A package that can't be verified isn't installed
+    let checksum = volt
+        .checksum
+        .as_deref()
+        .ok_or_else(|| anyhow!("the registry doesn't list a checksum for {id}"))?;
+
+    let url = format!(
+        "{REGISTRY_URL}/{}/{}/{}/download",
+        volt.author, volt.name, volt.version
+    );
+
+    let resp = reqwest::blocking::get(url)?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("can't download plugin"));
+    }
+
+    // this is the s3 url
+    let url = resp.text()?;
+
+    let resp = reqwest::blocking::get(url)?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("can't download plugin"));
+    }
+
+    let is_zstd = resp
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        == Some("application/zstd");
+    let content = resp.bytes()?;
+    verify_checksum(&content, checksum)?;
+
+    let plugins_dir = Directory::plugins_directory()
+        .ok_or_else(|| anyhow!("can't get plugin directory"))?;
+    let plugin_dir = plugins_dir.join(&id);
+    // Unpacked next to the installed volt first, so that a broken package
+    // doesn't replace it. The directories starting with a dot aren't loaded
+    // as volts.
+    let staging_dir = plugins_dir.join(format!(".{id}.download"));
+    let _ = fs::remove_dir_all(&staging_dir);
+    fs::create_dir_all(&staging_dir)?;
+    let unpacked = if is_zstd {
+        zstd::Decoder::new(&content[..])
+            .map_err(anyhow::Error::from)
+            .and_then(|tar| unpack(tar, &staging_dir))
+    } else {
+        unpack(GzDecoder::new(&content[..]), &staging_dir)
+    }
+    .and_then(|_| load_volt(&staging_dir.join("volt.toml")));
+    if let Err(err) = unpacked {
+        let _ = fs::remove_dir_all(&staging_dir);
+        return Err(err);
+    }
+
+    let _ = fs::remove_dir_all(&plugin_dir);
+    fs::rename(&staging_dir, &plugin_dir)?;
+
+    let meta_path = plugin_dir.join("volt.toml");
+    let meta = load_volt(&meta_path)?;
+    Ok(meta)
+}
+
+fn unpack(tar: impl Read, dir: &Path) -> Result<()> {
+    Archive::new(tar).unpack(dir)?;
+    Ok(())
+}
+
+/// Check a package against the hex encoded SHA-256 the registry lists for it.
+fn verify_checksum(content: &[u8], checksum: &str) -> Result<()> {
+    let mut hasher = Sha256::new();
+    hasher.update(content);
+    let actual = format!("{:x}", hasher.finalize());
+    if !actual.eq_ignore_ascii_case(checksum.trim()) {
+        return Err(anyhow!(
+            "plugin checksum mismatch: expected {checksum}, got {actual}"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify_checksum() {
+        let checksum =
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert!(verify_checksum(b"abc", checksum).is_ok());
+        assert!(verify_checksum(b"abc", &checksum.to_uppercase()).is_ok());
+        assert!(verify_checksum(b"abd", checksum).is_err());
+    }
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("0.2.0", "0.1.9"));
+        assert!(is_newer("1.0.0", "1.0.0-beta.1"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1.0", "0.2.0"));
+        assert!(!is_newer("latest", "0.1.0"));
+    }
+}
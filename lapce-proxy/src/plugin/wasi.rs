@@ -13,14 +13,16 @@
 use jsonrpc_lite::{Id, Params};
 use lapce_core::directory::Directory;
 use lapce_rpc::{
-    plugin::{PluginId, VoltInfo, VoltMetadata},
+    plugin::{
+        PluginId, VoltInfo, VoltMetadata, VoltPermissions, PLUGIN_API_VERSION,
+    },
     style::LineStyle,
     RpcError,
 };
 use lapce_xi_rope::{Rope, RopeDelta};
 use lsp_types::{
-    request::Initialize, ClientCapabilities, InitializeParams,
-    TextDocumentContentChangeEvent, TextDocumentIdentifier, Url,
+    request::Initialize, ClientCapabilities, InitializeParams, MessageType,
+    ShowMessageParams, TextDocumentContentChangeEvent, TextDocumentIdentifier, Url,
     VersionedTextDocumentIdentifier,
 };
 use parking_lot::Mutex;
@@ -34,7 +36,7 @@
         handle_plugin_server_message, PluginHandlerNotification, PluginHostHandler,
         PluginServerHandler, RpcCallback,
     },
-    volt_icon, PluginCatalogRpcHandler,
+    registry, volt_icon, PluginCatalogRpcHandler,
 };
 use crate::plugin::psp::PluginServerRpcHandler;
 
@@ -211,20 +213,26 @@ pub fn load_all_volts(
     plugin_rpc: PluginCatalogRpcHandler,
     disabled_volts: Vec<String>,
 ) {
-    let all_volts = find_all_volts();
-    let volts = all_volts
+    let all_volts: Vec<VoltMetadata> = find_all_volts()
         .into_iter()
+        .filter(|meta| meta.wasm.is_some())
+        .collect();
+    approve_installed_volts(&all_volts);
+    let volts = all_volts
+        .iter()
         .filter_map(|meta| {
-            meta.wasm.as_ref()?;
-            let icon = volt_icon(&meta);
+            let icon = volt_icon(meta);
             plugin_rpc.core_rpc.volt_installed(meta.clone(), icon);
             if disabled_volts.contains(&meta.id()) {
                 return None;
             }
-            Some(meta)
+            Some(meta.clone())
         })
         .collect();
     let _ = plugin_rpc.unactivated_volts(volts);
+    // Not on the way of the startup, the registry can be slow or unreachable
+    let core_rpc = plugin_rpc.core_rpc.clone();
+    thread::spawn(move || registry::check_updates(&core_rpc, &all_volts));
 }
 
 pub fn find_all_volts() -> Vec<VoltMetadata> {
@@ -318,6 +326,35 @@ pub fn start_volt(
     plugin_rpc: PluginCatalogRpcHandler,
     meta: VoltMetadata,
 ) -> Result<()> {
+    if let Some(api_version) = meta.api_version {
+        if api_version > PLUGIN_API_VERSION {
+            plugin_rpc.core_rpc.show_message(
+                format!("Plugin: {}", meta.display_name),
+                ShowMessageParams {
+                    typ: MessageType::ERROR,
+                    message: format!(
+                        "{} needs a newer version of Lapce",
+                        meta.display_name
+                    ),
+                },
+            );
+            return Err(anyhow!(
+                "volt needs plugin api {api_version}, lapce has {PLUGIN_API_VERSION}"
+            ));
+        }
+    }
+    if !is_approved(&meta) {
+        return Err(anyhow!("the permissions of {} aren't approved", meta.id()));
+    }
+    if meta.permissions.is_none() {
+        eprintln!(
+            "{} doesn't declare its permissions, so it can reach any host, \
+             run programs and read the environment",
+            meta.id()
+        );
+    }
+    let permissions = meta.permissions();
+
     let engine = wasmtime::Engine::default();
     let module = wasmtime::Module::from_file(
         &engine,
@@ -327,8 +364,9 @@ pub fn start_volt(
     )?;
     let mut linker = wasmtime::Linker::new(&engine);
     wasmtime_wasi::add_to_linker(&mut linker, |s| s)?;
-    HttpState::new()?.add_to_linker(&mut linker, |_| HttpCtx {
-        allowed_hosts: Some(vec!["insecure:allow-all".to_string()]),
+    let hosts = allowed_hosts(&permissions);
+    HttpState::new()?.add_to_linker(&mut linker, move |_| HttpCtx {
+        allowed_hosts: Some(hosts.clone()),
         max_concurrent_requests: Some(100),
     })?;
 
@@ -363,8 +401,8 @@ pub fn start_volt(
     let stdin = Arc::new(RwLock::new(WasiPipe::new()));
     let stdout = Arc::new(RwLock::new(WasiPipe::new()));
     let stderr = Arc::new(RwLock::new(WasiPipe::new()));
-    let wasi = WasiCtxBuilder::new()
-        .inherit_env()?
+    let mut wasi = WasiCtxBuilder::new()
+        .env("VOLT_API_VERSION", &PLUGIN_API_VERSION.to_string())?
         .env("VOLT_OS", std::env::consts::OS)?
         .env("VOLT_ARCH", std::env::consts::ARCH)?
         .env("VOLT_LIBC", volt_libc)?
@@ -389,12 +427,34 @@ pub fn start_volt(
                 wasmtime_wasi::ambient_authority(),
             )?,
             "/",
-        )?
-        .build();
+        )?;
+    for dir in allowed_dirs(&permissions, workspace.as_deref(), volt_path) {
+        match wasmtime_wasi::Dir::open_ambient_dir(
+            &dir,
+            wasmtime_wasi::ambient_authority(),
+        ) {
+            Ok(preopened) => {
+                wasi = wasi.preopened_dir(preopened, &dir)?;
+            }
+            Err(err) => {
+                eprintln!("can't give {} access to {dir:?}: {err}", meta.id());
+            }
+        }
+    }
+    if permissions.all_env() {
+        wasi = wasi.inherit_env()?;
+    } else {
+        for name in &permissions.env {
+            if let Ok(value) = std::env::var(name) {
+                wasi = wasi.env(name, &value)?;
+            }
+        }
+    }
+    let wasi = wasi.build();
     let mut store = wasmtime::Store::new(&engine, wasi);
 
     let (io_tx, io_rx) = crossbeam_channel::unbounded();
-    let rpc = PluginServerRpcHandler::new(meta.name.clone(), io_tx);
+    let rpc = PluginServerRpcHandler::new(meta.id(), io_tx);
 
     let local_rpc = rpc.clone();
     let local_stdin = stdin.clone();
@@ -412,6 +472,7 @@ pub fn start_volt(
             eprintln!("got stderr from plugin: {msg}");
         }
     })?;
+    linker.func_wrap("lapce", "host_api_version", || PLUGIN_API_VERSION)?;
     linker.module(&mut store, "", &module)?;
     let handle_rpc = linker
         .get(&mut store, "", "handle_rpc")
@@ -437,6 +498,7 @@ pub fn start_volt(
             meta.dir.clone(),
             meta.id(),
             meta.display_name.clone(),
+            permissions,
             Vec::new(),
             rpc.clone(),
             plugin_rpc.clone(),
@@ -454,6 +516,112 @@ pub fn start_volt(
     Ok(())
 }
 
+/// Where the permissions the user approved for a volt are kept. They're kept
+/// out of the volt's own directory, which the volt can write to.
+fn approval_path(volt_id: &str) -> Option<PathBuf> {
+    approvals_directory().map(|dir| dir.join(format!("{volt_id}.toml")))
+}
+
+fn approvals_directory() -> Option<PathBuf> {
+    Directory::plugins_directory().map(|dir| dir.join(".permissions"))
+}
+
+/// Whether the user approved the permissions the volt asks for. A volt that
+/// asks for more after an upgrade needs to be approved again.
+pub fn is_approved(meta: &VoltMetadata) -> bool {
+    let approved = approval_path(&meta.id())
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| toml::from_str::<VoltPermissions>(&contents).ok());
+    approved == Some(meta.permissions())
+}
+
+/// The volts installed before lapce asked for permissions keep working as they
+/// did: the ones that don't declare any are approved with what they could
+/// always do the first time the approvals are looked for. The volts installed
+/// after that ask the user.
+fn approve_installed_volts(volts: &[VoltMetadata]) {
+    let dir = match approvals_directory() {
+        Some(dir) => dir,
+        None => return,
+    };
+    if dir.exists() {
+        return;
+    }
+    if let Err(err) = fs::create_dir_all(&dir) {
+        eprintln!("can't create {dir:?}: {err}");
+        return;
+    }
+    for volt in volts.iter().filter(|volt| volt.permissions.is_none()) {
+        if let Err(err) = approve_permissions(&volt.id(), &VoltPermissions::legacy())
+        {
+            eprintln!("can't approve the permissions of {}: {err}", volt.id());
+        }
+    }
+}
+
+pub fn approve_permissions(
+    volt_id: &str,
+    permissions: &VoltPermissions,
+) -> Result<()> {
+    let path = approval_path(volt_id)
+        .ok_or_else(|| anyhow!("can't get plugin directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, toml::to_string(permissions)?)?;
+    Ok(())
+}
+
+pub fn remove_approval(volt_id: &str) {
+    if let Some(path) = approval_path(volt_id) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// The urls the volt can make requests to, in the form the http host
+/// functions take them.
+fn allowed_hosts(permissions: &VoltPermissions) -> Vec<String> {
+    if permissions.network.iter().any(|host| host == "*") {
+        vec!["insecure:allow-all".to_string()]
+    } else {
+        permissions.network.clone()
+    }
+}
+
+/// The directories the volt can reach besides its own, by their absolute
+/// path, which is also where the volt finds them. The ones that don't exist, or
+/// that are outside of the workspace and of the volt's directory, like
+/// `${workspace}/..`, are left out.
+fn allowed_dirs(
+    permissions: &VoltPermissions,
+    workspace: Option<&Path>,
+    volt_dir: &Path,
+) -> Vec<PathBuf> {
+    let roots: Vec<PathBuf> = workspace
+        .into_iter()
+        .chain(Some(volt_dir))
+        .filter_map(|root| root.canonicalize().ok())
+        .collect();
+    permissions
+        .fs
+        .iter()
+        .filter_map(|dir| {
+            let dir = match dir.strip_prefix("${workspace}") {
+                Some(rest) => workspace?.join(rest.trim_start_matches(['/', '\\'])),
+                None => PathBuf::from(dir),
+            };
+            if !dir.is_absolute() {
+                return None;
+            }
+            let dir = dir.canonicalize().ok()?;
+            roots
+                .iter()
+                .any(|root| dir.starts_with(root))
+                .then_some(dir)
+        })
+        .collect()
+}
+
 fn wasi_read_string(stdout: &Arc<RwLock<WasiPipe>>) -> Result<String> {
     let mut buf = String::new();
     stdout.write().unwrap().read_to_string(&mut buf)?;
@@ -486,7 +654,10 @@ mod test {
 
     use serde_json::{json, Value};
 
-    use crate::plugin::wasi::unflatten_map;
+    use lapce_rpc::plugin::VoltPermissions;
+    use toml_edit::easy as toml;
+
+    use crate::plugin::wasi::{allowed_dirs, allowed_hosts, unflatten_map};
 
     #[test]
     fn test_unflatten_map() {
@@ -507,4 +678,59 @@ fn test_unflatten_map() {
             })
         );
     }
+
+    #[test]
+    fn test_volt_permissions() {
+        let permissions = VoltPermissions {
+            fs: vec![
+                "${workspace}".to_string(),
+                "${workspace}/src".to_string(),
+                "relative".to_string(),
+            ],
+            network: vec!["https://api.github.com".to_string()],
+            process: false,
+            env: vec!["GITHUB_TOKEN".to_string()],
+        };
+        assert_eq!(allowed_hosts(&permissions), vec!["https://api.github.com"]);
+        assert_eq!(
+            allowed_hosts(&VoltPermissions::legacy()),
+            vec!["insecure:allow-all"]
+        );
+
+        let workspace = std::env::current_dir().unwrap().canonicalize().unwrap();
+        let volt_dir = workspace.join("src");
+        assert_eq!(
+            allowed_dirs(&permissions, Some(&workspace), &volt_dir),
+            vec![workspace.clone(), workspace.join("src")]
+        );
+        assert!(allowed_dirs(&permissions, None, &volt_dir).is_empty());
+
+        // Nothing outside of the workspace and of the volt's directory
+        let escaping = VoltPermissions {
+            fs: vec![
+                "${workspace}/..".to_string(),
+                "${workspace}/src/../..".to_string(),
+                "${workspace}/missing".to_string(),
+                volt_dir.join("plugin").to_string_lossy().to_string(),
+                workspace.parent().unwrap().to_string_lossy().to_string(),
+            ],
+            ..permissions.clone()
+        };
+        assert_eq!(
+            allowed_dirs(&escaping, None, &volt_dir),
+            vec![volt_dir.join("plugin")]
+        );
+        assert_eq!(
+            allowed_dirs(&escaping, Some(&workspace), &volt_dir),
+            vec![volt_dir.join("plugin")]
+        );
+
+        // The approved permissions are stored as toml and compared with the
+        // ones the volt asks for when it's loaded.
+        let stored = toml::to_string(&permissions).unwrap();
+        assert_eq!(
+            toml::from_str::<VoltPermissions>(&stored).unwrap(),
+            permissions
+        );
+    }
 }
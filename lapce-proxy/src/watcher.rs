@@ -1,10 +1,10 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     path::{Path, PathBuf},
     sync::Arc,
 };
 
-use crossbeam_channel::{unbounded, Receiver};
+use crossbeam_channel::{bounded, Receiver};
 use notify::{
     event::{ModifyKind, RenameMode},
     recommended_watcher, Event, EventKind, RecommendedWatcher, RecursiveMode,
@@ -12,6 +12,8 @@
 };
 use parking_lot::Mutex;
 
+use crate::CHANNEL_CAPACITY;
+
 /// Wrapper around a `notify::Watcher`. It runs the inner watcher
 /// in a separate thread, and communicates with it via a [crossbeam channel].
 /// [crossbeam channel]: https://docs.rs/crossbeam-channel
@@ -57,7 +59,8 @@ pub trait Notify: Send {
 
 impl FileWatcher {
     pub fn new() -> Self {
-        let (tx_event, rx_event) = unbounded();
+        // Once it's full, the watcher waits for the events to be handled
+        let (tx_event, rx_event) = bounded(CHANNEL_CAPACITY);
 
         let state = Arc::new(Mutex::new(WatcherState::default()));
 
@@ -74,7 +77,16 @@ pub fn notify<T: Notify + 'static>(&mut self, peer: T) {
         let rx_event = self.rx_event.take().unwrap();
         let state = self.state.clone();
         std::thread::spawn(move || {
-            while let Ok(Ok(event)) = rx_event.recv() {
+            while let Ok(event) = rx_event.recv() {
+                // Along with the ones that are queued up already, such as the
+                // bursts of the same event a save can make
+                let batch: Result<Vec<Event>, _> =
+                    std::iter::once(event).chain(rx_event.try_iter()).collect();
+                let batch = match batch {
+                    Ok(batch) => dedup_events(batch),
+                    Err(_) => return,
+                };
+
                 let mut events = Vec::new();
                 {
                     let mut state = state.lock();
@@ -82,14 +94,18 @@ pub fn notify<T: Notify + 'static>(&mut self, peer: T) {
                         ref mut watchees, ..
                     } = *state;
 
-                    watchees
-                        .iter()
-                        .filter(|w| w.wants_event(&event))
-                        .map(|w| w.token)
-                        .for_each(|t| events.push((t, event.clone())));
+                    for event in batch {
+                        watchees
+                            .iter()
+                            .filter(|w| w.wants_event(&event))
+                            .map(|w| w.token)
+                            .for_each(|t| events.push((t, event.clone())));
+                    }
                 }
 
-                peer.notify(events);
+                if !events.is_empty() {
+                    peer.notify(events);
+                }
             }
         });
     }
@@ -195,6 +211,22 @@ pub fn unwatch(&mut self, path: &Path, token: WatchToken) {
         }
     }
 
+    /// Removes every path watched with the provided token from the watch
+    /// list.
+    pub fn unwatch_token(&mut self, token: WatchToken) {
+        let paths: Vec<PathBuf> = self
+            .state
+            .lock()
+            .watchees
+            .iter()
+            .filter(|w| w.token == token)
+            .map(|w| w.path.clone())
+            .collect();
+        for path in paths {
+            self.unwatch(&path, token);
+        }
+    }
+
     /// Takes ownership of this `Watcher`'s current event queue.
     pub fn take_events(&self) -> VecDeque<(WatchToken, Event)> {
         let mut state = self.state.lock();
@@ -260,6 +292,19 @@ fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
     }
 }
 
+/// Drop the events that happen again later in the batch. The last of them is
+/// kept, so that the events of a path stay in the order they ended up in.
+fn dedup_events(events: Vec<Event>) -> Vec<Event> {
+    let mut seen = HashSet::new();
+    let mut events: Vec<Event> = events
+        .into_iter()
+        .rev()
+        .filter(|event| seen.insert(event.clone()))
+        .collect();
+    events.reverse();
+    events
+}
+
 fn mode_from_bool(is_recursive: bool) -> RecursiveMode {
     if is_recursive {
         RecursiveMode::Recursive
@@ -267,3 +312,32 @@ fn mode_from_bool(is_recursive: bool) -> RecursiveMode {
         RecursiveMode::NonRecursive
     }
 }
+
+#[cfg(test)]
+mod test {
+    use notify::event::{CreateKind, DataChange, RemoveKind};
+
+    use super::*;
+
+    #[test]
+    fn test_dedup_events() {
+        let modify = |path: &str| {
+            Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content)))
+                .add_path(PathBuf::from(path))
+        };
+        let create =
+            Event::new(EventKind::Create(CreateKind::File)).add_path("a".into());
+        let remove =
+            Event::new(EventKind::Remove(RemoveKind::File)).add_path("a".into());
+
+        assert_eq!(
+            dedup_events(vec![modify("a"), modify("b"), modify("a"), modify("a")]),
+            vec![modify("b"), modify("a")]
+        );
+        // The file is there in the end
+        assert_eq!(
+            dedup_events(vec![create.clone(), remove.clone(), create.clone()]),
+            vec![remove, create]
+        );
+    }
+}
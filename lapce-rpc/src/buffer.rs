@@ -15,6 +15,7 @@ pub fn next() -> Self {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewBufferResponse {
     pub content: String,
+    pub rev: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
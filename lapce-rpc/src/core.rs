@@ -10,25 +10,67 @@
 use crossbeam_channel::{Receiver, Sender};
 use lsp_types::{
     CompletionResponse, LogMessageParams, ProgressParams, PublishDiagnosticsParams,
-    ShowMessageParams, SignatureHelp,
+    ShowMessageParams, SignatureHelp, Url,
 };
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    file::FileNodeItem,
-    plugin::{PluginId, VoltInfo, VoltMetadata},
+    file::{FileNodeItem, PathObject},
+    metrics,
+    plugin::{PluginCommand, PluginId, VoltInfo, VoltMetadata},
     source_control::DiffInfo,
     terminal::TermId,
+    trace::{self, TraceChannel, TraceKind},
     RequestId, RpcError, RpcMessage,
 };
 
+/// The number of messages that can be queued up for lapce, or for the proxy.
+/// Once there are that many, sending another one waits until one was taken.
+pub const CHANNEL_CAPACITY: usize = 1024;
+
 pub enum CoreRpc {
     Request(RequestId, CoreRequest),
     Notification(Box<CoreNotification>), // Box it since clippy complains
     Shutdown,
 }
 
+/// What is queued up for lapce: either a message, or the place of a
+/// notification of which only the latest one matters, see [`CoalesceKey`].
+enum Queued {
+    Rpc(CoreRpc),
+    Latest(CoalesceKey),
+}
+
+/// Notifications of which only the latest one matters. While one of them is
+/// still queued up, a newer one replaces it instead of being queued as well.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum CoalesceKey {
+    OpenFileChanged(PathBuf),
+    PublishDiagnostics(Url),
+    WorkspaceFileChange,
+    DiffFiles,
+    DiffInfo,
+}
+
+impl CoalesceKey {
+    fn of(notification: &CoreNotification) -> Option<Self> {
+        use CoreNotification::*;
+        Some(match notification {
+            OpenFileChanged { path, .. } => {
+                CoalesceKey::OpenFileChanged(path.clone())
+            }
+            PublishDiagnostics { diagnostics } => {
+                CoalesceKey::PublishDiagnostics(diagnostics.uri.clone())
+            }
+            WorkspaceFileChange {} => CoalesceKey::WorkspaceFileChange,
+            DiffFiles { .. } => CoalesceKey::DiffFiles,
+            DiffInfo { .. } => CoalesceKey::DiffInfo,
+            _ => return None,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "method", content = "params")]
@@ -54,10 +96,17 @@ pub enum CoreNotification {
         content: String,
         rev: u64,
     },
+    /// The proxy's copy of the buffer got out of sync, and needs the whole
+    /// content with [`crate::proxy::ProxyNotification::ReopenBuffer`].
+    ResyncBuffer {
+        path: PathBuf,
+    },
     OpenPaths {
         window_tab_id: Option<(usize, usize)>,
         folders: Vec<PathBuf>,
-        files: Vec<PathBuf>,
+        files: Vec<PathObject>,
+        new_window: bool,
+        reuse_window: bool,
     },
     WorkspaceFileChange {},
     PublishDiagnostics {
@@ -92,6 +141,19 @@ pub enum CoreNotification {
         volt: VoltInfo,
         only_installing: bool,
     },
+    /// The installed volts that have a newer version in the registry
+    VoltUpdatesAvailable {
+        volts: Vec<VoltInfo>,
+    },
+    /// The volts that wait for the user to approve their permissions before
+    /// they run
+    VoltPermissionsRequested {
+        volts: Vec<VoltMetadata>,
+    },
+    PluginCommandRegistered {
+        volt_id: String,
+        command: PluginCommand,
+    },
     ListDir {
         items: Vec<FileNodeItem>,
     },
@@ -131,21 +193,27 @@ pub trait CoreHandler {
 
 #[derive(Clone)]
 pub struct CoreRpcHandler {
-    tx: Sender<CoreRpc>,
-    rx: Receiver<CoreRpc>,
+    tx: Sender<Queued>,
+    rx: Receiver<Queued>,
+    /// The latest of the notifications whose place is queued up
+    latest: Arc<Mutex<HashMap<CoalesceKey, Box<CoreNotification>>>>,
     id: Arc<AtomicU64>,
     #[allow(clippy::type_complexity)]
     pending: Arc<Mutex<HashMap<u64, Sender<Result<CoreResponse, RpcError>>>>>,
+    /// Tells the messages of this handler apart in the trace and the metrics
+    trace_source: u64,
 }
 
 impl CoreRpcHandler {
     pub fn new() -> Self {
-        let (tx, rx) = crossbeam_channel::unbounded();
+        let (tx, rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
         Self {
             tx,
             rx,
+            latest: Arc::new(Mutex::new(HashMap::new())),
             id: Arc::new(AtomicU64::new(0)),
             pending: Arc::new(Mutex::new(HashMap::new())),
+            trace_source: trace::next_source(),
         }
     }
 
@@ -153,7 +221,7 @@ pub fn mainloop<H>(&self, handler: &mut H)
     where
         H: CoreHandler,
     {
-        for msg in &self.rx {
+        while let Some(msg) = self.recv() {
             match msg {
                 CoreRpc::Request(id, rpc) => {
                     handler.handle_request(id, rpc);
@@ -168,8 +236,44 @@ pub fn mainloop<H>(&self, handler: &mut H)
         }
     }
 
-    pub fn rx(&self) -> &Receiver<CoreRpc> {
-        &self.rx
+    /// Wait for the next message for lapce.
+    pub fn recv(&self) -> Option<CoreRpc> {
+        loop {
+            let queued = self.rx.recv().ok()?;
+            if let Some(msg) = self.take(queued) {
+                return Some(msg);
+            }
+        }
+    }
+
+    /// The next message for lapce, if there is one queued up already.
+    pub fn try_recv(&self) -> Option<CoreRpc> {
+        loop {
+            let queued = self.rx.try_recv().ok()?;
+            if let Some(msg) = self.take(queued) {
+                return Some(msg);
+            }
+        }
+    }
+
+    fn take(&self, queued: Queued) -> Option<CoreRpc> {
+        self.record_queue();
+        match queued {
+            Queued::Rpc(msg) => Some(msg),
+            Queued::Latest(key) => {
+                self.latest.lock().remove(&key).map(CoreRpc::Notification)
+            }
+        }
+    }
+
+    fn send(&self, msg: CoreRpc) {
+        let _ = self.tx.send(Queued::Rpc(msg));
+        self.record_queue();
+    }
+
+    /// Record how many messages are queued up for lapce in the metrics.
+    fn record_queue(&self) {
+        metrics::record_queue(TraceChannel::Core, self.trace_source, self.rx.len());
     }
 
     pub fn handle_response(
@@ -177,6 +281,22 @@ pub fn handle_response(
         id: RequestId,
         response: Result<CoreResponse, RpcError>,
     ) {
+        match &response {
+            Ok(resp) => trace::record(
+                TraceChannel::Core,
+                self.trace_source,
+                TraceKind::Response,
+                Some(id),
+                resp,
+            ),
+            Err(err) => trace::record(
+                TraceChannel::Core,
+                self.trace_source,
+                TraceKind::Error,
+                Some(id),
+                err,
+            ),
+        }
         let tx = { self.pending.lock().remove(&id) };
         if let Some(tx) = tx {
             let _ = tx.send(response);
@@ -190,7 +310,14 @@ pub fn request(&self, request: CoreRequest) -> Result<CoreResponse, RpcError> {
             let mut pending = self.pending.lock();
             pending.insert(id, tx);
         }
-        let _ = self.tx.send(CoreRpc::Request(id, request));
+        trace::record(
+            TraceChannel::Core,
+            self.trace_source,
+            TraceKind::Request,
+            Some(id),
+            &request,
+        );
+        self.send(CoreRpc::Request(id, request));
         rx.recv().unwrap_or_else(|_| {
             Err(RpcError {
                 code: 0,
@@ -200,11 +327,36 @@ pub fn request(&self, request: CoreRequest) -> Result<CoreResponse, RpcError> {
     }
 
     pub fn shutdown(&self) {
-        let _ = self.tx.send(CoreRpc::Shutdown);
+        self.send(CoreRpc::Shutdown);
     }
 
     pub fn notification(&self, notification: CoreNotification) {
-        let _ = self.tx.send(CoreRpc::Notification(Box::new(notification)));
+        trace::record(
+            TraceChannel::Core,
+            self.trace_source,
+            TraceKind::Notification,
+            None,
+            &notification,
+        );
+        let key = match CoalesceKey::of(&notification) {
+            Some(key) => key,
+            None => {
+                self.send(CoreRpc::Notification(Box::new(notification)));
+                return;
+            }
+        };
+        {
+            let mut latest = self.latest.lock();
+            if let Some(queued) = latest.get_mut(&key) {
+                // Its place is still queued up
+                *queued = Box::new(notification);
+                return;
+            }
+            latest.insert(key.clone(), Box::new(notification));
+        }
+        // Sent without holding the lock, as it waits while the queue is full
+        let _ = self.tx.send(Queued::Latest(key));
+        self.record_queue();
     }
 
     pub fn proxy_connected(&self) {
@@ -223,6 +375,10 @@ pub fn open_file_changed(&self, path: PathBuf, content: String) {
         self.notification(CoreNotification::OpenFileChanged { path, content });
     }
 
+    pub fn resync_buffer(&self, path: PathBuf) {
+        self.notification(CoreNotification::ResyncBuffer { path });
+    }
+
     pub fn completion_response(
         &self,
         request_id: usize,
@@ -270,6 +426,25 @@ pub fn volt_removed(&self, volt: VoltInfo, only_installing: bool) {
         });
     }
 
+    pub fn volt_updates_available(&self, volts: Vec<VoltInfo>) {
+        self.notification(CoreNotification::VoltUpdatesAvailable { volts });
+    }
+
+    pub fn volt_permissions_requested(&self, volts: Vec<VoltMetadata>) {
+        self.notification(CoreNotification::VoltPermissionsRequested { volts });
+    }
+
+    pub fn plugin_command_registered(
+        &self,
+        volt_id: String,
+        command: PluginCommand,
+    ) {
+        self.notification(CoreNotification::PluginCommandRegistered {
+            volt_id,
+            command,
+        });
+    }
+
     pub fn log(&self, level: log::Level, message: String) {
         self.notification(CoreNotification::Log {
             level: level.as_str().to_string(),
@@ -307,3 +482,48 @@ fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn file_changed(path: &str, content: &str) -> CoreNotification {
+        CoreNotification::OpenFileChanged {
+            path: PathBuf::from(path),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_coalesce_notifications() {
+        let core_rpc = CoreRpcHandler::new();
+        core_rpc.notification(file_changed("a", "1"));
+        core_rpc.notification(CoreNotification::WorkspaceFileChange {});
+        core_rpc.notification(file_changed("b", "1"));
+        core_rpc.notification(file_changed("a", "2"));
+        core_rpc.notification(CoreNotification::WorkspaceFileChange {});
+        core_rpc.notification(CoreNotification::ProxyConnected {});
+
+        let mut messages = Vec::new();
+        while let Some(CoreRpc::Notification(n)) = core_rpc.try_recv() {
+            messages.push(match *n {
+                CoreNotification::OpenFileChanged { path, content } => {
+                    format!("{}:{content}", path.display())
+                }
+                CoreNotification::WorkspaceFileChange {} => "workspace".to_string(),
+                CoreNotification::ProxyConnected {} => "connected".to_string(),
+                _ => unreachable!(),
+            });
+        }
+        assert_eq!(messages, vec!["a:2", "workspace", "b:1", "connected"]);
+
+        // Once taken, the next one is queued up again
+        core_rpc.notification(file_changed("a", "3"));
+        assert!(matches!(
+            core_rpc.try_recv(),
+            Some(CoreRpc::Notification(n))
+                if matches!(*n, CoreNotification::OpenFileChanged { .. })
+        ));
+        assert!(core_rpc.try_recv().is_none());
+    }
+}
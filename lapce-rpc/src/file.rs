@@ -174,3 +174,114 @@ pub fn update_node_count(&mut self, path: &Path) -> Option<()> {
         None
     }
 }
+
+/// Zero based line and UTF8 column offset
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LineCol {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A path given on the command line, optionally followed by a one based
+/// position as in `path:line` or `path:line:column`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PathObject {
+    pub path: PathBuf,
+    pub linecol: Option<LineCol>,
+}
+
+impl PathObject {
+    pub fn from_path(path: PathBuf) -> PathObject {
+        PathObject {
+            path,
+            linecol: None,
+        }
+    }
+
+    /// Resolve `arg` against `pwd`. The position suffix is only split off
+    /// when the whole argument isn't an existing path.
+    pub fn from_arg(pwd: &Path, arg: &Path) -> PathObject {
+        let full_path = pwd.join(arg);
+        if full_path.exists() {
+            return PathObject::from_path(
+                full_path.canonicalize().unwrap_or(full_path),
+            );
+        }
+
+        let (path, linecol) = arg
+            .to_str()
+            .and_then(split_position)
+            .map(|(path, line, column)| {
+                (
+                    PathBuf::from(path),
+                    Some(LineCol {
+                        line: line.saturating_sub(1),
+                        column: column.unwrap_or(1).saturating_sub(1),
+                    }),
+                )
+            })
+            .unwrap_or_else(|| (arg.to_path_buf(), None));
+        // A file that doesn't exist yet is opened as a new one
+        let path = pwd.join(path);
+        PathObject {
+            path: path.canonicalize().unwrap_or(path),
+            linecol,
+        }
+    }
+}
+
+/// Split `path:line:column` or `path:line` into its parts
+fn split_position(arg: &str) -> Option<(&str, usize, Option<usize>)> {
+    let (rest, last) = arg.rsplit_once(':')?;
+    let last = last.parse::<usize>().ok()?;
+    if let Some((path, line)) = rest.rsplit_once(':') {
+        if let Ok(line) = line.parse::<usize>() {
+            return Some((path, line, Some(last)));
+        }
+    }
+    Some((rest, last, None))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{split_position, LineCol, PathObject};
+
+    #[test]
+    fn test_from_arg_new_file() {
+        let pwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            PathObject::from_arg(&pwd, Path::new("newfile.rs")),
+            PathObject::from_path(pwd.join("newfile.rs"))
+        );
+        assert_eq!(
+            PathObject::from_arg(&pwd, Path::new("newfile.rs:3:2")),
+            PathObject {
+                path: pwd.join("newfile.rs"),
+                linecol: Some(LineCol { line: 2, column: 1 }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_split_position() {
+        assert_eq!(split_position("src/main.rs"), None);
+        assert_eq!(
+            split_position("src/main.rs:12"),
+            Some(("src/main.rs", 12, None))
+        );
+        assert_eq!(
+            split_position("src/main.rs:12:5"),
+            Some(("src/main.rs", 12, Some(5)))
+        );
+        assert_eq!(
+            split_position("C:\\src\\main.rs:12"),
+            Some(("C:\\src\\main.rs", 12, None))
+        );
+        assert_eq!(
+            split_position("src/main.rs:a:5"),
+            Some(("src/main.rs:a", 5, None))
+        );
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    io::{self, BufRead, Read, Write},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::RecvTimeoutError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{session::SessionId, RpcCodec};
+
+/// How long lapce waits for the hello of a proxy it started. A proxy from
+/// before the handshake never answers it.
+pub const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest a hello can be, in bytes. It's read before the peer is let
+/// in, so anyone who can connect can send one.
+const MAX_HELLO: u64 = 64 * 1024;
+
+/// The optional parts of the protocol a peer can support. Anything that a
+/// peer doesn't announce is not sent to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Length-prefixed MessagePack frames, see [`RpcCodec::MessagePack`]
+    MessagePack,
+    /// `RpcMessage::Cancel`
+    Cancel,
+    /// `RpcMessage::Partial`
+    Partial,
+    /// Picking up a session again after reconnecting, see [`Hello::session`]
+    Resume,
+    /// Several workspaces sharing the connection, see [`crate::mux`]. Only
+    /// announced by the peers that want to multiplex the connection.
+    Multiplex,
+    /// Compressing the large MessagePack frames, see
+    /// [`RpcCodec::ZstdMessagePack`]
+    Zstd,
+    /// A capability of a newer version of lapce
+    #[serde(other)]
+    Unknown,
+}
+
+/// The first message on a transport between lapce and a remote proxy.
+///
+/// It is always sent as a single JSON line, so that both sides can read it
+/// before anything else has been agreed on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    /// The version of lapce the peer is running
+    pub version: String,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    /// Sent by lapce to pick up the session it had before it lost the
+    /// connection, and by the proxy with the session the connection belongs
+    /// to. A proxy that can't keep sessions across connections sends none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionId>,
+    /// Sent by lapce to a proxy that only lets in the ones that know its
+    /// token, see [`check_token`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// How many messages of the session the side sending the hello received
+    /// before the connection was lost, so that the other side sends the
+    /// rest again, see [`crate::session::Unacked`]
+    #[serde(default)]
+    pub received: u64,
+}
+
+/// Sent by a proxy instead of its hello when it turns the connection down.
+#[derive(Debug, Serialize, Deserialize)]
+struct Refusal {
+    refused: String,
+}
+
+impl Hello {
+    /// The hello of lapce or a proxy running `version`, see
+    /// `lapce_core::meta::VERSION`, with all the capabilities that lapce-rpc
+    /// implements.
+    pub fn new(version: &str) -> Self {
+        Self {
+            version: version.to_string(),
+            capabilities: vec![
+                Capability::MessagePack,
+                Capability::Cancel,
+                Capability::Partial,
+                Capability::Resume,
+                Capability::Zstd,
+            ],
+            session: None,
+            token: None,
+            received: 0,
+        }
+    }
+
+    /// What a peer from before the handshake is taken to have sent: it
+    /// supports none of the capabilities.
+    fn legacy() -> Self {
+        Self {
+            version: String::new(),
+            capabilities: Vec::new(),
+            session: None,
+            token: None,
+            received: 0,
+        }
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Announce a capability that isn't announced by default.
+    pub fn with(mut self, capability: Capability) -> Self {
+        if !self.supports(capability) {
+            self.capabilities.push(capability);
+        }
+        self
+    }
+}
+
+/// What both sides agreed on in the handshake.
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    /// The hello the other side sent
+    pub peer: Hello,
+    pub codec: RpcCodec,
+    /// Whether the connection picked up the session lapce asked for
+    pub resumed: bool,
+    /// Whether the messages on the connection are tagged with the workspace
+    /// they belong to
+    pub multiplexed: bool,
+    /// The first message of a peer from before the handshake, which it sent
+    /// in place of a hello, see [`Handshake::reader`]
+    pending: Option<String>,
+    /// The version of lapce on this side
+    version: String,
+}
+
+impl Handshake {
+    fn new(local: &Hello, peer: Hello) -> Self {
+        let both =
+            |capability| local.supports(capability) && peer.supports(capability);
+        let codec = if !both(Capability::MessagePack) {
+            RpcCodec::Json
+        } else if both(Capability::Zstd) {
+            RpcCodec::ZstdMessagePack
+        } else {
+            RpcCodec::MessagePack
+        };
+        let resumed = local.session.is_some() && local.session == peer.session;
+        let multiplexed = both(Capability::Multiplex);
+        Self {
+            peer,
+            codec,
+            resumed,
+            multiplexed,
+            pending: None,
+            version: local.version.clone(),
+        }
+    }
+
+    /// The handshake with a peer from before the handshake, which only
+    /// speaks JSON.
+    pub fn legacy(local: &Hello) -> Self {
+        Self::new(local, Hello::legacy())
+    }
+
+    /// Whether the other side is from before the handshake.
+    pub fn is_legacy(&self) -> bool {
+        self.peer.version.is_empty()
+    }
+
+    /// The reader to read the messages of the other side from, after the
+    /// handshake. If the other side didn't send a hello, the message it sent
+    /// in its place is read first.
+    pub fn reader<R: BufRead>(
+        &mut self,
+        reader: R,
+    ) -> io::Chain<io::Cursor<Vec<u8>>, R> {
+        let pending = self.pending.take().unwrap_or_default();
+        io::Cursor::new(pending.into_bytes()).chain(reader)
+    }
+
+    /// Whether the other side understands `capability`.
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.peer.supports(capability)
+    }
+
+    /// Whether the other side runs the same version of lapce.
+    pub fn same_version(&self) -> bool {
+        self.peer.version == self.version
+    }
+}
+
+/// Run by the side that starts the connection: it sends its hello first and
+/// then reads the one of the other side.
+pub fn client_handshake<W: Write, R: BufRead>(
+    writer: &mut W,
+    reader: &mut R,
+    hello: Hello,
+) -> Result<Handshake> {
+    write_line(writer, &hello)?;
+    let peer = read_hello(reader)?;
+    Ok(Handshake::new(&hello, peer))
+}
+
+/// Like [`client_handshake`], but gives up on the other side if its hello
+/// doesn't arrive within `timeout`, in which case `None` is returned. The
+/// reader is still being read from then, so the connection can't be used
+/// anymore.
+pub fn client_handshake_timeout<W, R>(
+    writer: &mut W,
+    mut reader: R,
+    hello: Hello,
+    timeout: Duration,
+) -> Result<Option<(Handshake, R)>>
+where
+    W: Write,
+    R: 'static + BufRead + Send,
+{
+    write_line(writer, &hello)?;
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let peer = read_hello(&mut reader);
+        let _ = tx.send((peer, reader));
+    });
+    match rx.recv_timeout(timeout) {
+        Ok((peer, reader)) => Ok(Some((Handshake::new(&hello, peer?), reader))),
+        Err(RecvTimeoutError::Timeout) => Ok(None),
+        Err(RecvTimeoutError::Disconnected) => {
+            Err(anyhow!("the handshake was interrupted"))
+        }
+    }
+}
+
+/// Run by the side that accepts the connection: it reads the hello of the
+/// other side and answers with its own. `accept` is given the hello of the
+/// other side, and fills in the session the connection belongs to in the
+/// hello sent back, or turns the connection down with an error that is passed
+/// on to the other side.
+pub fn server_handshake<W: Write, R: BufRead>(
+    writer: &mut W,
+    reader: &mut R,
+    mut hello: Hello,
+    accept: impl FnOnce(&Hello, &mut Hello) -> Result<()>,
+) -> Result<Handshake> {
+    let line = read_line(reader)?;
+    // An older lapce starts right away with its first message, and doesn't
+    // expect a hello back
+    let legacy = !is_hello(&line);
+    let peer = if legacy {
+        Hello::legacy()
+    } else {
+        parse_hello(&line)?
+    };
+    if let Err(e) = accept(&peer, &mut hello) {
+        let _ = write_line(
+            writer,
+            &Refusal {
+                refused: e.to_string(),
+            },
+        );
+        return Err(e);
+    }
+    if legacy {
+        return Ok(Handshake {
+            pending: Some(line),
+            ..Handshake::new(&hello, peer)
+        });
+    }
+    write_line(writer, &hello)?;
+    Ok(Handshake::new(&hello, peer))
+}
+
+/// Check the token lapce sent in its hello against the one the proxy
+/// expects, if it expects one.
+pub fn check_token(expected: Option<&str>, hello: &Hello) -> Result<()> {
+    let expected = match expected {
+        Some(expected) => expected.as_bytes(),
+        None => return Ok(()),
+    };
+    let token = hello.token.as_deref().unwrap_or_default().as_bytes();
+    // Compares every byte, so that the time it takes doesn't tell how much
+    // of the token was right
+    let matches = token.len() == expected.len()
+        && token
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+    if matches {
+        Ok(())
+    } else if hello.token.is_none() {
+        Err(anyhow!("the proxy requires a token"))
+    } else {
+        Err(anyhow!("invalid token"))
+    }
+}
+
+fn write_line<W: Write, T: Serialize>(writer: &mut W, msg: &T) -> Result<()> {
+    let msg = format!("{}\n", serde_json::to_string(msg)?);
+    writer.write_all(msg.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_hello<R: BufRead>(reader: &mut R) -> Result<Hello> {
+    parse_hello(&read_line(reader)?)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String> {
+    let mut buf = String::new();
+    let len = reader.take(MAX_HELLO).read_line(&mut buf)?;
+    if len == 0 {
+        return Err(anyhow!("connection closed before the handshake"));
+    }
+    if len as u64 == MAX_HELLO && !buf.ends_with('\n') {
+        return Err(anyhow!("the hello is longer than {MAX_HELLO} bytes"));
+    }
+    Ok(buf)
+}
+
+/// Whether the line is a hello, rather than a message of a peer from before
+/// the handshake. Every hello has a version, and no message has one.
+fn is_hello(line: &str) -> bool {
+    serde_json::from_str::<Value>(line)
+        .map(|value| value.get("version").is_some())
+        .unwrap_or(true)
+}
+
+fn parse_hello(buf: &str) -> Result<Hello> {
+    if let Ok(refusal) = serde_json::from_str::<Refusal>(buf) {
+        return Err(anyhow!(
+            "the proxy refused the connection: {}",
+            refusal.refused
+        ));
+    }
+    serde_json::from_str(buf)
+        .map_err(|e| anyhow!("invalid handshake message {buf:?}: {e}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const VERSION: &str = "0.2.4";
+
+    #[test]
+    fn test_handshake_codec() {
+        let local = Hello::new(VERSION);
+        let json_only = Hello {
+            version: "0.1.0".to_string(),
+            capabilities: vec![Capability::Cancel],
+            session: None,
+            token: None,
+            received: 0,
+        };
+        let uncompressed = Hello {
+            capabilities: vec![Capability::MessagePack],
+            ..json_only.clone()
+        };
+        assert_eq!(
+            Handshake::new(&local, local.clone()).codec,
+            RpcCodec::ZstdMessagePack
+        );
+        assert_eq!(
+            Handshake::new(&local, uncompressed).codec,
+            RpcCodec::MessagePack
+        );
+        assert_eq!(
+            Handshake::new(&local, json_only.clone()).codec,
+            RpcCodec::Json
+        );
+        assert_eq!(Handshake::new(&json_only, local).codec, RpcCodec::Json);
+    }
+
+    #[test]
+    fn test_handshake_multiplex() {
+        let local = Hello::new(VERSION);
+        let multiplex = Hello::new(VERSION).with(Capability::Multiplex);
+        assert!(Handshake::new(&multiplex, multiplex.clone()).multiplexed);
+        assert!(!Handshake::new(&multiplex, local.clone()).multiplexed);
+        assert!(!Handshake::new(&local, multiplex).multiplexed);
+    }
+
+    #[test]
+    fn test_hello_from_newer_peer() {
+        let hello: Hello = serde_json::from_str(
+            r#"{"version":"9.9.9","capabilities":["cancel","teleport"],"extra":1}"#,
+        )
+        .unwrap();
+        assert!(hello.supports(Capability::Cancel));
+        assert!(!hello.supports(Capability::Partial));
+        assert_eq!(hello.capabilities[1], Capability::Unknown);
+        assert_eq!(hello.session, None);
+    }
+
+    #[test]
+    fn test_handshake_token() {
+        let expected = Some("secret");
+        let accept = |token: Option<&str>| {
+            let mut request = Vec::new();
+            let hello = Hello {
+                token: token.map(String::from),
+                ..Hello::new(VERSION)
+            };
+            let _ = client_handshake(
+                &mut request,
+                &mut std::io::Cursor::new(Vec::new()),
+                hello.clone(),
+            );
+            let mut reply = Vec::new();
+            let accepted = server_handshake(
+                &mut reply,
+                &mut std::io::Cursor::new(request),
+                Hello::new(VERSION),
+                |hello, _| check_token(expected, hello),
+            );
+            let answer = client_handshake(
+                &mut Vec::new(),
+                &mut std::io::Cursor::new(reply),
+                hello,
+            );
+            assert_eq!(accepted.is_ok(), answer.is_ok());
+            answer.map_err(|e| e.to_string())
+        };
+        assert!(accept(Some("secret")).is_ok());
+        assert_eq!(
+            accept(Some("secreT")).unwrap_err(),
+            "the proxy refused the connection: invalid token"
+        );
+        assert_eq!(
+            accept(None).unwrap_err(),
+            "the proxy refused the connection: the proxy requires a token"
+        );
+        assert!(check_token(None, &Hello::new(VERSION)).is_ok());
+    }
+
+    #[test]
+    fn test_hello_too_long() {
+        // A line that never ends
+        let handshake = server_handshake(
+            &mut Vec::new(),
+            &mut std::io::BufReader::new(std::io::repeat(b' ')),
+            Hello::new(VERSION),
+            |_, _| Ok(()),
+        );
+        assert_eq!(
+            handshake.unwrap_err().to_string(),
+            format!("the hello is longer than {MAX_HELLO} bytes")
+        );
+    }
+
+    #[test]
+    fn test_handshake_version() {
+        let local = Hello::new(VERSION);
+        assert!(Handshake::new(&local, Hello::new(VERSION)).same_version());
+        assert!(!Handshake::new(&local, Hello::new("0.2.3")).same_version());
+        assert!(!Handshake::new(&local, Hello::new("debug")).same_version());
+
+        // A proxy from before the handshake, which sends no hello
+        let handshake = Handshake::legacy(&local);
+        assert!(handshake.is_legacy());
+        assert!(!handshake.same_version());
+        assert_eq!(handshake.codec, RpcCodec::Json);
+        assert!(!handshake.multiplexed);
+    }
+
+    #[test]
+    fn test_handshake_legacy_client() {
+        // The first message of a lapce from before the handshake
+        let first = "{\"method\":\"shutdown\",\"params\":{}}\n";
+        let rest = "{\"method\":\"git_init\",\"params\":{}}\n";
+        let mut reply = Vec::new();
+        let mut reader = std::io::Cursor::new(format!("{first}{rest}"));
+        let mut handshake = server_handshake(
+            &mut reply,
+            &mut reader,
+            Hello::new(VERSION),
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        // Nothing is sent that it wouldn't understand
+        assert!(reply.is_empty());
+        assert!(handshake.is_legacy());
+        assert!(!handshake.same_version());
+        assert_eq!(handshake.codec, RpcCodec::Json);
+        assert!(!handshake.multiplexed);
+
+        let mut messages = String::new();
+        handshake
+            .reader(reader)
+            .read_to_string(&mut messages)
+            .unwrap();
+        assert_eq!(messages, format!("{first}{rest}"));
+    }
+
+    #[test]
+    fn test_handshake_timeout() {
+        /// A proxy from before the handshake, which never answers it
+        struct Silent;
+        impl Read for Silent {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                thread::sleep(Duration::from_secs(60));
+                Ok(0)
+            }
+        }
+
+        let handshake = client_handshake_timeout(
+            &mut Vec::new(),
+            io::BufReader::new(Silent),
+            Hello::new(VERSION),
+            Duration::from_millis(10),
+        )
+        .unwrap();
+        assert!(handshake.is_none());
+
+        let mut reply = Vec::new();
+        write_line(&mut reply, &Hello::new(VERSION)).unwrap();
+        let handshake = client_handshake_timeout(
+            &mut Vec::new(),
+            io::Cursor::new(reply),
+            Hello::new(VERSION),
+            HELLO_TIMEOUT,
+        )
+        .unwrap();
+        assert!(handshake.is_some());
+    }
+
+    #[test]
+    fn test_handshake_resume() {
+        let mut request = Vec::new();
+        let mut reply = Vec::new();
+        let resume = Hello {
+            session: Some("a".to_string()),
+            received: 3,
+            ..Hello::new(VERSION)
+        };
+        client_handshake(
+            &mut request,
+            &mut std::io::Cursor::new(Vec::new()),
+            resume.clone(),
+        )
+        .unwrap_err();
+
+        // The proxy still has the session
+        let handshake = server_handshake(
+            &mut reply,
+            &mut std::io::Cursor::new(request.clone()),
+            Hello::new(VERSION),
+            |peer, hello| {
+                hello.session = peer.session.clone();
+                hello.received = 5;
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(handshake.resumed);
+        assert_eq!(handshake.peer.received, 3);
+        let handshake = client_handshake(
+            &mut Vec::new(),
+            &mut std::io::Cursor::new(reply),
+            resume.clone(),
+        )
+        .unwrap();
+        assert!(handshake.resumed);
+        assert_eq!(handshake.peer.received, 5);
+
+        // The proxy started a new one
+        let mut reply = Vec::new();
+        let handshake = server_handshake(
+            &mut reply,
+            &mut std::io::Cursor::new(request),
+            Hello::new(VERSION),
+            |_, hello| {
+                hello.session = Some("b".to_string());
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(!handshake.resumed);
+        let handshake = client_handshake(
+            &mut Vec::new(),
+            &mut std::io::Cursor::new(reply),
+            resume,
+        )
+        .unwrap();
+        assert!(!handshake.resumed);
+        assert_eq!(handshake.peer.session.as_deref(), Some("b"));
+    }
+}
@@ -2,26 +2,56 @@
 pub mod core;
 pub mod counter;
 pub mod file;
+pub mod handshake;
+pub mod metrics;
+pub mod mux;
 mod parse;
 pub mod plugin;
 pub mod proxy;
+pub mod session;
 pub mod source_control;
 pub mod stdio;
 pub mod style;
 pub mod terminal;
+pub mod trace;
+pub mod transport;
 
 pub use parse::{Call, RequestId, RpcObject};
 use serde::{Deserialize, Serialize};
-pub use stdio::stdio_transport;
+pub use stdio::{stdio_transport, RpcCodec};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum RpcMessage<Req, Notif, Resp> {
     Request(RequestId, Req),
+    /// A chunk of the response to a request that is streamed back in parts.
+    /// The `Response` that follows it marks the end of the stream.
+    Partial(RequestId, Resp),
     Response(RequestId, Resp),
     Notification(Notif),
     Error(RequestId, RpcError),
+    /// The request with this id is no longer needed by the peer that sent it.
+    Cancel(RequestId),
+    /// The peer that sent it received this many of the messages sent to it in
+    /// the session, see [`session::Unacked`].
+    Ack(u64),
+    /// A request that was received but couldn't be decoded, like one with a
+    /// method only a newer peer knows. It's answered with the error, so that
+    /// the peer doesn't wait for it. Only ever read, never sent.
+    Invalid(RequestId, RpcError),
 }
 
+/// The error code a cancelled request is answered with, the same as the
+/// one used by the language server protocol.
+pub const REQUEST_CANCELLED: i64 = -32800;
+
+/// The error code a request that can't be decoded is answered with, the
+/// JSON-RPC code for a method that doesn't exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The error code a request that wasn't answered in time is failed with, one
+/// of the codes JSON-RPC leaves to implementations.
+pub const REQUEST_TIMED_OUT: i64 = -32000;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RpcError {
     pub code: i64,
@@ -0,0 +1,388 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Write as _},
+    fs, io,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+use parking_lot::{const_mutex, Mutex};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    trace::{TraceChannel, TraceEvent, TraceKind},
+    RequestId,
+};
+
+/// The upper bounds of the latency buckets, in milliseconds. The last bucket
+/// has no bound.
+pub const LATENCY_BUCKETS: [u64; 11] =
+    [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 10000];
+
+/// The metrics of the whole process, which the functions of this module use.
+static METRICS: Metrics = Metrics::new();
+
+/// The metrics of one rpc.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChannelMetrics {
+    /// Requests that are still waiting for a response
+    pub in_flight: usize,
+    /// Messages that are waiting to be handled, as of the last time a
+    /// message was queued up or taken
+    pub queued: usize,
+    pub messages: u64,
+    /// The size of the messages encoded as JSON, in bytes
+    pub bytes: u64,
+    pub methods: BTreeMap<String, MethodMetrics>,
+}
+
+/// The metrics of one method, counting both its requests and their
+/// responses.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MethodMetrics {
+    pub requests: u64,
+    pub notifications: u64,
+    /// Requests that failed or were cancelled
+    pub errors: u64,
+    pub bytes: u64,
+    /// How many requests were answered within each of the
+    /// [`LATENCY_BUCKETS`], plus one for the slower ones
+    pub latency: Vec<u64>,
+    /// The slowest answer, in milliseconds
+    pub max_latency: u64,
+}
+
+impl MethodMetrics {
+    fn record_latency(&mut self, millis: u64) {
+        if self.latency.is_empty() {
+            self.latency = vec![0; LATENCY_BUCKETS.len() + 1];
+        }
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| millis <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency[bucket] += 1;
+        self.max_latency = self.max_latency.max(millis);
+    }
+
+    /// The upper bound of the bucket that the given fraction of the answered
+    /// requests fall in, e.g. 0.9 for the 90th percentile. `None` for the
+    /// slowest bucket, and if no request was answered.
+    pub fn percentile(&self, fraction: f64) -> Option<u64> {
+        let total: u64 = self.latency.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = (total as f64 * fraction).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.latency.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BUCKETS.get(bucket).copied();
+            }
+        }
+        None
+    }
+}
+
+#[derive(Default)]
+struct Collector {
+    channels: HashMap<TraceChannel, ChannelMetrics>,
+    /// When the requests in flight were sent, and their method, by the
+    /// handler that sent them and their id
+    started: HashMap<(TraceChannel, u64, RequestId), (Instant, String)>,
+    /// How many messages each handler has queued up
+    queued: HashMap<(TraceChannel, u64), usize>,
+}
+
+/// Collects the metrics while it's enabled.
+pub struct Metrics {
+    enabled: AtomicBool,
+    collector: Mutex<Option<Collector>>,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            collector: const_mutex(None),
+        }
+    }
+
+    /// Start or stop collecting the metrics. Stopping drops the ones
+    /// collected so far.
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut collector = self.collector.lock();
+        if enabled {
+            collector.get_or_insert_with(Collector::default);
+        } else {
+            *collector = None;
+        }
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Count a message.
+    pub fn record(&self, event: &TraceEvent) {
+        if let Some(collector) = self.collector.lock().as_mut() {
+            collector.record(event);
+        }
+    }
+
+    /// The number of messages queued up by one of the handlers of the
+    /// channel, e.g. the `len()` of its channel.
+    pub fn record_queue(&self, channel: TraceChannel, source: u64, len: usize) {
+        if let Some(collector) = self.collector.lock().as_mut() {
+            collector.queued.insert((channel, source), len);
+            let queued = collector
+                .queued
+                .iter()
+                .filter(|((queue_channel, _), _)| *queue_channel == channel)
+                .map(|(_, len)| len)
+                .sum();
+            collector.channels.entry(channel).or_default().queued = queued;
+        }
+    }
+
+    /// The metrics collected so far.
+    pub fn snapshot(&self) -> Vec<(TraceChannel, ChannelMetrics)> {
+        let mut channels: Vec<_> = self
+            .collector
+            .lock()
+            .as_ref()
+            .map(|collector| {
+                collector
+                    .channels
+                    .iter()
+                    .map(|(channel, metrics)| (*channel, metrics.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        channels.sort_by_key(|(channel, _)| *channel as u8);
+        channels
+    }
+
+    /// The metrics collected so far, as a table to read or to attach to a
+    /// bug report.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for (channel, metrics) in self.snapshot() {
+            let _ = write_channel(&mut report, channel, &metrics);
+        }
+        if report.is_empty() {
+            report.push_str("No rpc messages yet\n");
+        }
+        report
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Start or stop collecting the metrics. Stopping drops the ones collected
+/// so far.
+pub fn set_enabled(enabled: bool) {
+    METRICS.set_enabled(enabled);
+}
+
+pub fn is_enabled() -> bool {
+    METRICS.is_enabled()
+}
+
+/// Count a message, see [`crate::trace::record`] which passes them on.
+pub(crate) fn record(event: &TraceEvent) {
+    METRICS.record(event);
+}
+
+/// Record how many messages a handler has queued up, if the metrics are
+/// enabled.
+pub fn record_queue(channel: TraceChannel, source: u64, len: usize) {
+    if is_enabled() {
+        METRICS.record_queue(channel, source, len);
+    }
+}
+
+impl Collector {
+    fn record(&mut self, event: &TraceEvent) {
+        let size = event.size as u64;
+        let key = event.id.map(|id| (event.channel, event.source, id));
+        let method = match (event.kind, key) {
+            (TraceKind::Request, Some(key)) => {
+                let method = event.method.clone().unwrap_or_default();
+                self.started.insert(key, (Instant::now(), method.clone()));
+                Some((method, None))
+            }
+            (TraceKind::Partial, Some(key)) => self
+                .started
+                .get(&key)
+                .map(|(_, method)| (method.clone(), None)),
+            (
+                TraceKind::Response | TraceKind::Error | TraceKind::Cancel,
+                Some(key),
+            ) => self
+                .started
+                .remove(&key)
+                .map(|(start, method)| (method, Some(start.elapsed()))),
+            _ => event.method.clone().map(|method| (method, None)),
+        };
+        let in_flight = self
+            .started
+            .keys()
+            .filter(|(channel, _, _)| *channel == event.channel)
+            .count();
+
+        let channel = self.channels.entry(event.channel).or_default();
+        channel.in_flight = in_flight;
+        channel.messages += 1;
+        channel.bytes += size;
+        if let Some((method, elapsed)) = method {
+            let metrics = channel.methods.entry(method).or_default();
+            metrics.bytes += size;
+            match event.kind {
+                TraceKind::Request => metrics.requests += 1,
+                TraceKind::Notification => metrics.notifications += 1,
+                TraceKind::Error | TraceKind::Cancel => metrics.errors += 1,
+                TraceKind::Response => {
+                    if let Some(elapsed) = elapsed {
+                        metrics.record_latency(elapsed.as_millis() as u64);
+                    }
+                }
+                TraceKind::Partial => {}
+            }
+        }
+    }
+}
+
+/// The metrics collected so far.
+pub fn snapshot() -> Vec<(TraceChannel, ChannelMetrics)> {
+    METRICS.snapshot()
+}
+
+/// The metrics collected so far, as a table to read or to attach to a bug
+/// report.
+pub fn report() -> String {
+    METRICS.report()
+}
+
+fn write_channel(
+    out: &mut String,
+    channel: TraceChannel,
+    metrics: &ChannelMetrics,
+) -> fmt::Result {
+    let millis = |bound: Option<u64>| match bound {
+        Some(bound) => format!("<={bound}ms"),
+        None => "-".to_string(),
+    };
+    writeln!(
+        out,
+        "{channel:?}: {} messages, {} bytes, {} requests in flight, {} queued\n",
+        metrics.messages, metrics.bytes, metrics.in_flight, metrics.queued
+    )?;
+    writeln!(
+        out,
+        "{:<40} {:>8} {:>8} {:>6} {:>12} {:>9} {:>9} {:>9} {:>9}",
+        "method",
+        "requests",
+        "notifs",
+        "errors",
+        "bytes",
+        "p50",
+        "p90",
+        "p99",
+        "max"
+    )?;
+    for (method, m) in &metrics.methods {
+        let max = if m.latency.is_empty() {
+            "-".to_string()
+        } else {
+            format!("{}ms", m.max_latency)
+        };
+        writeln!(
+            out,
+            "{:<40} {:>8} {:>8} {:>6} {:>12} {:>9} {:>9} {:>9} {:>9}",
+            method,
+            m.requests,
+            m.notifications,
+            m.errors,
+            m.bytes,
+            millis(m.percentile(0.5)),
+            millis(m.percentile(0.9)),
+            millis(m.percentile(0.99)),
+            max,
+        )?;
+    }
+    writeln!(out)
+}
+
+/// Write the [`report`] to a file.
+pub fn write_to(path: &Path) -> io::Result<()> {
+    fs::write(path, report())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(
+        source: u64,
+        kind: TraceKind,
+        id: Option<RequestId>,
+        method: &str,
+    ) -> TraceEvent {
+        TraceEvent {
+            time: 0,
+            channel: TraceChannel::Plugin,
+            source,
+            kind,
+            id,
+            method: (!method.is_empty()).then(|| method.to_string()),
+            size: 10,
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        // Metrics of their own, as the other tests record to the ones of the
+        // process
+        let metrics = Metrics::new();
+        metrics.record(&event(1, TraceKind::Request, Some(1), "hover"));
+        assert!(metrics.snapshot().is_empty());
+
+        metrics.set_enabled(true);
+        metrics.record(&event(1, TraceKind::Request, Some(1), "hover"));
+        metrics.record(&event(1, TraceKind::Request, Some(2), "hover"));
+        // Another plugin numbers its requests on its own
+        metrics.record(&event(2, TraceKind::Request, Some(1), "hover"));
+        metrics.record(&event(1, TraceKind::Notification, None, "did_change"));
+        metrics.record_queue(TraceChannel::Plugin, 1, 3);
+        metrics.record_queue(TraceChannel::Plugin, 2, 4);
+        let (_, snapshot) = metrics.snapshot().pop().unwrap();
+        assert_eq!(snapshot.in_flight, 3);
+        assert_eq!(snapshot.queued, 7);
+
+        metrics.record(&event(1, TraceKind::Response, Some(1), ""));
+        metrics.record(&event(1, TraceKind::Error, Some(2), ""));
+        metrics.record_queue(TraceChannel::Plugin, 1, 0);
+        let (channel, snapshot) = metrics.snapshot().pop().unwrap();
+        assert_eq!(channel, TraceChannel::Plugin);
+        assert_eq!(snapshot.in_flight, 1);
+        assert_eq!(snapshot.queued, 4);
+        assert_eq!(snapshot.messages, 6);
+        assert_eq!(snapshot.bytes, 60);
+        let hover = &snapshot.methods["hover"];
+        assert_eq!((hover.requests, hover.errors, hover.bytes), (3, 1, 50));
+        assert_eq!(hover.percentile(0.5), Some(1));
+        assert_eq!(snapshot.methods["did_change"].notifications, 1);
+        assert!(metrics.report().contains("hover"));
+
+        metrics.set_enabled(false);
+        assert!(metrics.snapshot().is_empty());
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use anyhow::Result;
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{RpcCodec, RpcMessage};
+
+/// Tells apart the workspaces that share a connection to a proxy. The ids are
+/// picked by lapce, which opens the workspaces.
+pub type WorkspaceId = u64;
+
+/// A message on a multiplexed transport, tagged with its workspace. `None`
+/// closes the workspace.
+pub type MuxFrame<M> = (WorkspaceId, Option<M>);
+
+/// Like [`crate::stdio_transport`], but for a transport that several
+/// workspaces share.
+pub fn mux_transport<W, R, Req1, Notif1, Resp1, Req2, Notif2, Resp2>(
+    codec: RpcCodec,
+    mut writer: W,
+    writer_receiver: Receiver<MuxFrame<RpcMessage<Req2, Notif2, Resp2>>>,
+    mut reader: R,
+    reader_sender: Sender<MuxFrame<RpcMessage<Req1, Notif1, Resp1>>>,
+) where
+    W: 'static + Write + Send,
+    R: 'static + BufRead + Send,
+    Req1: 'static + Serialize + DeserializeOwned + Send + Sync,
+    Notif1: 'static + Serialize + DeserializeOwned + Send + Sync,
+    Resp1: 'static + Serialize + DeserializeOwned + Send + Sync,
+    Req2: 'static + Serialize + DeserializeOwned + Send + Sync,
+    Notif2: 'static + Serialize + DeserializeOwned + Send + Sync,
+    Resp2: 'static + Serialize + DeserializeOwned + Send + Sync,
+{
+    thread::spawn(move || {
+        for (workspace, msg) in writer_receiver {
+            if codec.write_mux_msg(&mut writer, workspace, msg).is_err() {
+                return;
+            };
+        }
+    });
+    thread::spawn(move || -> Result<()> {
+        loop {
+            match codec.read_mux_msg(&mut reader) {
+                Ok(msg) => reader_sender.send(msg)?,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::InvalidData | io::ErrorKind::NotFound
+                    ) =>
+                {
+                    log::warn!("skipping unreadable rpc message: {e}");
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    });
+}
+
+/// Where the messages for each workspace go. `None` once the connection is
+/// closed.
+type Routes<In> = Arc<Mutex<Option<HashMap<WorkspaceId, Sender<In>>>>>;
+
+/// Splits one connection into a pair of channels per workspace, so that each
+/// workspace can be served as if it had a connection of its own.
+pub struct Multiplexer<Out, In> {
+    writer_tx: Sender<MuxFrame<Out>>,
+    routes: Routes<In>,
+    next_id: AtomicU64,
+}
+
+impl<Out: 'static + Send, In: 'static + Send> Multiplexer<Out, In> {
+    /// For the side that opens the workspaces.
+    pub fn new(
+        writer_tx: Sender<MuxFrame<Out>>,
+        reader_rx: Receiver<MuxFrame<In>>,
+    ) -> Self {
+        let routes = Arc::new(Mutex::new(Some(HashMap::new())));
+        spawn_router(writer_tx.clone(), reader_rx, routes.clone(), None);
+        Self {
+            writer_tx,
+            routes,
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// For the side that serves the workspaces: the channels of a workspace
+    /// come out of the returned receiver when its first message arrives. A
+    /// workspace that was closed isn't opened again by a late message.
+    pub fn accept(
+        writer_tx: Sender<MuxFrame<Out>>,
+        reader_rx: Receiver<MuxFrame<In>>,
+    ) -> Receiver<(Sender<Out>, Receiver<In>)> {
+        let (incoming_tx, incoming_rx) = crossbeam_channel::unbounded();
+        let routes = Arc::new(Mutex::new(Some(HashMap::new())));
+        spawn_router(writer_tx, reader_rx, routes, Some(incoming_tx));
+        incoming_rx
+    }
+
+    /// Open a new workspace on the connection. Dropping the sender closes
+    /// it, and the receiver ends when the peer closes it or the connection
+    /// is lost.
+    pub fn open(&self) -> (WorkspaceId, Sender<Out>, Receiver<In>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (in_tx, in_rx) = crossbeam_channel::unbounded();
+        let out_tx = route(id, in_tx, &self.writer_tx, &self.routes);
+        (id, out_tx, in_rx)
+    }
+
+    /// Close a workspace while its sender may still be around, which also
+    /// ends its receiver.
+    pub fn close(&self, id: WorkspaceId) {
+        if let Some(routes) = self.routes.lock().as_mut() {
+            routes.remove(&id);
+        }
+        let _ = self.writer_tx.send((id, None));
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.routes.lock().is_none()
+    }
+}
+
+/// Hand the messages from the peer to their workspaces, until the connection
+/// is closed.
+fn spawn_router<Out: 'static + Send, In: 'static + Send>(
+    writer_tx: Sender<MuxFrame<Out>>,
+    reader_rx: Receiver<MuxFrame<In>>,
+    routes: Routes<In>,
+    incoming: Option<Sender<(Sender<Out>, Receiver<In>)>>,
+) {
+    thread::spawn(move || {
+        // The workspaces that were opened or closed, so that the ones that
+        // are closed by now stay closed
+        let mut seen = HashSet::new();
+        for (id, msg) in reader_rx {
+            let msg = match msg {
+                Some(msg) => msg,
+                None => {
+                    if let Some(routes) = routes.lock().as_mut() {
+                        routes.remove(&id);
+                    }
+                    seen.insert(id);
+                    continue;
+                }
+            };
+            let tx = routes.lock().as_ref().and_then(|r| r.get(&id).cloned());
+            let tx = match (tx, incoming.as_ref()) {
+                (Some(tx), _) => tx,
+                (None, Some(incoming)) if seen.insert(id) => {
+                    let (in_tx, in_rx) = crossbeam_channel::unbounded();
+                    let out_tx = route(id, in_tx.clone(), &writer_tx, &routes);
+                    let _ = incoming.send((out_tx, in_rx));
+                    in_tx
+                }
+                (None, _) => {
+                    log::warn!("message for unknown workspace {id}");
+                    continue;
+                }
+            };
+            let _ = tx.send(msg);
+        }
+        // Ends the receivers of all the workspaces
+        routes.lock().take();
+    });
+}
+
+/// Register the workspace, and tag what it sends with its id until it's
+/// closed.
+fn route<Out: 'static + Send, In: 'static + Send>(
+    id: WorkspaceId,
+    in_tx: Sender<In>,
+    writer_tx: &Sender<MuxFrame<Out>>,
+    routes: &Routes<In>,
+) -> Sender<Out> {
+    // If the connection is closed already, `in_tx` is dropped right away
+    if let Some(routes) = routes.lock().as_mut() {
+        routes.insert(id, in_tx);
+    }
+    let (out_tx, out_rx) = crossbeam_channel::unbounded();
+    let writer_tx = writer_tx.clone();
+    let routes = routes.clone();
+    thread::spawn(move || {
+        for msg in out_rx {
+            if writer_tx.send((id, Some(msg))).is_err() {
+                break;
+            }
+        }
+        let _ = writer_tx.send((id, None));
+        if let Some(routes) = routes.lock().as_mut() {
+            routes.remove(&id);
+        }
+    });
+    out_tx
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crossbeam_channel::{RecvTimeoutError, TryRecvError};
+
+    use super::*;
+
+    type Msg = RpcMessage<(), String, u64>;
+
+    #[test]
+    fn test_mux_codec() {
+        for codec in [
+            RpcCodec::Json,
+            RpcCodec::MessagePack,
+            RpcCodec::ZstdMessagePack,
+        ] {
+            let mut buf = Vec::new();
+            codec
+                .write_mux_msg::<_, (), String, u64>(
+                    &mut buf,
+                    3,
+                    Some(RpcMessage::Response(1, 2)),
+                )
+                .unwrap();
+            codec
+                .write_mux_msg::<_, (), String, u64>(&mut buf, 4, None)
+                .unwrap();
+            let mut reader = io::Cursor::new(buf);
+            let (workspace, msg): MuxFrame<Msg> =
+                codec.read_mux_msg(&mut reader).unwrap();
+            assert_eq!(workspace, 3);
+            assert!(matches!(msg, Some(RpcMessage::Response(1, 2))));
+            let (workspace, msg): MuxFrame<Msg> =
+                codec.read_mux_msg(&mut reader).unwrap();
+            assert_eq!(workspace, 4);
+            assert!(msg.is_none());
+        }
+    }
+
+    #[test]
+    fn test_multiplexer() {
+        let timeout = Duration::from_secs(1);
+        // Two ends of a connection, wired back to back
+        let (client_tx, server_rx) = crossbeam_channel::unbounded();
+        let (server_tx, client_rx) = crossbeam_channel::unbounded();
+        let raw_client_tx = client_tx.clone();
+        let client = Multiplexer::<Msg, Msg>::new(client_tx, client_rx);
+        let incoming = Multiplexer::<Msg, Msg>::accept(server_tx, server_rx);
+
+        let (first, first_tx, first_rx) = client.open();
+        let (second, second_tx, second_rx) = client.open();
+        second_tx.send(RpcMessage::Cancel(2)).unwrap();
+        let (served_second_tx, served_second_rx) =
+            incoming.recv_timeout(timeout).unwrap();
+        assert!(matches!(
+            served_second_rx.recv_timeout(timeout),
+            Ok(RpcMessage::Cancel(2))
+        ));
+        first_tx.send(RpcMessage::Cancel(1)).unwrap();
+        let (served_first_tx, served_first_rx) =
+            incoming.recv_timeout(timeout).unwrap();
+        assert!(matches!(
+            served_first_rx.recv_timeout(timeout),
+            Ok(RpcMessage::Cancel(1))
+        ));
+
+        served_first_tx.send(RpcMessage::Cancel(11)).unwrap();
+        served_second_tx.send(RpcMessage::Cancel(12)).unwrap();
+        assert!(matches!(
+            first_rx.recv_timeout(timeout),
+            Ok(RpcMessage::Cancel(11))
+        ));
+        assert!(matches!(
+            second_rx.recv_timeout(timeout),
+            Ok(RpcMessage::Cancel(12))
+        ));
+
+        // Closing a workspace ends it on the other side, and leaves the
+        // others alone
+        drop(first_tx);
+        assert!(matches!(
+            served_first_rx.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+        second_tx.send(RpcMessage::Cancel(3)).unwrap();
+        assert!(matches!(
+            served_second_rx.recv_timeout(timeout),
+            Ok(RpcMessage::Cancel(3))
+        ));
+        client.close(second);
+        assert!(matches!(
+            served_second_rx.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+        assert!(matches!(
+            second_rx.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+        assert!(!client.is_closed());
+
+        // A late message for a closed workspace doesn't open it again, so
+        // the next workspace to come in is the one opened after it
+        raw_client_tx
+            .send((first, Some(RpcMessage::Cancel(4))))
+            .unwrap();
+        let (_, third_tx, _third_rx) = client.open();
+        third_tx.send(RpcMessage::Cancel(5)).unwrap();
+        let (_, served_third_rx) = incoming.recv_timeout(timeout).unwrap();
+        assert!(matches!(
+            served_third_rx.recv_timeout(timeout),
+            Ok(RpcMessage::Cancel(5))
+        ));
+        assert!(matches!(incoming.try_recv(), Err(TryRecvError::Empty)));
+    }
+}
@@ -21,6 +21,10 @@ pub fn get_id(&self) -> Option<RequestId> {
         self.0.get("id").and_then(Value::as_u64)
     }
 
+    pub fn get_method(&self) -> Option<&str> {
+        self.0.get("method").and_then(Value::as_str)
+    }
+
     pub fn is_response(&self) -> bool {
         self.0.get("id").is_some() && self.0.get("method").is_none()
     }
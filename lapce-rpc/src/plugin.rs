@@ -5,6 +5,11 @@
 
 use crate::counter::Counter;
 
+/// The version of the host functions and the `lapce/*` messages that wasm
+/// volts are written against. It's bumped when they change in a way that
+/// breaks the volts written for an older one.
+pub const PLUGIN_API_VERSION: u32 = 1;
+
 #[derive(Eq, PartialEq, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct PluginId(pub u64);
 
@@ -33,6 +38,10 @@ pub struct VoltInfo {
     pub repository: Option<String>,
     pub wasm: bool,
     pub updated_at_ts: i64,
+    /// The hex encoded SHA-256 of the package. The volts without one can't
+    /// be installed.
+    #[serde(default)]
+    pub checksum: Option<String>,
 }
 
 impl VoltInfo {
@@ -41,6 +50,13 @@ pub fn id(&self) -> String {
     }
 }
 
+/// A page of the volts found in the registry
+#[derive(Deserialize, Serialize)]
+pub struct PluginsInfo {
+    pub plugins: Vec<VoltInfo>,
+    pub total: usize,
+}
+
 #[derive(Deserialize, Clone, Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct VoltActivation {
@@ -70,6 +86,9 @@ pub struct VoltMetadata {
     pub dir: Option<PathBuf>,
     pub activation: Option<VoltActivation>,
     pub config: Option<HashMap<String, VoltConfig>>,
+    /// The [`PLUGIN_API_VERSION`] the volt needs at least
+    pub api_version: Option<u32>,
+    pub permissions: Option<VoltPermissions>,
 }
 
 impl VoltMetadata {
@@ -87,6 +106,95 @@ pub fn info(&self) -> VoltInfo {
             repository: self.repository.clone(),
             wasm: self.wasm.is_some(),
             updated_at_ts: 0,
+            checksum: None,
+        }
+    }
+
+    /// What the volt is allowed to do. The volts that don't declare their
+    /// permissions predate them, and can do what volts always could.
+    pub fn permissions(&self) -> VoltPermissions {
+        self.permissions
+            .clone()
+            .unwrap_or_else(VoltPermissions::legacy)
+    }
+}
+
+/// What a wasm volt can reach outside of its own directory, as declared in
+/// the `[permissions]` table of its volt.toml.
+#[derive(Deserialize, Clone, Debug, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct VoltPermissions {
+    /// Directories the volt can read and write, `${workspace}` being the
+    /// workspace folder. Only the ones in the workspace or in the volt's own
+    /// directory are given to it.
+    #[serde(default)]
+    pub fs: Vec<String>,
+    /// The urls the volt can make requests to, like `https://api.github.com`,
+    /// or `*` for any
+    #[serde(default)]
+    pub network: Vec<String>,
+    /// Whether the volt can run programs, including language servers
+    #[serde(default)]
+    pub process: bool,
+    /// The environment variables of the host the volt can read, by name, or
+    /// `*` for all of them
+    #[serde(default)]
+    pub env: Vec<String>,
+}
+
+impl VoltPermissions {
+    /// What the volts could do before they declared their permissions: reach
+    /// any host, run programs and read the whole environment of the host.
+    pub fn legacy() -> Self {
+        Self {
+            fs: Vec::new(),
+            network: vec!["*".to_string()],
+            process: true,
+            env: vec!["*".to_string()],
         }
     }
+
+    /// Whether the volt can read every environment variable of the host.
+    pub fn all_env(&self) -> bool {
+        self.env.iter().any(|name| name == "*")
+    }
+
+    /// What the permissions let a volt do, one line each, to show to the
+    /// user before they approve them.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for dir in &self.fs {
+            lines.push(format!("Read and write {dir}"));
+        }
+        for host in &self.network {
+            if host == "*" {
+                lines.push("Connect to any host".to_string());
+            } else {
+                lines.push(format!("Connect to {host}"));
+            }
+        }
+        if self.process {
+            lines.push("Run programs, including language servers".to_string());
+        }
+        if self.all_env() {
+            lines.push("Read all the environment variables".to_string());
+        } else if !self.env.is_empty() {
+            lines.push(format!(
+                "Read the environment variables {}",
+                self.env.join(", ")
+            ));
+        }
+        if lines.is_empty() {
+            lines.push("Nothing outside of its own directory".to_string());
+        }
+        lines
+    }
+}
+
+/// A command a volt added to the palette.
+#[derive(Deserialize, Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct PluginCommand {
+    /// What the volt is told to execute, unique within the volt
+    pub command: String,
+    pub title: String,
 }
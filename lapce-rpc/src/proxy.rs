@@ -1,15 +1,18 @@
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
     path::PathBuf,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
+    thread,
+    time::{Duration, Instant},
 };
 
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use indexmap::IndexMap;
-use lapce_xi_rope::RopeDelta;
+use lapce_xi_rope::{Delta, DeltaElement, Interval, Rope, RopeDelta, RopeInfo};
 use lsp_types::{
     request::GotoTypeDefinitionResponse, CodeAction, CodeActionResponse,
     CompletionItem, Diagnostic, DocumentSymbolResponse, GotoDefinitionResponse,
@@ -21,18 +24,22 @@
 
 use crate::{
     buffer::BufferId,
-    file::FileNodeItem,
-    plugin::{PluginId, VoltInfo, VoltMetadata},
+    core::CHANNEL_CAPACITY,
+    file::{FileNodeItem, PathObject},
+    metrics,
+    plugin::{PluginId, VoltInfo, VoltMetadata, VoltPermissions},
     source_control::FileDiff,
     style::SemanticStyles,
     terminal::TermId,
-    RequestId, RpcError, RpcMessage,
+    trace::{self, TraceChannel, TraceKind},
+    RequestId, RpcError, RpcMessage, REQUEST_CANCELLED, REQUEST_TIMED_OUT,
 };
 
 #[allow(clippy::large_enum_variant)]
 pub enum ProxyRpc {
     Request(RequestId, ProxyRequest),
     Notification(ProxyNotification),
+    Cancel(RequestId),
     Shutdown,
 }
 
@@ -150,6 +157,48 @@ pub enum ProxyRequest {
         to: PathBuf,
     },
 }
+impl ProxyRequest {
+    /// Whether the request is answered by a plugin or a language server,
+    /// rather than by the proxy itself.
+    pub fn is_plugin_request(&self) -> bool {
+        use ProxyRequest::*;
+        matches!(
+            self,
+            CompletionResolve { .. }
+                | CodeActionResolve { .. }
+                | GetHover { .. }
+                | GetSignature { .. }
+                | GetSelectionRange { .. }
+                | GetReferences { .. }
+                | GetDefinition { .. }
+                | GetTypeDefinition { .. }
+                | GetInlayHints { .. }
+                | GetSemanticTokens { .. }
+                | PrepareRename { .. }
+                | Rename { .. }
+                | GetCodeActions { .. }
+                | GetDocumentSymbols { .. }
+                | GetWorkspaceSymbols { .. }
+                | GetDocumentFormatting { .. }
+        )
+    }
+
+    /// Whether the request can be sent again after it timed out: it has no
+    /// side effects, and its answer is still wanted a while later.
+    pub fn is_retryable(&self) -> bool {
+        use ProxyRequest::*;
+        matches!(
+            self,
+            GetSelectionRange { .. }
+                | GetReferences { .. }
+                | GetInlayHints { .. }
+                | GetSemanticTokens { .. }
+                | GetDocumentSymbols { .. }
+                | GetWorkspaceSymbols { .. }
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "method", content = "params")]
@@ -166,7 +215,7 @@ pub enum ProxyNotification {
     },
     OpenPaths {
         folders: Vec<PathBuf>,
-        files: Vec<PathBuf>,
+        files: Vec<PathObject>,
     },
     Shutdown {},
     Completion {
@@ -180,9 +229,23 @@ pub enum ProxyNotification {
         path: PathBuf,
         position: Position,
     },
+    /// The buffer changed from `base_rev` to `rev`. The revisions are one
+    /// apart, unless consecutive updates were coalesced. A proxy whose
+    /// buffer isn't at `base_rev` asks lapce for the whole content with
+    /// [`crate::core::CoreNotification::ResyncBuffer`].
     Update {
         path: PathBuf,
         delta: RopeDelta,
+        base_rev: u64,
+        rev: u64,
+    },
+    /// Open a buffer with the content lapce has for it, when lapce starts
+    /// a new session after it lost the connection to the proxy, or when the
+    /// proxy's copy of the buffer got out of sync
+    ReopenBuffer {
+        buffer_id: BufferId,
+        path: PathBuf,
+        content: String,
         rev: u64,
     },
     UpdatePluginConfigs {
@@ -208,6 +271,15 @@ pub enum ProxyNotification {
     EnableVolt {
         volt: VoltInfo,
     },
+    /// The user approved what the volt asks for, which lets it run
+    ApproveVoltPermissions {
+        volt: VoltInfo,
+        permissions: VoltPermissions,
+    },
+    ExecutePluginCommand {
+        volt_id: String,
+        command: String,
+    },
     GitCommit {
         message: String,
         diffs: Vec<FileDiff>,
@@ -243,6 +315,7 @@ pub enum ProxyResponse {
     },
     NewBufferResponse {
         content: String,
+        rev: u64,
     },
     BufferHeadResponse {
         version: String,
@@ -314,6 +387,23 @@ pub enum ProxyResponse {
     SaveResponse {},
 }
 
+impl ProxyResponse {
+    /// Combine a chunk of a streamed response with the part that follows it,
+    /// for peers that can't receive the chunks on their own.
+    pub fn merge(self, next: ProxyResponse) -> ProxyResponse {
+        match (self, next) {
+            (
+                ProxyResponse::GlobalSearchResponse { mut matches },
+                ProxyResponse::GlobalSearchResponse { matches: next },
+            ) => {
+                matches.extend(next);
+                ProxyResponse::GlobalSearchResponse { matches }
+            }
+            (_, next) => next,
+        }
+    }
+}
+
 pub type ProxyMessage = RpcMessage<ProxyRequest, ProxyNotification, ProxyResponse>;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -325,15 +415,24 @@ pub trait ProxyCallback: Send + FnOnce(Result<ProxyResponse, RpcError>) {}
 
 impl<F: Send + FnOnce(Result<ProxyResponse, RpcError>)> ProxyCallback for F {}
 
+pub trait ProxyPartialCallback: Send + FnMut(ProxyResponse) {}
+
+impl<F: Send + FnMut(ProxyResponse)> ProxyPartialCallback for F {}
+
 enum ResponseHandler {
     Callback(Box<dyn ProxyCallback>),
     Chan(Sender<Result<ProxyResponse, RpcError>>),
+    Stream {
+        partial: Arc<Mutex<Box<dyn ProxyPartialCallback>>>,
+        done: Box<dyn ProxyCallback>,
+    },
 }
 
 impl ResponseHandler {
     fn invoke(self, result: Result<ProxyResponse, RpcError>) {
         match self {
             ResponseHandler::Callback(f) => f(result),
+            ResponseHandler::Stream { done, .. } => done(result),
             ResponseHandler::Chan(tx) => {
                 let _ = tx.send(result);
             }
@@ -341,9 +440,33 @@ fn invoke(self, result: Result<ProxyResponse, RpcError>) {
     }
 }
 
+struct PendingRequest {
+    handler: ResponseHandler,
+    /// The id the request was made with, which it's still cancelled by after
+    /// it was sent again under a new one
+    first_id: RequestId,
+    /// For the requests that can time out, the request and how many more
+    /// times it may be sent again once it did
+    timeout: Option<(ProxyRequest, usize)>,
+}
+
+/// How long the requests answered by plugins and language servers may take,
+/// see [`ProxyRpcHandler::set_timeouts`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RequestTimeouts {
+    /// `None` waits for the answers forever
+    pub timeout: Option<Duration>,
+    /// How many times a request that [`ProxyRequest::is_retryable`] is sent
+    /// again after it timed out, before it fails
+    pub retries: usize,
+}
+
+pub type TimeoutCallback = Arc<dyn Fn(&ProxyRequest) + Send + Sync>;
+
 pub trait ProxyHandler {
     fn handle_notification(&mut self, rpc: ProxyNotification);
     fn handle_request(&mut self, id: RequestId, rpc: ProxyRequest);
+    fn handle_cancel(&mut self, id: RequestId);
 }
 
 #[derive(Clone)]
@@ -351,20 +474,161 @@ pub struct ProxyRpcHandler {
     tx: Sender<ProxyRpc>,
     rx: Receiver<ProxyRpc>,
     id: Arc<AtomicU64>,
-    pending: Arc<Mutex<HashMap<u64, ResponseHandler>>>,
+    pending: Arc<Mutex<HashMap<u64, PendingRequest>>>,
+    /// The ids of the requests that were sent again after they timed out,
+    /// by the ids they were made with. Only changed while `pending` is
+    /// locked.
+    retried: Arc<Mutex<HashMap<RequestId, RequestId>>>,
+    timeouts: Arc<Mutex<RequestTimeouts>>,
+    on_timeout: Arc<Mutex<Option<TimeoutCallback>>>,
+    /// Where the deadlines of the requests that can time out are sent
+    deadlines: Sender<(Instant, RequestId)>,
+    /// Tells the messages of this handler apart in the trace and the metrics
+    trace_source: u64,
 }
 
 impl ProxyRpcHandler {
     pub fn new() -> Self {
-        let (tx, rx) = crossbeam_channel::unbounded();
-        Self {
+        let (tx, rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+        let (deadlines, deadlines_rx) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+        let handler = Self {
             tx,
             rx,
             id: Arc::new(AtomicU64::new(0)),
             pending: Arc::new(Mutex::new(HashMap::new())),
+            retried: Arc::new(Mutex::new(HashMap::new())),
+            timeouts: Arc::new(Mutex::new(RequestTimeouts::default())),
+            on_timeout: Arc::new(Mutex::new(None)),
+            deadlines,
+            trace_source: trace::next_source(),
+        };
+        // Holding on to the sender would keep the deadlines open forever
+        let local_handler = Self {
+            deadlines: crossbeam_channel::unbounded().0,
+            ..handler.clone()
+        };
+        thread::spawn(move || local_handler.watch_deadlines(deadlines_rx));
+        handler
+    }
+
+    /// Make the requests answered by plugins and language servers fail, or
+    /// be sent again, once they took longer than `timeouts` allow. Only
+    /// applies to the requests made from now on.
+    pub fn set_timeouts(&self, timeouts: RequestTimeouts) {
+        *self.timeouts.lock() = timeouts;
+    }
+
+    /// Called with every request that failed because it timed out.
+    pub fn on_timeout(&self, f: impl Fn(&ProxyRequest) + Send + Sync + 'static) {
+        *self.on_timeout.lock() = Some(Arc::new(f));
+    }
+
+    /// Time the requests out as their deadlines pass. Ends once every
+    /// handler sharing the deadlines is dropped.
+    fn watch_deadlines(&self, deadlines_rx: Receiver<(Instant, RequestId)>) {
+        let mut deadlines = BinaryHeap::new();
+        loop {
+            let next = match deadlines.peek() {
+                Some(Reverse((deadline, _))) => {
+                    deadlines_rx.recv_deadline(*deadline)
+                }
+                None => deadlines_rx
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match next {
+                Ok(deadline) => deadlines.push(Reverse(deadline)),
+                Err(RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    while let Some(Reverse((deadline, id))) = deadlines.peek() {
+                        if *deadline > now {
+                            break;
+                        }
+                        let id = *id;
+                        deadlines.pop();
+                        if let Some(retry) = self.timed_out(id) {
+                            deadlines.push(Reverse(retry));
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    /// The request with the given id wasn't answered in time. It's sent
+    /// again under a new id if it can be retried, returning its new
+    /// deadline, and failed otherwise.
+    fn timed_out(&self, id: RequestId) -> Option<(Instant, RequestId)> {
+        // Locked until the request is pending again, so that cancelling it in
+        // the meantime finds it under one id or the other. It's sent once
+        // unlocked, as waiting for room in the queue while holding the lock
+        // would hold up the responses.
+        let mut pending_requests = self.pending.lock();
+        let pending = pending_requests.remove(&id)?;
+        let first_id = pending.first_id;
+        let (request, retries) = pending.timeout?;
+        // The other side may still be working on it
+        trace::record(
+            TraceChannel::Proxy,
+            self.trace_source,
+            TraceKind::Cancel,
+            Some(id),
+            &(),
+        );
+        let timeout = self.timeouts.lock().timeout;
+        match timeout {
+            Some(timeout) if retries > 0 => {
+                let new_id = self.id.fetch_add(1, Ordering::Relaxed);
+                trace::record(
+                    TraceChannel::Proxy,
+                    self.trace_source,
+                    TraceKind::Request,
+                    Some(new_id),
+                    &request,
+                );
+                pending_requests.insert(
+                    new_id,
+                    PendingRequest {
+                        handler: pending.handler,
+                        first_id,
+                        timeout: Some((request.clone(), retries - 1)),
+                    },
+                );
+                self.retried.lock().insert(first_id, new_id);
+                drop(pending_requests);
+                self.send(ProxyRpc::Cancel(id));
+                self.send(ProxyRpc::Request(new_id, request));
+                Some((Instant::now() + timeout, new_id))
+            }
+            _ => {
+                self.retried.lock().remove(&first_id);
+                drop(pending_requests);
+                self.send(ProxyRpc::Cancel(id));
+                pending.handler.invoke(Err(RpcError {
+                    code: REQUEST_TIMED_OUT,
+                    message: "request timed out".to_string(),
+                }));
+                let on_timeout = self.on_timeout.lock().clone();
+                if let Some(f) = on_timeout {
+                    f(&request);
+                }
+                None
+            }
         }
     }
 
+    fn send(&self, msg: ProxyRpc) {
+        let _ = self.tx.send(msg);
+        self.record_queue();
+    }
+
+    /// Record how many messages are queued up for the proxy in the metrics,
+    /// for those taking them from [`Self::rx`].
+    pub fn record_queue(&self) {
+        metrics::record_queue(TraceChannel::Proxy, self.trace_source, self.rx.len());
+    }
+
     pub fn rx(&self) -> &Receiver<ProxyRpc> {
         &self.rx
     }
@@ -374,32 +638,85 @@ pub fn mainloop<H>(&self, handler: &mut H)
         H: ProxyHandler,
     {
         use ProxyRpc::*;
-        for msg in &self.rx {
-            match msg {
-                Request(id, request) => {
-                    handler.handle_request(id, request);
-                }
-                Notification(notification) => {
-                    handler.handle_notification(notification);
-                }
-                Shutdown => {
-                    return;
+        while let Ok(msg) = self.rx.recv() {
+            // Take everything that's queued up already, so that a burst of
+            // edits is handled at once
+            let batch = std::iter::once(msg).chain(self.rx.try_iter()).collect();
+            self.record_queue();
+            for msg in coalesce(batch) {
+                match msg {
+                    Request(id, request) => {
+                        handler.handle_request(id, request);
+                    }
+                    Notification(notification) => {
+                        let shutdown =
+                            matches!(notification, ProxyNotification::Shutdown {});
+                        handler.handle_notification(notification);
+                        // The handler is done then, and it can't queue up
+                        // the shutdown for itself, as the queue may be full
+                        if shutdown {
+                            return;
+                        }
+                    }
+                    Cancel(id) => {
+                        handler.handle_cancel(id);
+                    }
+                    Shutdown => {
+                        return;
+                    }
                 }
             }
         }
     }
 
-    fn request_common(&self, request: ProxyRequest, rh: ResponseHandler) {
+    fn request_common(
+        &self,
+        request: ProxyRequest,
+        rh: ResponseHandler,
+    ) -> RequestId {
         let id = self.id.fetch_add(1, Ordering::Relaxed);
 
-        self.pending.lock().insert(id, rh);
+        let timeouts = *self.timeouts.lock();
+        let timeout = timeouts
+            .timeout
+            .filter(|_| request.is_plugin_request())
+            .map(|timeout| {
+                let retries = if request.is_retryable() {
+                    timeouts.retries
+                } else {
+                    0
+                };
+                (timeout, (request.clone(), retries))
+            });
+        let deadline = timeout
+            .as_ref()
+            .map(|(timeout, _)| (Instant::now() + *timeout, id));
+        self.pending.lock().insert(
+            id,
+            PendingRequest {
+                handler: rh,
+                first_id: id,
+                timeout: timeout.map(|(_, timeout)| timeout),
+            },
+        );
 
-        let _ = self.tx.send(ProxyRpc::Request(id, request));
+        trace::record(
+            TraceChannel::Proxy,
+            self.trace_source,
+            TraceKind::Request,
+            Some(id),
+            &request,
+        );
+        self.send(ProxyRpc::Request(id, request));
+        if let Some(deadline) = deadline {
+            let _ = self.deadlines.send(deadline);
+        }
+        id
     }
 
     fn request(&self, request: ProxyRequest) -> Result<ProxyResponse, RpcError> {
         let (tx, rx) = crossbeam_channel::bounded(1);
-        self.request_common(request, ResponseHandler::Chan(tx));
+        let _ = self.request_common(request, ResponseHandler::Chan(tx));
         rx.recv().unwrap_or_else(|_| {
             Err(RpcError {
                 code: 0,
@@ -412,23 +729,121 @@ pub fn request_async(
         &self,
         request: ProxyRequest,
         f: impl ProxyCallback + 'static,
-    ) {
+    ) -> RequestId {
         self.request_common(request, ResponseHandler::Callback(Box::new(f)))
     }
 
+    /// Make a request whose response may be streamed back in chunks.
+    /// `partial` is called with every chunk as it arrives, and `done` with
+    /// the final response once the stream is complete.
+    pub fn request_stream(
+        &self,
+        request: ProxyRequest,
+        partial: impl ProxyPartialCallback + 'static,
+        done: impl ProxyCallback + 'static,
+    ) -> RequestId {
+        self.request_common(
+            request,
+            ResponseHandler::Stream {
+                partial: Arc::new(Mutex::new(Box::new(partial))),
+                done: Box::new(done),
+            },
+        )
+    }
+
+    /// Send a chunk of the response to a request made with
+    /// [`Self::request_stream`]. Chunks for any other request are dropped.
+    pub fn handle_partial(&self, id: RequestId, chunk: ProxyResponse) {
+        trace::record(
+            TraceChannel::Proxy,
+            self.trace_source,
+            TraceKind::Partial,
+            Some(id),
+            &chunk,
+        );
+        let partial = match self.pending.lock().get(&id) {
+            Some(PendingRequest {
+                handler: ResponseHandler::Stream { partial, .. },
+                ..
+            }) => Some(partial.clone()),
+            _ => None,
+        };
+        if let Some(partial) = partial {
+            (partial.lock())(chunk);
+        }
+    }
+
     pub fn handle_response(
         &self,
         id: RequestId,
         result: Result<ProxyResponse, RpcError>,
     ) {
-        let handler = { self.pending.lock().remove(&id) };
-        if let Some(handler) = handler {
-            handler.invoke(result);
+        match &result {
+            Ok(resp) => trace::record(
+                TraceChannel::Proxy,
+                self.trace_source,
+                TraceKind::Response,
+                Some(id),
+                resp,
+            ),
+            Err(err) => trace::record(
+                TraceChannel::Proxy,
+                self.trace_source,
+                TraceKind::Error,
+                Some(id),
+                err,
+            ),
+        }
+        // Only the id the request is sent under now counts, as the attempts
+        // before it were cancelled and may still be answered with an error
+        let pending = {
+            let mut pending_requests = self.pending.lock();
+            let pending = pending_requests.remove(&id);
+            if let Some(pending) = pending.as_ref() {
+                self.retried.lock().remove(&pending.first_id);
+            }
+            pending
+        };
+        if let Some(pending) = pending {
+            pending.handler.invoke(result);
+        }
+    }
+
+    /// Give up on a request that is still in flight. Its handler gets a
+    /// cancellation error, and the handler on the other side is told to stop
+    /// working on it. A request that was sent again after it timed out is
+    /// still cancelled by the id it was made with.
+    pub fn cancel(&self, id: RequestId) {
+        let pending = {
+            let mut pending_requests = self.pending.lock();
+            let id = self.retried.lock().remove(&id).unwrap_or(id);
+            pending_requests.remove(&id).map(|pending| (id, pending))
+        };
+        if let Some((id, pending)) = pending {
+            pending.handler.invoke(Err(RpcError {
+                code: REQUEST_CANCELLED,
+                message: "request cancelled".to_string(),
+            }));
+            trace::record(
+                TraceChannel::Proxy,
+                self.trace_source,
+                TraceKind::Cancel,
+                Some(id),
+                &(),
+            );
+            self.send(ProxyRpc::Cancel(id));
         }
     }
 
     pub fn notification(&self, notification: ProxyNotification) {
-        let _ = self.tx.send(ProxyRpc::Notification(notification));
+        trace::record(
+            TraceChannel::Proxy,
+            self.trace_source,
+            TraceKind::Notification,
+            None,
+            &notification,
+        );
+        self.send(ProxyRpc::Notification(notification));
     }
 
     pub fn git_init(&self) {
@@ -463,9 +878,27 @@ pub fn enable_volt(&self, volt: VoltInfo) {
         self.notification(ProxyNotification::EnableVolt { volt });
     }
 
+    pub fn approve_volt_permissions(
+        &self,
+        volt: VoltInfo,
+        permissions: VoltPermissions,
+    ) {
+        self.notification(ProxyNotification::ApproveVoltPermissions {
+            volt,
+            permissions,
+        });
+    }
+
+    pub fn execute_plugin_command(&self, volt_id: String, command: String) {
+        self.notification(ProxyNotification::ExecutePluginCommand {
+            volt_id,
+            command,
+        });
+    }
+
     pub fn shutdown(&self) {
         self.notification(ProxyNotification::Shutdown {});
-        let _ = self.tx.send(ProxyRpc::Shutdown);
+        self.send(ProxyRpc::Shutdown);
     }
 
     pub fn initialize(
@@ -603,19 +1036,23 @@ pub fn save_buffer_as(
         );
     }
 
+    /// The matches are streamed back as `GlobalSearchResponse`s, to `partial`
+    /// while the search is running and to `done` for the last of them.
     pub fn global_search(
         &self,
         pattern: String,
         case_sensitive: bool,
-        f: impl ProxyCallback + 'static,
-    ) {
-        self.request_async(
+        partial: impl ProxyPartialCallback + 'static,
+        done: impl ProxyCallback + 'static,
+    ) -> RequestId {
+        self.request_stream(
             ProxyRequest::GlobalSearch {
                 pattern,
                 case_sensitive,
             },
-            f,
-        );
+            partial,
+            done,
+        )
     }
 
     pub fn save(&self, rev: u64, path: PathBuf, f: impl ProxyCallback + 'static) {
@@ -675,7 +1112,7 @@ pub fn get_hover(
         path: PathBuf,
         position: Position,
         f: impl ProxyCallback + 'static,
-    ) {
+    ) -> RequestId {
         self.request_async(
             ProxyRequest::GetHover {
                 request_id,
@@ -683,7 +1120,7 @@ pub fn get_hover(
                 position,
             },
             f,
-        );
+        )
     }
 
     pub fn get_definition(
@@ -735,7 +1172,7 @@ pub fn get_code_actions(
         position: Position,
         diagnostics: Vec<Diagnostic>,
         f: impl ProxyCallback + 'static,
-    ) {
+    ) -> RequestId {
         self.request_async(
             ProxyRequest::GetCodeActions {
                 path,
@@ -743,7 +1180,7 @@ pub fn get_code_actions(
                 diagnostics,
             },
             f,
-        );
+        )
     }
 
     pub fn get_document_formatting(
@@ -816,8 +1253,45 @@ pub fn get_inlay_hints(&self, path: PathBuf, f: impl ProxyCallback + 'static) {
         self.request_async(ProxyRequest::GetInlayHints { path }, f);
     }
 
+    /// Send an edit of the buffer, which takes it from `rev - 1` to `rev`.
     pub fn update(&self, path: PathBuf, delta: RopeDelta, rev: u64) {
-        self.notification(ProxyNotification::Update { path, delta, rev });
+        self.notification(ProxyNotification::Update {
+            path,
+            delta,
+            base_rev: rev.saturating_sub(1),
+            rev,
+        });
+    }
+
+    pub fn reopen_buffer(
+        &self,
+        buffer_id: BufferId,
+        path: PathBuf,
+        content: String,
+        rev: u64,
+    ) {
+        self.notification(ProxyNotification::ReopenBuffer {
+            buffer_id,
+            path,
+            content,
+            rev,
+        });
+    }
+
+    /// Fail every request that is still waiting for a response, for when the
+    /// other side lost them.
+    pub fn fail_pending(&self, message: &str) {
+        let pending: Vec<_> = {
+            let mut pending_requests = self.pending.lock();
+            self.retried.lock().clear();
+            pending_requests.drain().collect()
+        };
+        for (_, pending) in pending {
+            pending.handler.invoke(Err(RpcError {
+                code: 0,
+                message: message.to_string(),
+            }));
+        }
     }
 
     pub fn update_plugin_configs(
@@ -850,3 +1324,307 @@ fn default() -> Self {
         Self::new()
     }
 }
+
+/// Drop the completion requests that a later one for the same document
+/// replaces, after which the consecutive updates of a document are merged
+/// into one.
+fn coalesce(batch: Vec<ProxyRpc>) -> Vec<ProxyRpc> {
+    let mut latest_completion = HashMap::new();
+    for (i, msg) in batch.iter().enumerate() {
+        if let ProxyRpc::Notification(ProxyNotification::Completion {
+            path, ..
+        }) = msg
+        {
+            latest_completion.insert(path.clone(), i);
+        }
+    }
+
+    let mut coalesced: Vec<ProxyRpc> = Vec::with_capacity(batch.len());
+    for (i, msg) in batch.into_iter().enumerate() {
+        match msg {
+            ProxyRpc::Notification(ProxyNotification::Completion {
+                ref path,
+                ..
+            }) if latest_completion.get(path) != Some(&i) => {}
+            ProxyRpc::Notification(ProxyNotification::Update {
+                path,
+                delta,
+                base_rev,
+                rev,
+            }) => match coalesced.last_mut() {
+                // Merged only into an update it follows right after, a gap
+                // means that an update in between was lost
+                Some(ProxyRpc::Notification(ProxyNotification::Update {
+                    path: last_path,
+                    delta: last_delta,
+                    rev: last_rev,
+                    ..
+                })) if *last_path == path
+                    && *last_rev == base_rev
+                    && last_delta.new_document_len() == delta.base_len =>
+                {
+                    *last_delta = compose(last_delta, &delta);
+                    *last_rev = rev;
+                }
+                _ => coalesced.push(ProxyRpc::Notification(
+                    ProxyNotification::Update {
+                        path,
+                        delta,
+                        base_rev,
+                        rev,
+                    },
+                )),
+            },
+            msg => coalesced.push(msg),
+        }
+    }
+    coalesced
+}
+
+/// The delta that does what `first` and then `second` do.
+fn compose(first: &RopeDelta, second: &RopeDelta) -> RopeDelta {
+    let mut els: Vec<DeltaElement<RopeInfo>> = Vec::new();
+    let mut push = |el: DeltaElement<RopeInfo>| match (els.last_mut(), el) {
+        (Some(DeltaElement::Copy(_, end)), DeltaElement::Copy(start, new_end))
+            if *end == start =>
+        {
+            *end = new_end;
+        }
+        (Some(DeltaElement::Insert(text)), DeltaElement::Insert(new_text)) => {
+            *text = Rope::concat(text.clone(), new_text);
+        }
+        (_, el) => els.push(el),
+    };
+    for el in &second.els {
+        let (start, end) = match el {
+            DeltaElement::Copy(start, end) => (*start, *end),
+            DeltaElement::Insert(text) => {
+                if !text.is_empty() {
+                    push(DeltaElement::Insert(text.clone()));
+                }
+                continue;
+            }
+        };
+        // What `second` keeps is made of what `first` kept and inserted
+        let mut offset = 0;
+        for first_el in &first.els {
+            let len = match first_el {
+                DeltaElement::Copy(start, end) => end - start,
+                DeltaElement::Insert(text) => text.len(),
+            };
+            let (from, to) = (start.max(offset), end.min(offset + len));
+            if from < to {
+                push(match first_el {
+                    DeltaElement::Copy(copy_start, _) => DeltaElement::Copy(
+                        copy_start + from - offset,
+                        copy_start + to - offset,
+                    ),
+                    DeltaElement::Insert(text) => DeltaElement::Insert(
+                        text.subseq(Interval::new(from - offset, to - offset)),
+                    ),
+                });
+            }
+            offset += len;
+            if offset >= end {
+                break;
+            }
+        }
+    }
+    Delta {
+        els,
+        base_len: first.base_len,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_request_timeout() {
+        let recv_timeout = Duration::from_secs(1);
+        let handler = ProxyRpcHandler::new();
+        handler.set_timeouts(RequestTimeouts {
+            timeout: Some(Duration::from_millis(50)),
+            retries: 1,
+        });
+        let (timed_out_tx, timed_out_rx) = crossbeam_channel::unbounded();
+        handler.on_timeout(move |request| {
+            let _ = timed_out_tx.send(request.clone());
+        });
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        handler.get_document_symbols(PathBuf::from("a.rs"), move |result| {
+            let _ = tx.send(result);
+        });
+
+        assert!(matches!(
+            handler.rx().recv_timeout(recv_timeout),
+            Ok(ProxyRpc::Request(
+                0,
+                ProxyRequest::GetDocumentSymbols { .. }
+            ))
+        ));
+        // Not answered in time, so it's sent again
+        assert!(matches!(
+            handler.rx().recv_timeout(recv_timeout),
+            Ok(ProxyRpc::Cancel(0))
+        ));
+        assert!(matches!(
+            handler.rx().recv_timeout(recv_timeout),
+            Ok(ProxyRpc::Request(
+                1,
+                ProxyRequest::GetDocumentSymbols { .. }
+            ))
+        ));
+        // And fails once it's out of retries
+        assert!(matches!(
+            handler.rx().recv_timeout(recv_timeout),
+            Ok(ProxyRpc::Cancel(1))
+        ));
+        let err = rx.recv_timeout(recv_timeout).unwrap().unwrap_err();
+        assert_eq!(err.code, REQUEST_TIMED_OUT);
+        assert!(matches!(
+            timed_out_rx.recv_timeout(recv_timeout),
+            Ok(ProxyRequest::GetDocumentSymbols { .. })
+        ));
+    }
+
+    #[test]
+    fn test_cancel_retried_request() {
+        let recv_timeout = Duration::from_secs(1);
+        let handler = ProxyRpcHandler::new();
+        handler.set_timeouts(RequestTimeouts {
+            timeout: Some(Duration::from_millis(100)),
+            retries: 1,
+        });
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let id = handler.request_async(
+            ProxyRequest::GetDocumentSymbols {
+                path: PathBuf::from("a.rs"),
+            },
+            move |result| {
+                let _ = tx.send(result);
+            },
+        );
+        assert!(matches!(
+            handler.rx().recv_timeout(recv_timeout),
+            Ok(ProxyRpc::Request(0, _))
+        ));
+        assert!(matches!(
+            handler.rx().recv_timeout(recv_timeout),
+            Ok(ProxyRpc::Cancel(0))
+        ));
+        assert!(matches!(
+            handler.rx().recv_timeout(recv_timeout),
+            Ok(ProxyRpc::Request(1, _))
+        ));
+
+        // Cancelled by the id it was made with, which is what the caller has
+        handler.cancel(id);
+        assert!(matches!(
+            handler.rx().recv_timeout(recv_timeout),
+            Ok(ProxyRpc::Cancel(1))
+        ));
+        let err = rx.recv_timeout(recv_timeout).unwrap().unwrap_err();
+        assert_eq!(err.code, REQUEST_CANCELLED);
+    }
+
+    #[test]
+    fn test_compose() {
+        let text = Rope::from("hello");
+        let first = Delta::simple_edit(Interval::new(5, 5), Rope::from(" world"), 5);
+        let second = Delta::simple_edit(Interval::new(0, 1), Rope::from("H"), 11);
+        let composed = compose(&first, &second);
+        assert_eq!(String::from(composed.apply(&text)), "Hello world");
+
+        // Typing stays a simple insert
+        let first = Delta::simple_edit(Interval::new(5, 5), Rope::from("!"), 5);
+        let second = Delta::simple_edit(Interval::new(6, 6), Rope::from("?"), 6);
+        let composed = compose(&first, &second);
+        assert_eq!(
+            composed.as_simple_insert().map(String::from).as_deref(),
+            Some("!?")
+        );
+        assert_eq!(String::from(composed.apply(&text)), "hello!?");
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let update = |path: &str, rev: u64| {
+            ProxyRpc::Notification(ProxyNotification::Update {
+                path: PathBuf::from(path),
+                delta: Delta::simple_edit(
+                    Interval::new(rev as usize, rev as usize),
+                    Rope::from("a"),
+                    rev as usize,
+                ),
+                base_rev: rev - 1,
+                rev,
+            })
+        };
+        let completion = |request_id: usize| {
+            ProxyRpc::Notification(ProxyNotification::Completion {
+                request_id,
+                path: PathBuf::from("a.rs"),
+                input: String::new(),
+                position: Position::new(0, 0),
+            })
+        };
+        let batch = vec![
+            update("a.rs", 1),
+            completion(1),
+            update("a.rs", 2),
+            completion(2),
+            update("b.rs", 1),
+        ];
+        let coalesced = coalesce(batch);
+        assert_eq!(coalesced.len(), 3);
+        match &coalesced[0] {
+            ProxyRpc::Notification(ProxyNotification::Update {
+                delta, rev, ..
+            }) => {
+                assert_eq!(*rev, 2);
+                assert_eq!(String::from(delta.apply(&Rope::from("x"))), "xaa");
+            }
+            _ => panic!("expected an update"),
+        }
+        assert!(matches!(
+            coalesced[1],
+            ProxyRpc::Notification(ProxyNotification::Completion {
+                request_id: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            coalesced[2],
+            ProxyRpc::Notification(ProxyNotification::Update { rev: 1, .. })
+        ));
+
+        // Updates across a revision gap are kept apart, so that the buffer
+        // can refuse the one that doesn't follow its revision
+        let gap = ProxyRpc::Notification(ProxyNotification::Update {
+            path: PathBuf::from("a.rs"),
+            delta: Delta::simple_edit(Interval::new(3, 3), Rope::from("a"), 3),
+            base_rev: 3,
+            rev: 4,
+        });
+        let coalesced = coalesce(vec![update("a.rs", 1), update("a.rs", 2), gap]);
+        assert_eq!(coalesced.len(), 2);
+        assert!(matches!(
+            coalesced[0],
+            ProxyRpc::Notification(ProxyNotification::Update {
+                base_rev: 0,
+                rev: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            coalesced[1],
+            ProxyRpc::Notification(ProxyNotification::Update {
+                base_rev: 3,
+                rev: 4,
+                ..
+            })
+        ));
+    }
+}
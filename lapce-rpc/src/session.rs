@@ -0,0 +1,326 @@
+use std::{collections::VecDeque, thread};
+
+use crossbeam_channel::{select, Receiver, SendError, Sender, TryRecvError};
+
+/// Identifies the state a proxy keeps for lapce, so that lapce can pick it up
+/// again after it lost the connection to the proxy.
+pub type SessionId = String;
+
+pub fn new_session_id() -> SessionId {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// How many messages a side of a session receives before it acknowledges
+/// them with an `RpcMessage::Ack`.
+pub const ACK_INTERVAL: u64 = 64;
+
+/// How many messages a [`Relay`] sends before it waits for them to be
+/// acknowledged.
+const MAX_UNACKED: usize = 4096;
+
+/// The messages sent in a session that the other side hasn't acknowledged
+/// yet. They are sent again after a reconnect, starting after the last one
+/// the other side received.
+pub struct Unacked<T> {
+    /// The number of messages of the session acknowledged so far
+    acked: u64,
+    msgs: VecDeque<T>,
+}
+
+impl<T> Default for Unacked<T> {
+    fn default() -> Self {
+        Self {
+            acked: 0,
+            msgs: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> Unacked<T> {
+    pub fn push(&mut self, msg: T) {
+        self.msgs.push_back(msg);
+    }
+
+    /// The other side received the first `received` messages of the
+    /// session.
+    pub fn ack(&mut self, received: u64) {
+        while self.acked < received && self.msgs.pop_front().is_some() {
+            self.acked += 1;
+        }
+    }
+
+    /// The messages that weren't acknowledged, in the order they were sent.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.msgs.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.msgs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.msgs.is_empty()
+    }
+}
+
+enum RelayControl<T> {
+    Attach(Sender<T>, u64),
+    Ack(u64),
+    Detach,
+    Close,
+}
+
+/// Forwards messages to whichever connection is currently attached to a
+/// session. While there is none, the messages are held back until one is
+/// attached again.
+pub struct Relay<T> {
+    control: Sender<RelayControl<T>>,
+}
+
+impl<T: 'static + Clone + Send> Relay<T> {
+    /// Start forwarding the messages received on `rx`. With `resend`, the
+    /// messages are kept until they are acknowledged, and the ones that
+    /// weren't are sent again to the next connection.
+    pub fn spawn(rx: Receiver<T>, resend: bool) -> Self {
+        let (control, control_rx) = crossbeam_channel::unbounded();
+        thread::spawn(move || {
+            let mut state = RelayState {
+                current: None,
+                resend,
+                unacked: Unacked::default(),
+                unsent: None,
+            };
+            loop {
+                if !state.drain(&control_rx) {
+                    return;
+                }
+                if !state.accepting() {
+                    match control_rx.recv() {
+                        Ok(control) => {
+                            if !state.control(control) {
+                                return;
+                            }
+                        }
+                        Err(_) => return,
+                    }
+                    continue;
+                }
+                select! {
+                    recv(rx) -> msg => {
+                        let msg = match msg {
+                            Ok(msg) => msg,
+                            Err(_) => return,
+                        };
+                        // A connection detached before the message arrived
+                        // doesn't get it
+                        if !state.drain(&control_rx) {
+                            return;
+                        }
+                        state.forward(msg);
+                    }
+                    recv(control_rx) -> control => match control {
+                        Ok(control) => {
+                            if !state.control(control) {
+                                return;
+                            }
+                        }
+                        Err(_) => return,
+                    },
+                }
+            }
+        });
+        Self { control }
+    }
+
+    /// Send the messages to `tx` from now on. `received` is how many
+    /// messages of the session the other side already got, so that the
+    /// unacknowledged ones after them are sent again.
+    pub fn attach(&self, tx: Sender<T>, received: u64) {
+        let _ = self.control.send(RelayControl::Attach(tx, received));
+    }
+
+    /// The other side received the first `received` messages of the
+    /// session.
+    pub fn ack(&self, received: u64) {
+        let _ = self.control.send(RelayControl::Ack(received));
+    }
+
+    pub fn detach(&self) {
+        let _ = self.control.send(RelayControl::Detach);
+    }
+
+    /// Stop forwarding, dropping the messages that are held back. Anyone
+    /// still sending to the relay gets an error.
+    pub fn close(&self) {
+        let _ = self.control.send(RelayControl::Close);
+    }
+}
+
+struct RelayState<T> {
+    current: Option<Sender<T>>,
+    resend: bool,
+    unacked: Unacked<T>,
+    /// The message the connection went away before, when the messages
+    /// aren't kept until they are acknowledged
+    unsent: Option<T>,
+}
+
+impl<T: Clone> RelayState<T> {
+    /// Whether a new message can be taken. Otherwise whoever sends it has to
+    /// wait, rather than the messages piling up in the relay.
+    fn accepting(&self) -> bool {
+        self.current.is_some() && self.unacked.len() < MAX_UNACKED
+    }
+
+    /// Handle the control messages that are waiting, returning `false` once
+    /// the relay is closed.
+    fn drain(&mut self, control_rx: &Receiver<RelayControl<T>>) -> bool {
+        loop {
+            match control_rx.try_recv() {
+                Ok(control) => {
+                    if !self.control(control) {
+                        return false;
+                    }
+                }
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+    }
+
+    fn control(&mut self, control: RelayControl<T>) -> bool {
+        match control {
+            RelayControl::Attach(tx, received) => {
+                self.unacked.ack(received);
+                for msg in self.unacked.iter() {
+                    if tx.send(msg.clone()).is_err() {
+                        return true;
+                    }
+                }
+                if let Some(msg) = self.unsent.take() {
+                    if let Err(SendError(msg)) = tx.send(msg) {
+                        self.unsent = Some(msg);
+                        return true;
+                    }
+                }
+                self.current = Some(tx);
+            }
+            RelayControl::Ack(received) => self.unacked.ack(received),
+            RelayControl::Detach => self.current = None,
+            RelayControl::Close => return false,
+        }
+        true
+    }
+
+    fn forward(&mut self, msg: T) {
+        if self.resend {
+            self.unacked.push(msg.clone());
+        }
+        let unsent = match self.current.as_ref() {
+            Some(tx) => match tx.send(msg) {
+                Ok(()) => return,
+                Err(SendError(msg)) => msg,
+            },
+            None => msg,
+        };
+        // The connection is gone, so keep the message for the next one
+        self.current = None;
+        if !self.resend {
+            self.unsent = Some(unsent);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_relay() {
+        let timeout = Duration::from_secs(1);
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let relay = Relay::spawn(rx, false);
+
+        let (first_tx, first_rx) = crossbeam_channel::unbounded();
+        relay.attach(first_tx, 0);
+        tx.send(1).unwrap();
+        assert_eq!(first_rx.recv_timeout(timeout), Ok(1));
+
+        // Held back while there is no connection
+        relay.detach();
+        tx.send(2).unwrap();
+        tx.send(3).unwrap();
+
+        let (second_tx, second_rx) = crossbeam_channel::unbounded();
+        relay.attach(second_tx, 0);
+        assert_eq!(second_rx.recv_timeout(timeout), Ok(2));
+        assert_eq!(second_rx.recv_timeout(timeout), Ok(3));
+        assert_eq!(first_rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        // A connection that went away is detached by itself
+        drop(second_rx);
+        tx.send(4).unwrap();
+        let (third_tx, third_rx) = crossbeam_channel::unbounded();
+        relay.attach(third_tx, 0);
+        assert_eq!(third_rx.recv_timeout(timeout), Ok(4));
+    }
+
+    #[test]
+    fn test_relay_resend() {
+        let timeout = Duration::from_secs(1);
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let relay = Relay::spawn(rx, true);
+
+        let (first_tx, first_rx) = crossbeam_channel::unbounded();
+        relay.attach(first_tx, 0);
+        for i in 1..=4 {
+            tx.send(i).unwrap();
+        }
+        for i in 1..=4 {
+            assert_eq!(first_rx.recv_timeout(timeout), Ok(i));
+        }
+        relay.ack(1);
+
+        // The other side only got the first two before the connection was
+        // lost, so the ones after them are sent again
+        let (second_tx, second_rx) = crossbeam_channel::unbounded();
+        relay.attach(second_tx, 2);
+        tx.send(5).unwrap();
+        for i in 3..=5 {
+            assert_eq!(second_rx.recv_timeout(timeout), Ok(i));
+        }
+    }
+
+    #[test]
+    fn test_relay_close() {
+        let (tx, rx) = crossbeam_channel::bounded(0);
+        let relay = Relay::spawn(rx, false);
+        relay.close();
+        // Blocks until the relay is gone, as nothing takes the message
+        assert!(tx.send(1).is_err());
+    }
+
+    #[test]
+    fn test_unacked() {
+        let mut unacked = Unacked::default();
+        for i in 1..=3 {
+            unacked.push(i);
+        }
+        unacked.ack(2);
+        assert_eq!(unacked.iter().copied().collect::<Vec<_>>(), vec![3]);
+        // An older ack changes nothing
+        unacked.ack(1);
+        assert_eq!(unacked.len(), 1);
+        unacked.ack(5);
+        assert!(unacked.is_empty());
+    }
+
+    #[test]
+    fn test_new_session_id() {
+        let id = new_session_id();
+        assert_eq!(id.len(), 36);
+        assert_ne!(id, new_session_id());
+    }
+}
@@ -5,12 +5,227 @@
 
 use anyhow::Result;
 use crossbeam_channel::{Receiver, Sender};
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::{RpcError, RpcMessage, RpcObject};
+use crate::{
+    mux::{MuxFrame, WorkspaceId},
+    RpcError, RpcMessage, RpcObject, METHOD_NOT_FOUND,
+};
+
+const CANCEL_METHOD: &str = "$/cancelRequest";
+const ACK_METHOD: &str = "$/ack";
+/// The field a JSON message on a multiplexed transport carries its workspace
+/// in. A message with nothing but this field closes the workspace.
+const WORKSPACE_FIELD: &str = "workspace";
+/// Set in the length prefix of a MessagePack frame that is compressed.
+const COMPRESSED_FLAG: u32 = 1 << 31;
+/// Frames smaller than this are sent as they are, compressing them isn't
+/// worth the time.
+pub const COMPRESSION_THRESHOLD: usize = 8 * 1024;
+/// The largest frame that is read, so that a bad length prefix can't make the
+/// reader allocate gigabytes. Larger frames are skipped.
+pub const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// The encoding of the messages sent over a transport.
+///
+/// Both sides start out speaking JSON, and only switch to another codec
+/// once they agreed on it in the [`handshake`](crate::handshake).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RpcCodec {
+    /// One JSON object per line.
+    #[default]
+    Json,
+    /// MessagePack frames, each prefixed with its length as a big endian u32.
+    MessagePack,
+    /// Like [`Self::MessagePack`], but the frames larger than
+    /// [`COMPRESSION_THRESHOLD`] are compressed with zstd. The top bit of the
+    /// length of a frame tells whether it is compressed.
+    ZstdMessagePack,
+}
+
+impl RpcCodec {
+    pub fn write_msg<W, Req, Notif, Resp>(
+        &self,
+        out: &mut W,
+        msg: RpcMessage<Req, Notif, Resp>,
+    ) -> io::Result<()>
+    where
+        W: Write,
+        Req: Serialize,
+        Notif: Serialize,
+        Resp: Serialize,
+    {
+        match self {
+            RpcCodec::Json => write_msg(out, msg),
+            RpcCodec::MessagePack => write_frame(out, &msg, false),
+            RpcCodec::ZstdMessagePack => write_frame(out, &msg, true),
+        }
+    }
+
+    pub fn read_msg<R, Req, Notif, Resp>(
+        &self,
+        inp: &mut R,
+    ) -> io::Result<RpcMessage<Req, Notif, Resp>>
+    where
+        R: BufRead,
+        Req: DeserializeOwned,
+        Notif: DeserializeOwned,
+        Resp: DeserializeOwned,
+    {
+        match self {
+            RpcCodec::Json => read_msg(inp),
+            RpcCodec::MessagePack | RpcCodec::ZstdMessagePack => read_frame(inp),
+        }
+    }
+
+    /// Write a message for a workspace on a multiplexed transport, see
+    /// [`crate::mux`]. `None` tells the peer the workspace is closed.
+    pub fn write_mux_msg<W, Req, Notif, Resp>(
+        &self,
+        out: &mut W,
+        workspace: WorkspaceId,
+        msg: Option<RpcMessage<Req, Notif, Resp>>,
+    ) -> io::Result<()>
+    where
+        W: Write,
+        Req: Serialize,
+        Notif: Serialize,
+        Resp: Serialize,
+    {
+        match self {
+            RpcCodec::Json => {
+                let mut value = match msg {
+                    Some(msg) => msg_to_value(msg)?,
+                    None => json!({}),
+                };
+                value
+                    .as_object_mut()
+                    .ok_or(io::ErrorKind::NotFound)?
+                    .insert(WORKSPACE_FIELD.into(), workspace.into());
+                write_value(out, &value)
+            }
+            RpcCodec::MessagePack => write_frame(out, &(workspace, msg), false),
+            RpcCodec::ZstdMessagePack => write_frame(out, &(workspace, msg), true),
+        }
+    }
+
+    pub fn read_mux_msg<R, Req, Notif, Resp>(
+        &self,
+        inp: &mut R,
+    ) -> io::Result<MuxFrame<RpcMessage<Req, Notif, Resp>>>
+    where
+        R: BufRead,
+        Req: DeserializeOwned,
+        Notif: DeserializeOwned,
+        Resp: DeserializeOwned,
+    {
+        match self {
+            RpcCodec::Json => {
+                let mut value = read_value(inp)?;
+                let object = value.as_object_mut().ok_or(io::ErrorKind::NotFound)?;
+                let workspace = object
+                    .remove(WORKSPACE_FIELD)
+                    .and_then(|workspace| workspace.as_u64())
+                    .ok_or(io::ErrorKind::NotFound)?;
+                if object.is_empty() {
+                    return Ok((workspace, None));
+                }
+                Ok((workspace, Some(msg_from_value(value)?)))
+            }
+            RpcCodec::MessagePack | RpcCodec::ZstdMessagePack => read_frame(inp),
+        }
+    }
+}
+
+/// Write a MessagePack frame, prefixed with its length. With `compress`, a
+/// large frame is compressed if that makes it smaller.
+fn write_frame<W: Write, T: Serialize>(
+    out: &mut W,
+    msg: &T,
+    compress: bool,
+) -> io::Result<()> {
+    // Structs have to be encoded as maps rather than arrays, otherwise
+    // optional fields skipped during serialization shift every field that
+    // comes after them.
+    let mut buf = rmp_serde::to_vec_named(msg)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut flag = 0;
+    if compress && buf.len() >= COMPRESSION_THRESHOLD {
+        let compressed = zstd::bulk::compress(&buf, 0)?;
+        if compressed.len() < buf.len() {
+            buf = compressed;
+            flag = COMPRESSED_FLAG;
+        }
+    }
+    let len = u32::try_from(buf.len())
+        .ok()
+        .filter(|len| *len as usize <= MAX_FRAME_LEN)
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "rpc message too large")
+        })?;
+    out.write_all(&(len | flag).to_be_bytes())?;
+    out.write_all(&buf)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn read_frame<R: BufRead, T: DeserializeOwned>(inp: &mut R) -> io::Result<T> {
+    let mut len = [0; 4];
+    inp.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    let frame_len = (len & !COMPRESSED_FLAG) as usize;
+    if frame_len > MAX_FRAME_LEN {
+        // Read past it, so that the frames after it can still be read
+        io::copy(&mut io::Read::take(inp, frame_len as u64), &mut io::sink())?;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "rpc message too large",
+        ));
+    }
+    let mut buf = vec![0; frame_len];
+    inp.read_exact(&mut buf)?;
+    if len & COMPRESSED_FLAG != 0 {
+        // Bounded like the frames themselves, so that a small frame can't
+        // decompress to anything larger
+        buf = zstd::bulk::decompress(&buf, MAX_FRAME_LEN)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    }
+    rmp_serde::from_slice(&buf).or_else(|e| {
+        let invalid_data = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        // A request that can't be decoded still has an id to answer
+        let mut value: Value =
+            rmp_serde::from_slice(&buf).map_err(|_| invalid_data(e.to_string()))?;
+        invalid_request(&mut value, &e.to_string())
+            .ok_or_else(|| invalid_data(e.to_string()))?;
+        serde_json::from_value(value).map_err(|e| invalid_data(e.to_string()))
+    })
+}
+
+/// Replace the request in a decoded MessagePack frame, on its own or in a mux
+/// frame, with the [`RpcMessage::Invalid`] it's answered with.
+fn invalid_request(value: &mut Value, error: &str) -> Option<()> {
+    let msg = match value {
+        Value::Array(frame) => frame.last_mut()?,
+        value => value,
+    };
+    let request = msg.get("Request")?;
+    let id = request.get(0)?.as_u64()?;
+    let method = request.get(1)?.get("method")?.as_str()?;
+    *msg = json!({ "Invalid": [id, unknown_method(method, error)] });
+    Some(())
+}
+
+fn unknown_method(method: &str, error: &str) -> RpcError {
+    RpcError {
+        code: METHOD_NOT_FOUND,
+        message: format!("can't handle {method}: {error}"),
+    }
+}
 
 pub fn stdio_transport<W, R, Req1, Notif1, Resp1, Req2, Notif2, Resp2>(
+    codec: RpcCodec,
     mut writer: W,
     writer_receiver: Receiver<RpcMessage<Req2, Notif2, Resp2>>,
     mut reader: R,
@@ -27,15 +242,28 @@ pub fn stdio_transport<W, R, Req1, Notif1, Resp1, Req2, Notif2, Resp2>(
 {
     thread::spawn(move || {
         for value in writer_receiver {
-            if write_msg(&mut writer, value).is_err() {
+            if codec.write_msg(&mut writer, value).is_err() {
                 return;
             };
         }
     });
     thread::spawn(move || -> Result<()> {
         loop {
-            let msg = read_msg(&mut reader)?;
-            reader_sender.send(msg)?;
+            match codec.read_msg(&mut reader) {
+                Ok(msg) => reader_sender.send(msg)?,
+                // A message this version doesn't understand, e.g. from a newer
+                // peer, is skipped rather than ending the transport. Requests
+                // are read as `RpcMessage::Invalid` instead, to be answered
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::InvalidData | io::ErrorKind::NotFound
+                    ) =>
+                {
+                    log::warn!("skipping unreadable rpc message: {e}");
+                }
+                Err(e) => return Err(e.into()),
+            }
         }
     });
 }
@@ -49,6 +277,24 @@ pub fn write_msg<W, Req, Notif, Resp>(
     Req: Serialize,
     Notif: Serialize,
     Resp: Serialize,
+{
+    write_value(out, &msg_to_value(msg)?)
+}
+
+fn write_value<W: Write>(out: &mut W, value: &Value) -> io::Result<()> {
+    let msg = format!("{}\n", serde_json::to_string(value)?);
+    out.write_all(msg.as_bytes())?;
+    out.flush()?;
+    Ok(())
+}
+
+fn msg_to_value<Req, Notif, Resp>(
+    msg: RpcMessage<Req, Notif, Resp>,
+) -> io::Result<Value>
+where
+    Req: Serialize,
+    Notif: Serialize,
+    Resp: Serialize,
 {
     let value = match msg {
         RpcMessage::Request(id, req) => {
@@ -58,6 +304,12 @@ pub fn write_msg<W, Req, Notif, Resp>(
                 .insert("id".into(), id.into());
             msg
         }
+        RpcMessage::Partial(id, resp) => {
+            json!({
+                "id": id,
+                "partial": resp,
+            })
+        }
         RpcMessage::Response(id, resp) => {
             json!({
                 "id": id,
@@ -65,17 +317,27 @@ pub fn write_msg<W, Req, Notif, Resp>(
             })
         }
         RpcMessage::Notification(n) => serde_json::to_value(n)?,
-        RpcMessage::Error(id, err) => {
+        // An invalid request is only ever answered, with its error
+        RpcMessage::Error(id, err) | RpcMessage::Invalid(id, err) => {
             json!({
                 "id": id,
                 "error": err,
             })
         }
+        RpcMessage::Cancel(id) => {
+            json!({
+                "method": CANCEL_METHOD,
+                "params": { "id": id },
+            })
+        }
+        RpcMessage::Ack(received) => {
+            json!({
+                "method": ACK_METHOD,
+                "params": { "received": received },
+            })
+        }
     };
-    let msg = format!("{}\n", serde_json::to_string(&value)?);
-    out.write_all(msg.as_bytes())?;
-    out.flush()?;
-    Ok(())
+    Ok(value)
 }
 
 pub fn read_msg<R, Req, Notif, Resp>(
@@ -87,11 +349,50 @@ pub fn read_msg<R, Req, Notif, Resp>(
     Notif: DeserializeOwned,
     Resp: DeserializeOwned,
 {
+    msg_from_value(read_value(inp)?)
+}
+
+fn read_value<R: BufRead>(inp: &mut R) -> io::Result<Value> {
     let mut buf = String::new();
     let _s = inp.read_line(&mut buf)?;
-    let value: Value = serde_json::from_str(&buf)?;
+    Ok(serde_json::from_str(&buf)?)
+}
+
+fn msg_from_value<Req, Notif, Resp>(
+    value: Value,
+) -> io::Result<RpcMessage<Req, Notif, Resp>>
+where
+    Req: DeserializeOwned,
+    Notif: DeserializeOwned,
+    Resp: DeserializeOwned,
+{
     let object = RpcObject(value);
+    if object.get_method() == Some(CANCEL_METHOD) {
+        let id = object
+            .0
+            .get("params")
+            .and_then(|params| params.get("id"))
+            .and_then(Value::as_u64)
+            .ok_or(io::ErrorKind::NotFound)?;
+        return Ok(RpcMessage::Cancel(id));
+    }
+    if object.get_method() == Some(ACK_METHOD) {
+        let received = object
+            .0
+            .get("params")
+            .and_then(|params| params.get("received"))
+            .and_then(Value::as_u64)
+            .ok_or(io::ErrorKind::NotFound)?;
+        return Ok(RpcMessage::Ack(received));
+    }
     let is_response = object.is_response();
+    if is_response {
+        if let Some(partial) = object.0.get("partial") {
+            let id = object.get_id().ok_or(io::ErrorKind::NotFound)?;
+            let resp: Resp = serde_json::from_value(partial.clone())?;
+            return Ok(RpcMessage::Partial(id, resp));
+        }
+    }
     let msg = if is_response {
         let id = object.get_id().ok_or(io::ErrorKind::NotFound)?;
         let resp = object
@@ -110,8 +411,14 @@ pub fn read_msg<R, Req, Notif, Resp>(
     } else {
         match object.get_id() {
             Some(id) => {
-                let req: Req = serde_json::from_value(object.0)?;
-                RpcMessage::Request(id, req)
+                let method = object.get_method().unwrap_or_default().to_string();
+                match serde_json::from_value::<Req>(object.0) {
+                    Ok(req) => RpcMessage::Request(id, req),
+                    Err(e) => RpcMessage::Invalid(
+                        id,
+                        unknown_method(&method, &e.to_string()),
+                    ),
+                }
             }
             None => {
                 let notif: Notif = serde_json::from_value(object.0)?;
@@ -121,3 +428,118 @@ pub fn read_msg<R, Req, Notif, Resp>(
     };
     Ok(msg)
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use super::*;
+
+    type Msg = RpcMessage<(), (), String>;
+
+    fn write(codec: RpcCodec, msg: Msg) -> Vec<u8> {
+        let mut buf = Vec::new();
+        codec.write_msg(&mut buf, msg).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_compressed_frames() {
+        let large = "a".repeat(COMPRESSION_THRESHOLD * 2);
+        let small = "a".repeat(COMPRESSION_THRESHOLD / 2);
+        for text in [large, small] {
+            let plain =
+                write(RpcCodec::MessagePack, RpcMessage::Response(1, text.clone()));
+            let buf = write(
+                RpcCodec::ZstdMessagePack,
+                RpcMessage::Response(1, text.clone()),
+            );
+            let compressed = buf[0] & 0x80 != 0;
+            assert_eq!(compressed, text.len() > COMPRESSION_THRESHOLD);
+            assert_eq!(compressed, buf.len() < plain.len());
+            // Either codec reads both kinds of frames
+            for codec in [RpcCodec::MessagePack, RpcCodec::ZstdMessagePack] {
+                let msg: Msg = codec.read_msg(&mut io::Cursor::new(&buf)).unwrap();
+                assert!(
+                    matches!(msg, RpcMessage::Response(1, resp) if resp == text)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_too_large() {
+        // Only the header is real, the frame itself is read from zeros
+        let header = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+        let next = write(RpcCodec::MessagePack, RpcMessage::Response(1, "a".into()));
+        let mut reader = io::BufReader::new(
+            header[..]
+                .chain(io::repeat(0).take(MAX_FRAME_LEN as u64 + 1))
+                .chain(&next[..]),
+        );
+        let err = RpcCodec::MessagePack
+            .read_msg::<_, (), (), String>(&mut reader)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // The frame after it is still read
+        let msg: Msg = RpcCodec::MessagePack.read_msg(&mut reader).unwrap();
+        assert!(matches!(msg, RpcMessage::Response(1, resp) if resp == "a"));
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        #[derive(Serialize, Deserialize)]
+        #[serde(rename_all = "snake_case", tag = "method", content = "params")]
+        enum NewRequest {
+            NewMethod { a: u32 },
+        }
+        #[derive(Debug, Deserialize)]
+        #[serde(rename_all = "snake_case", tag = "method", content = "params")]
+        enum OldRequest {
+            OldMethod {},
+        }
+
+        for codec in [RpcCodec::Json, RpcCodec::MessagePack] {
+            let mut buf = Vec::new();
+            codec
+                .write_msg::<_, _, (), ()>(
+                    &mut buf,
+                    RpcMessage::Request(3, NewRequest::NewMethod { a: 1 }),
+                )
+                .unwrap();
+            let msg: RpcMessage<OldRequest, (), ()> =
+                codec.read_msg(&mut io::Cursor::new(&buf)).unwrap();
+            assert!(matches!(
+                msg,
+                RpcMessage::Invalid(
+                    3,
+                    RpcError {
+                        code: METHOD_NOT_FOUND,
+                        ..
+                    }
+                )
+            ));
+
+            let mut buf = Vec::new();
+            codec
+                .write_mux_msg::<_, _, (), ()>(
+                    &mut buf,
+                    7,
+                    Some(RpcMessage::Request(3, NewRequest::NewMethod { a: 1 })),
+                )
+                .unwrap();
+            let msg: MuxFrame<RpcMessage<OldRequest, (), ()>> =
+                codec.read_mux_msg(&mut io::Cursor::new(&buf)).unwrap();
+            assert!(matches!(msg, (7, Some(RpcMessage::Invalid(3, _)))));
+        }
+    }
+
+    #[test]
+    fn test_ack() {
+        for codec in [RpcCodec::Json, RpcCodec::MessagePack] {
+            let buf = write(codec, RpcMessage::Ack(64));
+            let msg: Msg = codec.read_msg(&mut io::Cursor::new(&buf)).unwrap();
+            assert!(matches!(msg, RpcMessage::Ack(64)));
+        }
+    }
+}
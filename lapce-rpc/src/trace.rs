@@ -0,0 +1,258 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::{const_mutex, Mutex};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{metrics, RequestId};
+
+/// How many messages are kept, the oldest ones are dropped first.
+const TRACE_CAPACITY: usize = 10_000;
+
+/// The recorder of the whole process, which the functions of this module
+/// use.
+static RECORDER: Recorder = Recorder::new();
+
+static NEXT_SOURCE: AtomicU64 = AtomicU64::new(0);
+
+/// Which rpc a traced message went through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceChannel {
+    /// lapce talking to the proxy
+    Proxy,
+    /// The proxy talking to lapce
+    Core,
+    /// The proxy talking to a plugin or language server
+    Plugin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceKind {
+    Request,
+    Partial,
+    Response,
+    Error,
+    Notification,
+    Cancel,
+}
+
+/// One message in the trace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEvent {
+    /// Milliseconds since the unix epoch
+    pub time: u128,
+    pub channel: TraceChannel,
+    /// Which of the handlers of the channel the message went through, as
+    /// each of them numbers its requests on its own, see [`next_source`]
+    #[serde(default)]
+    pub source: u64,
+    pub kind: TraceKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<RequestId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    /// The size of the message encoded as JSON, in bytes
+    pub size: usize,
+}
+
+/// Keeps the last [`TRACE_CAPACITY`] messages while it's enabled.
+pub struct Recorder {
+    enabled: AtomicBool,
+    events: Mutex<Option<VecDeque<TraceEvent>>>,
+}
+
+impl Recorder {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            events: const_mutex(None),
+        }
+    }
+
+    /// Start or stop recording the messages. Stopping drops the ones
+    /// recorded so far.
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut events = self.events.lock();
+        if enabled {
+            events.get_or_insert_with(VecDeque::new);
+        } else {
+            *events = None;
+        }
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Keep the event, if the recorder is enabled.
+    pub fn push(&self, event: TraceEvent) {
+        if let Some(events) = self.events.lock().as_mut() {
+            if events.len() == TRACE_CAPACITY {
+                events.pop_front();
+            }
+            events.push_back(event);
+        }
+    }
+
+    /// The messages recorded so far, oldest first.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events
+            .lock()
+            .as_ref()
+            .map(|events| events.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Start or stop recording the messages. Stopping drops the ones recorded so
+/// far.
+pub fn set_enabled(enabled: bool) {
+    RECORDER.set_enabled(enabled);
+}
+
+pub fn is_enabled() -> bool {
+    RECORDER.is_enabled()
+}
+
+/// Whether the messages are looked at at all, for the trace or for the
+/// [`metrics`].
+pub fn is_recording() -> bool {
+    is_enabled() || metrics::is_enabled()
+}
+
+/// Record a message, if tracing or the metrics are enabled. The method is
+/// taken from the `method` field of the payload, if it has one.
+pub fn record<T: Serialize>(
+    channel: TraceChannel,
+    source: u64,
+    kind: TraceKind,
+    id: Option<RequestId>,
+    payload: &T,
+) {
+    if !is_recording() {
+        return;
+    }
+    record_event(TraceEvent::new(channel, source, kind, id, payload));
+}
+
+/// A new number to tell a handler apart from the others on its channel.
+pub fn next_source() -> u64 {
+    NEXT_SOURCE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Record a message whose method and size are known already.
+pub fn record_event(event: TraceEvent) {
+    metrics::record(&event);
+    RECORDER.push(event);
+}
+
+impl TraceEvent {
+    /// The event of a message sent now. The method is taken from the
+    /// `method` field of the payload, if it has one.
+    pub fn new<T: Serialize>(
+        channel: TraceChannel,
+        source: u64,
+        kind: TraceKind,
+        id: Option<RequestId>,
+        payload: &T,
+    ) -> Self {
+        let value = serde_json::to_value(payload).unwrap_or_default();
+        let method = value
+            .get("method")
+            .and_then(Value::as_str)
+            .map(|method| method.to_string());
+        let size = serde_json::to_vec(&value).map(|v| v.len()).unwrap_or(0);
+        Self {
+            time: now(),
+            channel,
+            source,
+            kind,
+            id,
+            method,
+            size,
+        }
+    }
+}
+
+pub fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0)
+}
+
+/// The messages recorded so far, oldest first.
+pub fn events() -> Vec<TraceEvent> {
+    RECORDER.events()
+}
+
+/// Write the messages recorded so far to a file, one JSON object per line.
+pub fn write_to(path: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for event in events() {
+        serde_json::to_writer(&mut file, &event)?;
+        file.write_all(b"\n")?;
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_trace() {
+        // A recorder of its own, as the other tests record to the one of the
+        // process
+        let recorder = Recorder::new();
+        recorder.push(TraceEvent::new(
+            TraceChannel::Proxy,
+            0,
+            TraceKind::Cancel,
+            Some(0),
+            &(),
+        ));
+        assert!(recorder.events().is_empty());
+
+        recorder.set_enabled(true);
+        recorder.push(TraceEvent::new(
+            TraceChannel::Proxy,
+            0,
+            TraceKind::Request,
+            Some(1),
+            &serde_json::json!({ "method": "get_files", "params": {} }),
+        ));
+        recorder.push(TraceEvent::new(
+            TraceChannel::Core,
+            0,
+            TraceKind::Response,
+            Some(1),
+            &[1, 2],
+        ));
+        let events = recorder.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].method.as_deref(), Some("get_files"));
+        assert_eq!(events[0].id, Some(1));
+        assert_eq!(events[1].method, None);
+        assert_eq!(events[1].size, 5);
+
+        recorder.set_enabled(false);
+        assert!(recorder.events().is_empty());
+    }
+}
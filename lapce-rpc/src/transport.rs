@@ -0,0 +1,699 @@
+use std::{
+    collections::VecDeque,
+    env,
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    net::{IpAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use parking_lot::Mutex;
+use rustls::{
+    server::AllowAnyAuthenticatedClient, Certificate, ClientConfig,
+    ClientConnection, Connection, PrivateKey, RootCertStore, ServerConfig,
+    ServerConnection, ServerName,
+};
+use serde::{Deserialize, Serialize};
+use tungstenite::{Message, WebSocket};
+
+/// How long a connection gets for its handshakes, of the transport and then
+/// of lapce, before it's dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The environment variables with the files of the PEM encoded certificate,
+/// and its PKCS #8 private key, that lapce presents to the proxies that ask
+/// for one.
+pub const CLIENT_CERT_ENV: &str = "LAPCE_PROXY_CLIENT_CERT";
+pub const CLIENT_KEY_ENV: &str = "LAPCE_PROXY_CLIENT_KEY";
+
+pub type TransportWriter = Box<dyn Write + Send>;
+pub type TransportReader = Box<dyn BufRead + Send>;
+pub type Transport = (TransportWriter, TransportReader);
+
+/// When reading from a transport that isn't a socket times out, if it does.
+type Deadline = Arc<Mutex<Option<Instant>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TransportScheme {
+    Tcp,
+    Tls,
+    Ws,
+    Wss,
+}
+
+impl TransportScheme {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TransportScheme::Tcp => "tcp",
+            TransportScheme::Tls => "tls",
+            TransportScheme::Ws => "ws",
+            TransportScheme::Wss => "wss",
+        }
+    }
+
+    fn is_tls(&self) -> bool {
+        matches!(self, TransportScheme::Tls | TransportScheme::Wss)
+    }
+}
+
+/// The address of a proxy reachable over the network, written as
+/// `scheme://host:port` with one of the schemes `tcp`, `tls`, `ws` or `wss`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ProxyAddress {
+    pub scheme: TransportScheme,
+    pub host: String,
+    pub port: u16,
+}
+
+impl ProxyAddress {
+    fn host_port(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// The host name without the brackets around IPv6 addresses.
+    fn domain(&self) -> &str {
+        self.host.trim_start_matches('[').trim_end_matches(']')
+    }
+
+    /// Whether only this machine can reach the address.
+    pub fn is_loopback(&self) -> bool {
+        let domain = self.domain();
+        domain == "localhost"
+            || matches!(domain.parse::<IpAddr>(), Ok(ip) if ip.is_loopback())
+    }
+}
+
+impl FromStr for ProxyAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or_else(|| anyhow!("missing scheme in proxy address {s}"))?;
+        let scheme = match scheme {
+            "tcp" => TransportScheme::Tcp,
+            "tls" => TransportScheme::Tls,
+            "ws" => TransportScheme::Ws,
+            "wss" => TransportScheme::Wss,
+            _ => return Err(anyhow!("unsupported proxy scheme {scheme}")),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (host, port) = rest
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("missing port in proxy address {s}"))?;
+        if host.is_empty() {
+            return Err(anyhow!("missing host in proxy address {s}"));
+        }
+        let port = port
+            .parse()
+            .map_err(|_| anyhow!("invalid port in proxy address {s}"))?;
+        Ok(Self {
+            scheme,
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl Display for ProxyAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}:{}", self.scheme.as_str(), self.host, self.port)
+    }
+}
+
+/// Connect to a proxy that listens on `address`.
+pub fn connect(address: &ProxyAddress) -> Result<Transport> {
+    let stream = connect_tcp(address)?;
+    stream.set_nodelay(true)?;
+    // Bounds the handshakes of tls and websockets
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let transport: Transport = match address.scheme {
+        TransportScheme::Tcp => split_tcp(stream.try_clone()?)?,
+        TransportScheme::Tls => {
+            let (writer, reader) = connect_tls(address, stream.try_clone()?)?;
+            (Box::new(writer), Box::new(BufReader::new(reader)))
+        }
+        TransportScheme::Ws => {
+            let url = format!("ws://{}/", address.host_port());
+            let (socket, _) = tungstenite::client(url, WsStream::tcp(&stream)?)
+                .map_err(|e| anyhow!("websocket handshake failed: {e}"))?;
+            pump_websocket(socket, Deadline::default())
+        }
+        TransportScheme::Wss => {
+            let (writer, reader) = connect_tls(address, stream.try_clone()?)?;
+            let url = format!("wss://{}/", address.host_port());
+            let (socket, _) =
+                tungstenite::client(url, WsStream::new(writer, reader))
+                    .map_err(|e| anyhow!("websocket handshake failed: {e}"))?;
+            pump_websocket(socket, Deadline::default())
+        }
+    };
+    stream.set_read_timeout(None)?;
+    Ok(transport)
+}
+
+/// Connect to the first of the addresses the host resolves to that answers
+/// within [`HANDSHAKE_TIMEOUT`].
+fn connect_tcp(address: &ProxyAddress) -> Result<TcpStream> {
+    let mut last_err = None;
+    for addr in address.host_port().to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(match last_err {
+        Some(err) => err.into(),
+        None => anyhow!("{} doesn't resolve to any address", address.host),
+    })
+}
+
+fn connect_tls(
+    address: &ProxyAddress,
+    stream: TcpStream,
+) -> Result<(TlsWriter, TlsReader)> {
+    let name = ServerName::try_from(address.domain())
+        .map_err(|_| anyhow!("invalid server name {}", address.domain()))?;
+    let conn = ClientConnection::new(client_config()?, name)?;
+    split_tls(conn.into(), stream)
+}
+
+/// The tls settings of lapce, which trusts the certificates of the system,
+/// with the certificate from [`CLIENT_CERT_ENV`] if it has one.
+fn client_config() -> Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_native_certs::load_native_certs()? {
+        // The system can have certificates rustls doesn't understand
+        let _ = roots.add(&Certificate(cert.0));
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    let config = match (env::var_os(CLIENT_CERT_ENV), env::var_os(CLIENT_KEY_ENV)) {
+        (Some(cert), Some(key)) => config.with_single_cert(
+            read_certs(Path::new(&cert))?,
+            read_key(Path::new(&key))?,
+        )?,
+        _ => config.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+/// Listens for lapce to connect to a proxy over the network.
+pub struct Listener {
+    listener: TcpListener,
+    scheme: TransportScheme,
+    acceptor: Option<Arc<ServerConfig>>,
+}
+
+impl Listener {
+    /// Listen on `address`. The `tls` and `wss` schemes need the certificate
+    /// and the PKCS #8 private key of the server, both PEM encoded. With the
+    /// PEM encoded certificates of a `client_ca`, only the connections with a
+    /// certificate it signed are accepted.
+    pub fn bind(
+        address: &ProxyAddress,
+        tls: Option<(&Path, &Path)>,
+        client_ca: Option<&Path>,
+    ) -> Result<Self> {
+        let acceptor = if address.scheme.is_tls() {
+            let (cert, key) = tls.ok_or_else(|| {
+                anyhow!("a certificate and a key are needed to listen on {address}")
+            })?;
+            Some(Arc::new(server_config(cert, key, client_ca)?))
+        } else if client_ca.is_some() {
+            return Err(anyhow!(
+                "client certificates need the tls or wss scheme, not {address}"
+            ));
+        } else {
+            None
+        };
+        let listener = TcpListener::bind(address.host_port())?;
+        Ok(Self {
+            listener,
+            scheme: address.scheme,
+            acceptor,
+        })
+    }
+
+    /// Wait for the next connection. The handshake of the transport happens
+    /// in [`Incoming::establish`], so that a slow client doesn't hold up the
+    /// others.
+    pub fn accept(&self) -> Result<Incoming> {
+        let (stream, _) = self.listener.accept()?;
+        Ok(Incoming {
+            stream,
+            scheme: self.scheme,
+            acceptor: self.acceptor.clone(),
+        })
+    }
+}
+
+fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<ServerConfig> {
+    let certs = read_certs(cert)?;
+    let key = read_key(key)?;
+    let config = ServerConfig::builder().with_safe_defaults();
+    let config = match client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(client_ca)? {
+                roots.add(&cert)?;
+            }
+            config.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+        }
+        None => config.with_no_client_auth(),
+    };
+    Ok(config.with_single_cert(certs, key)?)
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate in {}", path.display()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey> {
+    let key =
+        rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                anyhow!("no PKCS #8 private key in {}", path.display())
+            })?;
+    Ok(PrivateKey(key))
+}
+
+/// Run the tls handshake on the stream, and split the connection into a
+/// writing and a reading half. The handshake is done here rather than on the
+/// first read, so that it's bound by the timeout of the stream, and a peer
+/// without a valid certificate is turned down right away.
+fn split_tls(
+    mut conn: Connection,
+    mut stream: TcpStream,
+) -> Result<(TlsWriter, TlsReader)> {
+    while conn.is_handshaking() {
+        conn.complete_io(&mut stream)?;
+    }
+    let conn = Arc::new(Mutex::new(conn));
+    let writer = TlsWriter {
+        conn: conn.clone(),
+        socket: stream.try_clone()?,
+    };
+    let reader = TlsReader {
+        conn,
+        socket: stream,
+        buf: vec![0; 8192],
+    };
+    Ok((writer, reader))
+}
+
+pub struct Incoming {
+    stream: TcpStream,
+    scheme: TransportScheme,
+    acceptor: Option<Arc<ServerConfig>>,
+}
+
+impl Incoming {
+    /// Run the handshake of the transport. Reading from the connection times
+    /// out after [`HANDSHAKE_TIMEOUT`], until the returned
+    /// [`HandshakeTimeout`] is cleared once lapce got in.
+    pub fn establish(self) -> Result<(Transport, HandshakeTimeout)> {
+        let stream = self.stream;
+        stream.set_nodelay(true)?;
+        // Also bounds the handshakes of tls and websockets
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let deadline =
+            Arc::new(Mutex::new(Some(Instant::now() + HANDSHAKE_TIMEOUT)));
+        let mut timeout = HandshakeTimeout {
+            socket: None,
+            deadline: deadline.clone(),
+        };
+        let acceptor = || {
+            self.acceptor
+                .clone()
+                .ok_or_else(|| anyhow!("no certificate to accept tls with"))
+        };
+        match self.scheme {
+            TransportScheme::Tcp => {
+                timeout.socket = Some(stream.try_clone()?);
+                Ok((split_tcp(stream)?, timeout))
+            }
+            TransportScheme::Tls => {
+                timeout.socket = Some(stream.try_clone()?);
+                let conn = ServerConnection::new(acceptor()?)?;
+                let (writer, reader) = split_tls(conn.into(), stream)?;
+                Ok((
+                    (Box::new(writer), Box::new(BufReader::new(reader))),
+                    timeout,
+                ))
+            }
+            TransportScheme::Ws => {
+                let socket = tungstenite::accept(WsStream::tcp(&stream)?)
+                    .map_err(|e| anyhow!("websocket handshake failed: {e}"))?;
+                // The reader of the transport times out from here
+                stream.set_read_timeout(None)?;
+                Ok((pump_websocket(socket, deadline), timeout))
+            }
+            TransportScheme::Wss => {
+                let conn = ServerConnection::new(acceptor()?)?;
+                let (writer, reader) = split_tls(conn.into(), stream.try_clone()?)?;
+                let socket = tungstenite::accept(WsStream::new(writer, reader))
+                    .map_err(|e| anyhow!("websocket handshake failed: {e}"))?;
+                stream.set_read_timeout(None)?;
+                Ok((pump_websocket(socket, deadline), timeout))
+            }
+        }
+    }
+}
+
+/// The timeout a connection has for its handshakes, see
+/// [`Incoming::establish`].
+pub struct HandshakeTimeout {
+    /// The socket, if the reader reads from it directly
+    socket: Option<TcpStream>,
+    deadline: Deadline,
+}
+
+impl HandshakeTimeout {
+    /// Let reading from the connection wait for as long as it takes.
+    pub fn clear(self) -> io::Result<()> {
+        if let Some(socket) = self.socket {
+            socket.set_read_timeout(None)?;
+        }
+        *self.deadline.lock() = None;
+        Ok(())
+    }
+}
+
+fn split_tcp(stream: TcpStream) -> Result<Transport> {
+    let reader = BufReader::new(stream.try_clone()?);
+    Ok((Box::new(stream), Box::new(reader)))
+}
+
+/// Writes to a tls connection, which it shares with a [`TlsReader`].
+struct TlsWriter {
+    conn: Arc<Mutex<Connection>>,
+    socket: TcpStream,
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock();
+        let n = conn.writer().write(buf)?;
+        while conn.wants_write() {
+            conn.write_tls(&mut self.socket)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = self.conn.lock();
+        conn.writer().flush()?;
+        while conn.wants_write() {
+            conn.write_tls(&mut self.socket)?;
+        }
+        self.socket.flush()
+    }
+}
+
+impl Drop for TlsWriter {
+    fn drop(&mut self) {
+        let mut conn = self.conn.lock();
+        conn.send_close_notify();
+        while conn.wants_write() {
+            if conn.write_tls(&mut self.socket).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Reads from a tls connection, which it shares with a [`TlsWriter`]. The
+/// connection is only locked once data came in, so that waiting for it doesn't
+/// hold up the writer.
+struct TlsReader {
+    conn: Arc<Mutex<Connection>>,
+    socket: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.conn.lock().reader().read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            let n = self.socket.read(&mut self.buf)?;
+            let mut conn = self.conn.lock();
+            let mut data = &self.buf[..n];
+            loop {
+                conn.read_tls(&mut data)?;
+                conn.process_new_packets()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                if data.is_empty() {
+                    break;
+                }
+            }
+            // Such as the answers to key updates
+            while conn.wants_write() {
+                conn.write_tls(&mut self.socket)?;
+            }
+        }
+    }
+}
+
+/// The stream a websocket runs on. It reads from its reader for the
+/// handshake, and from what [`pump_websocket`] received after that.
+struct WsStream {
+    reader: Option<Box<dyn Read + Send>>,
+    received: VecDeque<u8>,
+    writer: Box<dyn Write + Send>,
+}
+
+impl WsStream {
+    fn new(
+        writer: impl Write + Send + 'static,
+        reader: impl Read + Send + 'static,
+    ) -> Self {
+        Self {
+            reader: Some(Box::new(reader)),
+            received: VecDeque::new(),
+            writer: Box::new(writer),
+        }
+    }
+
+    fn tcp(stream: &TcpStream) -> io::Result<Self> {
+        Ok(Self::new(stream.try_clone()?, stream.try_clone()?))
+    }
+}
+
+impl Read for WsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.reader.as_mut() {
+            Some(reader) => reader.read(buf),
+            None if self.received.is_empty() => {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+            None => self.received.read(buf),
+        }
+    }
+}
+
+impl Write for WsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Collects what is written to it, and hands it over to the thread that
+/// writes to the connection on every flush.
+struct ChannelWriter {
+    buf: Vec<u8>,
+    tx: Sender<Vec<u8>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.tx
+                .send(std::mem::take(&mut self.buf))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the data received by the thread that reads from the connection.
+struct ChannelReader {
+    current: Cursor<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    deadline: Deadline,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.current.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            let deadline = *self.deadline.lock();
+            let data = match deadline {
+                Some(deadline) => self.rx.recv_deadline(deadline),
+                None => self.rx.recv().map_err(RecvTimeoutError::from),
+            };
+            match data {
+                Ok(data) => self.current = Cursor::new(data),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "the handshake timed out",
+                    ));
+                }
+                // The connection is closed
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+    }
+}
+
+fn channel_pair(
+    deadline: Deadline,
+) -> (Transport, Receiver<Vec<u8>>, Sender<Vec<u8>>) {
+    let (out_tx, out_rx) = crossbeam_channel::unbounded();
+    let (in_tx, in_rx) = crossbeam_channel::unbounded();
+    let writer = ChannelWriter {
+        buf: Vec::new(),
+        tx: out_tx,
+    };
+    let reader = BufReader::new(ChannelReader {
+        current: Cursor::new(Vec::new()),
+        rx: in_rx,
+        deadline,
+    });
+    ((Box::new(writer), Box::new(reader)), out_rx, in_tx)
+}
+
+/// Drive a websocket from two threads, one that sends each flush of the
+/// writer as a binary message, and one that waits for the messages from the
+/// other side. The websocket is only locked once data came in, so that
+/// waiting for it doesn't hold up the writer.
+fn pump_websocket(mut socket: WebSocket<WsStream>, deadline: Deadline) -> Transport {
+    let (transport, out_rx, in_tx) = channel_pair(deadline);
+    let mut raw = match socket.get_mut().reader.take() {
+        Some(raw) => raw,
+        None => return transport,
+    };
+    let socket = Arc::new(Mutex::new(socket));
+
+    let writer = socket.clone();
+    thread::spawn(move || -> Result<()> {
+        for data in out_rx {
+            writer.lock().write_message(Message::Binary(data))?;
+        }
+        let mut socket = writer.lock();
+        let _ = socket.close(None);
+        let _ = socket.write_pending();
+        Ok(())
+    });
+
+    thread::spawn(move || -> Result<()> {
+        let mut buf = vec![0; 8192];
+        loop {
+            // The messages that are complete, starting with the ones that came
+            // with the handshake
+            loop {
+                let data = match socket.lock().read_message() {
+                    Ok(Message::Binary(data)) => data,
+                    Ok(Message::Text(text)) => text.into_bytes(),
+                    Ok(Message::Close(_)) => return Ok(()),
+                    Ok(_) => continue,
+                    Err(tungstenite::Error::Io(e))
+                        if e.kind() == io::ErrorKind::WouldBlock =>
+                    {
+                        break;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                if in_tx.send(data).is_err() {
+                    return Ok(());
+                }
+            }
+            let n = raw.read(&mut buf)?;
+            if n == 0 {
+                return Ok(());
+            }
+            socket.lock().get_mut().received.extend(&buf[..n]);
+        }
+    });
+    transport
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_proxy_address() {
+        let address: ProxyAddress = "tcp://localhost:9000".parse().unwrap();
+        assert_eq!(address.scheme, TransportScheme::Tcp);
+        assert_eq!(address.host, "localhost");
+        assert_eq!(address.port, 9000);
+        assert_eq!(address.to_string(), "tcp://localhost:9000");
+        assert!(address.is_loopback());
+
+        let address: ProxyAddress = "wss://[::1]:443/".parse().unwrap();
+        assert_eq!(address.scheme, TransportScheme::Wss);
+        assert_eq!(address.domain(), "::1");
+        assert_eq!(address.to_string(), "wss://[::1]:443");
+        assert!(address.is_loopback());
+        let address: ProxyAddress = "tcp://0.0.0.0:9000".parse().unwrap();
+        assert!(!address.is_loopback());
+
+        assert!("localhost:9000".parse::<ProxyAddress>().is_err());
+        assert!("http://localhost:9000".parse::<ProxyAddress>().is_err());
+        assert!("tcp://localhost".parse::<ProxyAddress>().is_err());
+        assert!("tcp://:9000".parse::<ProxyAddress>().is_err());
+    }
+
+    #[test]
+    fn test_handshake_timeout() {
+        let deadline = Arc::new(Mutex::new(Some(Instant::now())));
+        let ((_, mut reader), _out_rx, in_tx) = channel_pair(deadline.clone());
+        let mut line = String::new();
+        let err = reader.read_line(&mut line).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        let timeout = HandshakeTimeout {
+            socket: None,
+            deadline,
+        };
+        timeout.clear().unwrap();
+        in_tx.send(b"hello\n".to_vec()).unwrap();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "hello\n");
+    }
+}
@@ -1,12 +1,17 @@
 #[cfg(target_os = "windows")]
 use std::os::windows::process::CommandExt;
-use std::{path::PathBuf, process::Stdio, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    process::Stdio,
+    sync::Arc,
+};
 
 use clap::Parser;
 use druid::{
     AppDelegate, AppLauncher, Command, Env, Event, LocalizedString, Point, Region,
-    Size, Target, Widget, WidgetExt, WidgetPod, WindowDesc, WindowHandle, WindowId,
-    WindowState,
+    Selector, Size, Target, Widget, WidgetExt, WidgetPod, WindowDesc, WindowHandle,
+    WindowId, WindowState,
 };
 #[cfg(target_os = "macos")]
 use druid::{Menu, MenuItem, SysMods};
@@ -21,7 +26,10 @@
         LapceWorkspaceType,
     },
     db::{TabsInfo, WindowInfo},
+    document::BufferContent,
+    proxy::LapceProxy,
 };
+use lapce_rpc::file::PathObject;
 
 use crate::{
     logging::override_log_levels,
@@ -34,12 +42,25 @@
 #[clap(version=*meta::VERSION)]
 #[derive(Debug)]
 struct Cli {
-    /// Launch new window even if Lapce is already running
-    #[clap(short, long, action)]
-    new: bool,
-    /// Don't return instantly when opened in terminal
+    /// Launch a new instance even if Lapce is already running
+    #[clap(short, long, alias = "new", action)]
+    new_instance: bool,
+    /// Open the paths in a new window of the running Lapce
+    #[clap(long, action)]
+    new_window: bool,
+    /// Open the folder in the active window of the running Lapce, replacing
+    /// its current workspace
+    #[clap(short, long, action, conflicts_with = "new-window")]
+    reuse_window: bool,
+    /// Don't return until the files are closed, or the window is when only
+    /// folders are given, e.g. for use as `GIT_EDITOR`
     #[clap(short, long, action)]
     wait: bool,
+    /// Stay in the foreground, which the process that detaches from the
+    /// terminal starts Lapce with
+    #[clap(long, hide = true, action)]
+    foreground: bool,
+    /// Files or folders to open, files can be given as `path:line:column`
     paths: Vec<PathBuf>,
 }
 
@@ -56,10 +77,31 @@ pub fn launch() {
 
     let cli = Cli::parse();
 
+    let pwd = std::env::current_dir().unwrap_or_default();
+    let paths: Vec<PathObject> = cli
+        .paths
+        .iter()
+        .map(|p| PathObject::from_arg(&pwd, p))
+        .collect();
+    // waiting on a running Lapce isn't possible, so `--wait` always
+    // gets a process of its own
+    if !cli.new_instance
+        && !cli.wait
+        && !cli.foreground
+        && LapceData::try_open_in_existing_process(
+            &paths,
+            cli.new_window,
+            cli.reuse_window,
+        )
+        .is_ok()
+    {
+        return;
+    }
+
     // small hack to unblock terminal if launched from it
-    if !cli.wait {
+    if !cli.wait && !cli.foreground {
         let mut args = std::env::args().collect::<Vec<_>>();
-        args.push("--wait".to_string());
+        args.push("--foreground".to_string());
         let mut cmd = std::process::Command::new(&args[0]);
         #[cfg(target_os = "windows")]
         cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
@@ -73,15 +115,6 @@ pub fn launch() {
         };
         return;
     }
-    let pwd = std::env::current_dir().unwrap_or_default();
-    let paths: Vec<PathBuf> = cli
-        .paths
-        .iter()
-        .map(|p| pwd.join(p).canonicalize().unwrap_or_default())
-        .collect();
-    if !cli.new && LapceData::try_open_in_existing_process(&paths).is_ok() {
-        return;
-    }
 
     #[cfg(feature = "updater")]
     lapce_data::update::cleanup();
@@ -135,7 +168,17 @@ pub fn launch() {
         .backtrace_mode(log_panics::BacktraceMode::Resolved)
         .install_panic_hook();
 
-    let mut launcher = AppLauncher::new().delegate(LapceAppDelegate::new());
+    let wait_files = if cli.wait {
+        paths
+            .iter()
+            .filter(|p| p.path.is_file())
+            .map(|p| p.path.clone())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut launcher =
+        AppLauncher::new().delegate(LapceAppDelegate::new(wait_files));
     let mut data = LapceData::load(launcher.get_external_handle(), paths, log_file);
 
     for (_window_id, window_data) in data.windows.iter_mut() {
@@ -437,12 +480,60 @@ fn load_shell_env() {
         })
 }
 
+/// Checks the files `--wait` waits on once a close command is handled
+const CHECK_WAIT_FILES: Selector = Selector::new("lapce.check-wait-files");
+
 /// The delegate handler for Top-Level Druid events (terminate, new window, etc.)
-struct LapceAppDelegate {}
+struct LapceAppDelegate {
+    /// The files `--wait` waits on, and whether they've been opened yet.
+    /// Lapce quits once they're all closed again.
+    wait_files: HashMap<PathBuf, bool>,
+}
 
 impl LapceAppDelegate {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(wait_files: Vec<PathBuf>) -> Self {
+        Self {
+            wait_files: wait_files.into_iter().map(|path| (path, false)).collect(),
+        }
+    }
+
+    /// Whether the command can close an editor, which is when the files
+    /// `--wait` waits on are checked.
+    fn closes_editors(cmd: &Command) -> bool {
+        matches!(
+            cmd.get(LAPCE_UI_COMMAND),
+            Some(
+                LapceUICommand::EditorTabRemove(..)
+                    | LapceUICommand::SplitEditorClose(_)
+                    | LapceUICommand::CloseTab
+                    | LapceUICommand::CloseTabId(_)
+            )
+        )
+    }
+
+    fn check_wait_files(&mut self, ctx: &mut druid::DelegateCtx, data: &LapceData) {
+        if self.wait_files.is_empty() {
+            return;
+        }
+
+        let open: HashSet<&PathBuf> = data
+            .windows
+            .values()
+            .flat_map(|window| window.tabs.values())
+            .flat_map(|tab| tab.main_split.editors.values())
+            .filter_map(|editor| match &editor.content {
+                BufferContent::File(path) => Some(path),
+                _ => None,
+            })
+            .collect();
+        self.wait_files.retain(|path, opened| {
+            let is_open = open.contains(path);
+            *opened |= is_open;
+            is_open || !*opened
+        });
+        if self.wait_files.is_empty() {
+            ctx.submit_command(druid::commands::QUIT_APP);
+        }
     }
 
     fn new_window(
@@ -450,6 +541,34 @@ fn new_window(
         ctx: &mut druid::DelegateCtx,
         data: &mut LapceData,
     ) {
+        Self::new_window_with_workspaces(window_id, vec![], None, ctx, data);
+    }
+
+    /// Open a new window on the workspace of the active tab of `window_id`,
+    /// the layout of which is restored from the workspace info in the db.
+    /// The new window shares the proxy of the tab, so that the documents open
+    /// in both windows stay in sync.
+    fn duplicate_window(
+        window_id: &WindowId,
+        ctx: &mut druid::DelegateCtx,
+        data: &mut LapceData,
+    ) {
+        let (workspaces, proxy) = data
+            .windows
+            .get(window_id)
+            .and_then(|win| win.tabs.get(&win.active_id))
+            .map(|tab| (vec![(*tab.workspace).clone()], Some(tab.proxy.clone())))
+            .unwrap_or_default();
+        Self::new_window_with_workspaces(window_id, workspaces, proxy, ctx, data);
+    }
+
+    fn new_window_with_workspaces(
+        window_id: &WindowId,
+        workspaces: Vec<LapceWorkspace>,
+        proxy: Option<Arc<LapceProxy>>,
+        ctx: &mut druid::DelegateCtx,
+        data: &mut LapceData,
+    ) -> WindowId {
         let (size, pos, current_panels) = data
             .windows
             .get(window_id)
@@ -476,7 +595,7 @@ fn new_window(
             maximised: false,
             tabs: TabsInfo {
                 active_tab: 0,
-                workspaces: vec![],
+                workspaces,
             },
         };
         let mut window_data = LapceWindowData::new(
@@ -489,6 +608,7 @@ fn new_window(
             ctx.get_external_handle(),
             &info,
             data.db.clone(),
+            proxy,
         );
         let root = build_window(&mut window_data);
         let window_id = window_data.window_id;
@@ -502,12 +622,13 @@ fn new_window(
             &window_data.config,
         );
         ctx.new_window(desc);
+        window_id
     }
 }
 
 impl Default for LapceAppDelegate {
     fn default() -> Self {
-        Self::new()
+        Self::new(Vec::new())
     }
 }
 
@@ -547,14 +668,17 @@ fn window_removed(
         id: WindowId,
         data: &mut LapceData,
         _env: &Env,
-        _ctx: &mut druid::DelegateCtx,
+        ctx: &mut druid::DelegateCtx,
     ) {
+        self.check_wait_files(ctx, data);
         if let Some(window) = data.windows.remove(&id) {
             for (_, tab) in window.tabs.iter() {
                 let _ = data.db.save_workspace(tab);
+                tab.proxy.detach_tab(tab.id);
             }
             data.db.save_last_window(&window);
         }
+        self.check_wait_files(ctx, data);
     }
 
     fn command(
@@ -565,7 +689,17 @@ fn command(
         data: &mut LapceData,
         _env: &Env,
     ) -> druid::Handled {
+        if Self::closes_editors(cmd) {
+            // The delegate sees the command before the editor is closed, so
+            // the files are checked once more after it's handled
+            self.check_wait_files(ctx, data);
+            ctx.submit_command(CHECK_WAIT_FILES);
+        }
         match cmd {
+            cmd if cmd.is(CHECK_WAIT_FILES) => {
+                self.check_wait_files(ctx, data);
+                return druid::Handled::Yes;
+            }
             cmd if cmd.is(LAPCE_TAB_META) => {
                 let meta = cmd.get_unchecked(LAPCE_TAB_META).take().unwrap();
 
@@ -593,6 +727,7 @@ fn command(
                     ctx.get_external_handle(),
                     &info,
                     data.db.clone(),
+                    None,
                 );
 
                 let mut tab = meta.data;
@@ -692,7 +827,40 @@ fn command(
                         window_tab_id,
                         folders,
                         files,
+                        new_window,
+                        reuse_window,
                     } => {
+                        if *new_window {
+                            let workspaces = folders
+                                .iter()
+                                .map(|folder| LapceWorkspace {
+                                    kind: LapceWorkspaceType::Local,
+                                    path: Some(folder.to_path_buf()),
+                                    last_open: 0,
+                                })
+                                .collect();
+                            let from_window_id = *data.active_window;
+                            let window_id = Self::new_window_with_workspaces(
+                                &from_window_id,
+                                workspaces,
+                                None,
+                                ctx,
+                                data,
+                            );
+                            for file in files {
+                                ctx.submit_command(Command::new(
+                                    LAPCE_UI_COMMAND,
+                                    LapceUICommand::JumpToLineColLocation(
+                                        None,
+                                        file.clone().into(),
+                                        false,
+                                    ),
+                                    Target::Window(window_id),
+                                ));
+                            }
+                            return druid::Handled::Yes;
+                        }
+
                         if let Some((window_id, tab_id)) = window_tab_id {
                             if let Some(window_data) = data.windows.get(window_id) {
                                 if let Some(tab_data) = window_data.tabs.get(tab_id)
@@ -717,8 +885,9 @@ fn command(
                                     for file in files {
                                         ctx.submit_command(Command::new(
                                             LAPCE_UI_COMMAND,
-                                            LapceUICommand::OpenFile(
-                                                file.to_path_buf(),
+                                            LapceUICommand::JumpToLineColLocation(
+                                                None,
+                                                file.clone().into(),
                                                 false,
                                             ),
                                             Target::Widget(*tab_id),
@@ -734,22 +903,31 @@ fn command(
                             LapceUICommand::ShowWindow,
                             Target::Window(*data.active_window),
                         ));
-                        for folder in folders {
+                        for (i, folder) in folders.iter().enumerate() {
                             let workspace = LapceWorkspace {
                                 kind: LapceWorkspaceType::Local,
                                 path: Some(folder.to_path_buf()),
                                 last_open: 0,
                             };
+                            let command = if *reuse_window && i == 0 {
+                                LapceUICommand::SetWorkspace(workspace)
+                            } else {
+                                LapceUICommand::NewTab(Some(workspace))
+                            };
                             ctx.submit_command(Command::new(
                                 LAPCE_UI_COMMAND,
-                                LapceUICommand::NewTab(Some(workspace)),
+                                command,
                                 Target::Window(*data.active_window),
                             ));
                         }
                         for file in files {
                             ctx.submit_command(Command::new(
                                 LAPCE_UI_COMMAND,
-                                LapceUICommand::OpenFile(file.to_path_buf(), false),
+                                LapceUICommand::JumpToLineColLocation(
+                                    None,
+                                    file.clone().into(),
+                                    false,
+                                ),
                                 Target::Window(*data.active_window),
                             ));
                         }
@@ -759,6 +937,10 @@ fn command(
                         Self::new_window(from_window_id, ctx, data);
                         return druid::Handled::Yes;
                     }
+                    LapceUICommand::DuplicateWindow(from_window_id) => {
+                        Self::duplicate_window(from_window_id, ctx, data);
+                        return druid::Handled::Yes;
+                    }
                     LapceUICommand::CloseWindow(window_id) => {
                         ctx.submit_command(Command::new(
                             druid::commands::CLOSE_WINDOW,
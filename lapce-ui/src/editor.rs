@@ -857,6 +857,7 @@ fn paint_content(
             {
                 let text = match data.palette.palette_type {
                     PaletteType::SshHost => Some("select or enter your ssh connection like [user@]host[:port]"),
+                    PaletteType::RemoteProxy => Some("select or enter the address of the proxy like tcp://host:port"),
                     _ => None,
                 };
                 if let Some(text) = text {
@@ -627,6 +627,9 @@ fn paint(
                     LapceWorkspaceType::RemoteWSL => {
                         format!("[wsl] {text}")
                     }
+                    LapceWorkspaceType::RemoteProxy(address) => {
+                        format!("[{address}] {text}")
+                    }
                 };
                 PaletteItemPaintInfo::new_text(text, self.indices.to_vec())
             }
@@ -655,6 +658,12 @@ fn paint(
                     keymap,
                 }
             }
+            PaletteItemContent::PluginCommand {
+                volt_name, command, ..
+            } => PaletteItemPaintInfo::new_text(
+                format!("{volt_name}: {}", command.title),
+                self.indices.to_vec(),
+            ),
             PaletteItemContent::ColorTheme(theme) => PaletteItemPaintInfo::new_text(
                 theme.to_string(),
                 self.indices.to_vec(),
@@ -677,6 +686,12 @@ fn paint(
                 format!("{ssh}"),
                 self.indices.to_vec(),
             ),
+            PaletteItemContent::RemoteProxy(address) => {
+                PaletteItemPaintInfo::new_text(
+                    address.to_string(),
+                    self.indices.to_vec(),
+                )
+            }
         };
 
         let line_height = data.line_height() as f64;
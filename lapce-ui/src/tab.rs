@@ -798,10 +798,15 @@ fn handle_command_event(
                         pattern,
                         case_sensitive,
                     } => {
+                        let search = Arc::make_mut(&mut data.search);
+                        if let Some(rpc_id) = search.rpc_id.take() {
+                            data.proxy.proxy_rpc.cancel(rpc_id);
+                        }
+                        search.request_id += 1;
+                        let request_id = search.request_id;
+                        search.matches = Arc::new(Default::default());
                         if pattern.is_empty() {
                             Arc::make_mut(&mut data.find).unset();
-                            Arc::make_mut(&mut data.search).matches =
-                                Arc::new(Default::default());
                         } else {
                             let find = Arc::make_mut(&mut data.find);
                             find.set_case_sensitive(*case_sensitive);
@@ -825,9 +830,26 @@ fn handle_command_event(
                             let pattern = pattern.to_string();
                             let event_sink = ctx.get_external_handle();
                             let tab_id = data.id;
-                            data.proxy.proxy_rpc.global_search(
+                            let partial_pattern = pattern.clone();
+                            let partial_event_sink = event_sink.clone();
+                            let rpc_id = data.proxy.proxy_rpc.global_search(
                                 pattern.clone(),
                                 find.case_sensitive(),
+                                move |chunk| {
+                                    if let ProxyResponse::GlobalSearchResponse {
+                                        matches,
+                                    } = chunk
+                                    {
+                                        let _ = partial_event_sink.submit_command(
+                                            LAPCE_UI_COMMAND,
+                                            LapceUICommand::GlobalSearchResult(
+                                                partial_pattern.clone(),
+                                                Arc::new(matches),
+                                            ),
+                                            Target::Widget(tab_id),
+                                        );
+                                    }
+                                },
                                 Box::new(move |result| {
                                     if let Ok(
                                         ProxyResponse::GlobalSearchResponse {
@@ -844,8 +866,16 @@ fn handle_command_event(
                                             Target::Widget(tab_id),
                                         );
                                     }
+                                    let _ = event_sink.submit_command(
+                                        LAPCE_UI_COMMAND,
+                                        LapceUICommand::GlobalSearchFinished(
+                                            request_id,
+                                        ),
+                                        Target::Widget(tab_id),
+                                    );
                                 }),
-                            )
+                            );
+                            Arc::make_mut(&mut data.search).rpc_id = Some(rpc_id);
                         }
                     }
                     LapceUICommand::UpdateSearch(pattern) => {
@@ -869,8 +899,17 @@ fn handle_command_event(
                             .get(&LocalBufferKind::Search)
                             .unwrap();
                         if &doc.buffer().text().slice_to_cow(..) == pattern {
-                            Arc::make_mut(&mut data.search).matches =
-                                matches.clone();
+                            let search = Arc::make_mut(&mut data.search);
+                            Arc::make_mut(&mut search.matches).extend(
+                                matches
+                                    .iter()
+                                    .map(|(path, m)| (path.clone(), m.clone())),
+                            );
+                        }
+                    }
+                    LapceUICommand::GlobalSearchFinished(request_id) => {
+                        if data.search.request_id == *request_id {
+                            Arc::make_mut(&mut data.search).rpc_id = None;
                         }
                     }
                     LapceUICommand::LoadBufferHead {
@@ -1159,6 +1198,18 @@ fn handle_command_event(
                             plugin.workspace_disabled.iter().collect(),
                         );
                     }
+                    LapceUICommand::PluginCommandRegistered(volt_id, command) => {
+                        let plugin = Arc::make_mut(&mut data.plugin);
+                        plugin.plugin_command_registered(volt_id, command);
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ExecutePluginCommand { volt_id, command } => {
+                        data.proxy.proxy_rpc.execute_plugin_command(
+                            volt_id.clone(),
+                            command.clone(),
+                        );
+                        ctx.set_handled();
+                    }
                     LapceUICommand::DisableVolt(volt) => {
                         let plugin = Arc::make_mut(&mut data.plugin);
                         plugin.disabled.insert(volt.id());
@@ -1691,6 +1742,27 @@ fn handle_command_event(
                         let doc = Arc::make_mut(doc);
                         doc.handle_file_changed(content.to_owned());
                     }
+                    LapceUICommand::ResyncBuffer { path } => {
+                        if let Some(doc) = data.main_split.open_docs.get(path) {
+                            data.proxy.proxy_rpc.reopen_buffer(
+                                doc.id(),
+                                path.clone(),
+                                doc.buffer().text().to_string(),
+                                doc.rev(),
+                            );
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::MirrorBufferUpdate {
+                        path,
+                        deltas,
+                        rev,
+                        content,
+                    } => {
+                        data.main_split
+                            .apply_mirrored_update(path, deltas, *rev, content);
+                        ctx.set_handled();
+                    }
                     LapceUICommand::ReloadBuffer { path, rev, content } => {
                         let doc = data.main_split.open_docs.get_mut(path).unwrap();
                         if doc.rev() + 1 == *rev {
@@ -1757,6 +1829,9 @@ fn handle_command_event(
                                     LapceWorkspaceType::RemoteWSL => {
                                         format!("{dir} [wsl]")
                                     }
+                                    LapceWorkspaceType::RemoteProxy(address) => {
+                                        format!("{dir} [{address}]")
+                                    }
                                 }
                             })
                             .unwrap_or_else(|| "Lapce".to_string());
@@ -2863,6 +2938,9 @@ fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, _env: &Env) {
                     LapceWorkspaceType::RemoteWSL => {
                         format!("{dir} [wsl]")
                     }
+                    LapceWorkspaceType::RemoteProxy(address) => {
+                        format!("{dir} [{address}]")
+                    }
                 }
             })
             .unwrap_or_else(|| "Lapce".to_string());
@@ -163,19 +163,19 @@ fn update_remote(
             LapceWorkspaceType::Local => data
                 .config
                 .get_color_unchecked(LapceTheme::LAPCE_REMOTE_LOCAL),
-            LapceWorkspaceType::RemoteSSH(_) | LapceWorkspaceType::RemoteWSL => {
-                match *data.proxy_status {
-                    ProxyStatus::Connecting => data
-                        .config
-                        .get_color_unchecked(LapceTheme::LAPCE_REMOTE_CONNECTING),
-                    ProxyStatus::Connected => data
-                        .config
-                        .get_color_unchecked(LapceTheme::LAPCE_REMOTE_CONNECTED),
-                    ProxyStatus::Disconnected => data
-                        .config
-                        .get_color_unchecked(LapceTheme::LAPCE_REMOTE_DISCONNECTED),
-                }
-            }
+            LapceWorkspaceType::RemoteSSH(_)
+            | LapceWorkspaceType::RemoteWSL
+            | LapceWorkspaceType::RemoteProxy(_) => match *data.proxy_status {
+                ProxyStatus::Connecting => data
+                    .config
+                    .get_color_unchecked(LapceTheme::LAPCE_REMOTE_CONNECTING),
+                ProxyStatus::Connected => data
+                    .config
+                    .get_color_unchecked(LapceTheme::LAPCE_REMOTE_CONNECTED),
+                ProxyStatus::Disconnected => data
+                    .config
+                    .get_color_unchecked(LapceTheme::LAPCE_REMOTE_DISCONNECTED),
+            },
         };
         self.rects.push((remote_rect, color.clone()));
         let remote_svg = data.config.ui_svg(LapceIcons::REMOTE);
@@ -196,14 +196,28 @@ fn update_remote(
         let command_rect =
             command_rect.with_size(Size::new(x - command_rect.x0, size.height));
 
-        let mut menu_items = vec![MenuKind::Item(MenuItem {
-            desc: None,
-            command: LapceCommand {
-                kind: CommandKind::Workbench(LapceWorkbenchCommand::ConnectSshHost),
-                data: None,
-            },
-            enabled: true,
-        })];
+        let mut menu_items = vec![
+            MenuKind::Item(MenuItem {
+                desc: None,
+                command: LapceCommand {
+                    kind: CommandKind::Workbench(
+                        LapceWorkbenchCommand::ConnectSshHost,
+                    ),
+                    data: None,
+                },
+                enabled: true,
+            }),
+            MenuKind::Item(MenuItem {
+                desc: None,
+                command: LapceCommand {
+                    kind: CommandKind::Workbench(
+                        LapceWorkbenchCommand::ConnectRemoteProxy,
+                    ),
+                    data: None,
+                },
+                enabled: true,
+            }),
+        ];
 
         #[cfg(target_os = "windows")]
         {
@@ -526,6 +540,7 @@ fn update_folder(
                 format!(" [SSH: {}]", ssh.host)
             }
             LapceWorkspaceType::RemoteWSL => " [WSL]".to_string(),
+            LapceWorkspaceType::RemoteProxy(address) => format!(" [{address}]"),
         };
         let text = format!("{path}{remote}");
         let text_layout = piet_text
@@ -94,6 +94,7 @@ pub fn new_tab(
             Some(current_panels),
             data.panel_orders.clone(),
             ctx.get_external_handle(),
+            None,
         );
         let tab = LapceTab::new(&mut tab_data).lens(LapceTabLens(tab_id));
         let tab_header = LapceTabHeader::new().lens(LapceTabLens(tab_id));
@@ -102,7 +103,7 @@ pub fn new_tab(
             self.tabs[data.active] = WidgetPod::new(tab.boxed());
             self.tab_headers[data.active] = WidgetPod::new(tab_header);
             if let Some(tab) = data.tabs.remove(&data.active_id) {
-                tab.proxy.stop();
+                tab.proxy.detach_tab(tab.id);
             }
             data.active_id = Arc::new(tab_id);
         } else {
@@ -143,7 +144,7 @@ pub fn close_index_tab(
         if let Some(tab) = data.tabs.remove(&id) {
             let _ = tab.db.save_workspace(&tab);
             if stop_proxy {
-                tab.proxy.stop();
+                tab.proxy.detach_tab(tab.id);
             }
             removed_tab = Some(LapceTabMeta {
                 data: tab,
@@ -348,6 +349,9 @@ fn event(
                             tab.proxy
                                 .proxy_rpc
                                 .update_plugin_configs(data.config.plugins.clone());
+                            tab.proxy
+                                .proxy_rpc
+                                .set_timeouts(tab.config.request_timeouts());
                         }
                         Arc::make_mut(&mut data.keypress)
                             .update_keymaps(&data.config);